@@ -0,0 +1,18 @@
+// Darkens the corners of the frame
+// params.values[0].x: strength, 0 to 1
+// params.values[0].y: radius where the darkening starts, 0 to 1
+
+@fragment
+fn fs_main(in: EffectVertex) -> @location(0) vec4<f32> {
+    let color = textureSample(input_texture, input_sampler, in.uv);
+    let strength = params.values[0].x;
+    let radius = params.values[0].y;
+
+    // Corrected for the aspect ratio so the vignette stays round
+    let aspect = globals.resolution.x / globals.resolution.y;
+    let offset = (in.uv - vec2<f32>(0.5)) * vec2<f32>(aspect, 1.0);
+    let distance = length(offset) / length(vec2<f32>(aspect, 1.0) * 0.5);
+    let falloff = smoothstep(radius, 1.0, distance);
+
+    return vec4<f32>(color.rgb * (1.0 - strength * falloff), color.a);
+}
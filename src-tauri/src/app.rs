@@ -1,55 +1,242 @@
-use crate::camera;
-use crate::webgpu::{CameraSettingsUniform, WgpuState};
+use crate::camera::{self, CameraSelector};
+use crate::cli::CliOptions;
+use crate::compositor::{self, CompositeLayout, Compositor};
+use crate::events::{self, AppEvent};
+use crate::hud::{self, HudInfo};
+use crate::image::FrameReadback;
+use crate::overlays::{self, OverlayFrame, TextOverlay};
+use crate::preview_server::PreviewServer;
+use crate::processing::{ProcessorChain, RgbaFrame};
+use crate::replay::{Replay, ReplayFrame};
+use crate::settings::{CameraPreferences, SettingsStore};
+use crate::stats::{self, FrameTimestamps, PipelineStatsRecorder};
+use crate::timelapse::Timelapse;
+use crate::webgpu::{self, RenderCommand, RenderTargetOutput, RenderTargets, WgpuState};
 use crate::windows_management;
+use chrono::{Local, TimeDelta};
+use nokhwa::utils::Resolution;
 use nokhwa::Buffer;
-use std::sync::atomic::{AtomicBool, Ordering};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::{Mutex, OnceLock};
 use std::{sync::Arc, time::Instant};
-use tauri::{async_runtime, Manager};
-use tracing::debug;
+use tauri::{async_runtime, AppHandle, Manager, PhysicalSize, WebviewWindowBuilder};
+use tracing::{debug, error, info, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DisplayMode {
+    /// Capture keeps running but nothing is rendered
+    Hidden,
+    /// Small overlay window on top of the main window
+    Thumbnail,
+    /// Full background of the main window, with the UI layered on top
+    Background,
+    /// Detached always-on-top window, independent of the main window
+    Floating,
+    /// Borderless fullscreen window on the monitor at `monitor` in `available_monitors()`
+    Fullscreen { monitor: usize },
+}
+
+/// Latest frame of an additional camera source, uploaded for the render loop
+struct SourceTexture {
+    sequence: u64,
+    aspect: f32,
+    bind_group: wgpu::BindGroup,
+}
+
+/// Camera frame on its way to the render loop
+pub struct CapturedFrame {
+    pub buffer: Buffer,
+    pub captured_at: Instant,
+}
+
+enum RenderEvent {
+    Frame(CapturedFrame),
+    Command(RenderCommand),
+    CameraStopped,
+}
 
 pub struct AppState {
-    pub is_background_mode: AtomicBool,
-    pub render_paused: AtomicBool,
+    pub display_mode: Mutex<DisplayMode>,
+    // Held for the duration of a mode switch
+    pub mode_switch: async_runtime::Mutex<()>,
+    // Thumbnail overlay placement and drag/resize state
+    pub overlay_layout: Mutex<windows_management::OverlayLayout>,
+    pub overlay_interactive: AtomicBool,
+    /// Wakes the worker that settles the overlay once a drag or resize ends
+    pub overlay_interactions: OnceLock<flume::Sender<()>>,
+    // Resolution of the active source, published by the capture worker
+    pub camera_resolution: Mutex<Option<Resolution>>,
+    pub pipeline_stats: Mutex<PipelineStatsRecorder>,
+    /// Runs on every decoded frame before upload
+    pub processors: Mutex<ProcessorChain>,
+    /// Cameras captured alongside the primary one and how they are laid out
+    pub compositor: Compositor,
+    /// Last seconds of captured frames, and the one the display is frozen on
+    pub replay: Replay,
+    /// Interval snapshots of the processed frames
+    pub timelapse: Timelapse,
+    /// MJPEG stream of the processed frames on localhost
+    pub preview_server: PreviewServer,
+    pub window_platform: Box<dyn windows_management::WindowPlatform>,
+    /// Started with `--headless`, frames are rendered offscreen and no window is ever opened
+    pub headless: bool,
+}
+
+impl AppState {
+    pub fn display_mode(&self) -> DisplayMode {
+        *self.display_mode.lock().unwrap()
+    }
+
+    pub fn camera_aspect(&self) -> f32 {
+        match *self.camera_resolution.lock().unwrap() {
+            Some(resolution) if resolution.height() > 0 => {
+                resolution.width() as f32 / resolution.height() as f32
+            }
+            _ => camera::DEFAULT_CAMERA_ASPECT,
+        }
+    }
 }
 
 impl Default for AppState {
     fn default() -> Self {
+        Self::with_platform(windows_management::native_platform())
+    }
+}
+
+impl AppState {
+    /// Tests hand in a platform that doesn't touch native windows
+    pub fn with_platform(window_platform: Box<dyn windows_management::WindowPlatform>) -> Self {
         Self {
-            is_background_mode: AtomicBool::new(false),
-            render_paused: AtomicBool::new(false),
+            display_mode: Mutex::new(DisplayMode::Thumbnail),
+            mode_switch: async_runtime::Mutex::new(()),
+            overlay_layout: Mutex::new(windows_management::OverlayLayout::default()),
+            overlay_interactive: AtomicBool::new(false),
+            overlay_interactions: OnceLock::new(),
+            camera_resolution: Mutex::new(None),
+            pipeline_stats: Mutex::new(PipelineStatsRecorder::default()),
+            processors: Mutex::new(ProcessorChain::with_builtins()),
+            compositor: Compositor::default(),
+            replay: Replay::default(),
+            timelapse: Timelapse::default(),
+            preview_server: PreviewServer::default(),
+            window_platform,
+            headless: false,
         }
     }
 }
 
-pub fn app_setup(app: &mut tauri::App) -> Result<(), Box<dyn std::error::Error>> {
-    let app_state = Arc::new(AppState::default());
-    app.manage(app_state);
+pub fn app_setup(
+    app: &mut tauri::App,
+    options: CliOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let settings_store = match &options.settings {
+        Some(path) => SettingsStore::load_file(path.clone()),
+        None => SettingsStore::load(&app.path().app_config_dir()?),
+    };
+    info!("Settings loaded from {}", settings_store.path().display());
+    let mut settings = settings_store.get();
+    app.manage(Arc::new(settings_store));
 
-    let main_webview_window = app.get_webview_window("main").unwrap();
-    windows_management::style_title_bar(&main_webview_window);
-    main_webview_window.show().unwrap();
+    // Command line options only apply to this run, they are not saved
+    if let Some(mode) = options.mode {
+        settings.display_mode = mode;
+    }
+    if let Some((width, height)) = options.resolution {
+        settings.camera.max_width = width;
+        settings.camera.max_height = height;
+    }
+    if let Some(frame_rate) = options.fps {
+        settings.camera.frame_rate = Some(frame_rate);
+    }
+    let camera_selector = options.camera.clone();
 
-    let main_window = app.get_window("main").unwrap();
-    let overlay_window = windows_management::create_overlay_window(app.app_handle(), &main_window);
+    let app_state = Arc::new(AppState {
+        headless: options.headless,
+        ..AppState::default()
+    });
+    *app_state.overlay_layout.lock().unwrap() = settings.overlay_layout;
+    app.manage(app_state.clone());
+    // A port taken by another program shouldn't keep the app from starting
+    if let Err(e) = app_state.preview_server.apply(settings.preview_server) {
+        warn!("Could not start the preview server: {}", e);
+    }
 
-    let wgpu_state = async_runtime::block_on(WgpuState::new(overlay_window.clone()));
-    app.manage(Arc::new(wgpu_state));
+    let (render_tx, render_rx) = flume::unbounded::<RenderCommand>();
+    let (wgpu_state, initial_targets) = if options.headless {
+        info!("Running headless, frames are rendered offscreen");
+        async_runtime::block_on(WgpuState::new_headless(
+            render_tx,
+            PhysicalSize::new(settings.camera.max_width, settings.camera.max_height),
+        ))
+    } else {
+        // The main window is created here rather than from the config, so headless runs have none
+        let main_window_config = app
+            .config()
+            .app
+            .windows
+            .iter()
+            .find(|window| window.label == "main")
+            .ok_or("No main window in the configuration")?
+            .clone();
+        let main_webview_window =
+            WebviewWindowBuilder::from_config(app.handle(), &main_window_config)?.build()?;
+        app_state
+            .window_platform
+            .style_title_bar(&main_webview_window);
+        main_webview_window.show()?;
 
-    // Camera loop
-    let (tx, rx) = flume::unbounded::<Buffer>();
-    async_runtime::spawn(async move {
-        let mut camera = camera::create_camera();
+        let main_window = app.get_window("main").unwrap();
+        let overlay_window =
+            windows_management::create_overlay_window(app.app_handle(), &main_window);
 
-        camera.open_stream().expect("Could not open stream");
+        async_runtime::block_on(WgpuState::new(overlay_window.clone(), render_tx))
+    };
+    wgpu_state.set_rendering_settings(settings.rendering)?;
+    wgpu_state
+        .hud_visible
+        .store(settings.hud_visible, Ordering::Relaxed);
+    // A watermark image that went missing shouldn't keep the app from starting
+    if let Err(e) = wgpu_state.set_watermark(settings.watermark.clone()) {
+        warn!("Could not restore the watermark: {}", e);
+    }
+    app.manage(Arc::new(wgpu_state));
 
-        for _i in 0..1000 {
-            let buffer = camera.frame().expect("Could not get frame");
-            if tx.send(buffer).is_err() {
-                break;
+    #[cfg(debug_assertions)]
+    if options.watch_shader {
+        crate::shader_reload::watch(app.app_handle().clone());
+    }
+
+    // The overlay is up already, any other saved mode is restored once the render loop runs
+    if !options.headless && settings.display_mode != DisplayMode::Thumbnail {
+        let mode_app_handle = app.app_handle().clone();
+        async_runtime::spawn(async move {
+            if let Err(e) =
+                windows_management::apply_display_mode(&mode_app_handle, settings.display_mode)
+                    .await
+            {
+                warn!("Could not restore {:?} mode: {}", settings.display_mode, e);
             }
-        }
+        });
+    }
 
-        camera.stop_stream().expect("Could not stop stream");
+    // Camera loop
+    let (tx, rx) = flume::unbounded::<CapturedFrame>();
+    let capture_app_handle = app.app_handle().clone();
+    async_runtime::spawn(async move {
+        if let Err(e) = run_capture(
+            &capture_app_handle,
+            camera_selector.as_ref(),
+            &settings.camera,
+            tx,
+        ) {
+            error!("{}", e);
+            AppEvent::camera_error(e).emit(&capture_app_handle);
+        }
+        AppEvent::CameraStopped.emit(&capture_app_handle);
     });
 
     // Render loop
@@ -57,160 +244,436 @@ pub fn app_setup(app: &mut tauri::App) -> Result<(), Box<dyn std::error::Error>>
     async_runtime::spawn(async move {
         let wgpu_state = app_handle.state::<Arc<WgpuState>>();
         let app_state = app_handle.state::<Arc<AppState>>();
+        // The render loop is the only owner of the render targets, mode switches and preview
+        // windows hand over new ones through `render_rx` and wait for them to be configured
+        let mut targets = initial_targets;
+        let mut camera_running = true;
+        // Consecutive frames in which a surface could not be acquired
+        let mut surface_errors = 0u32;
+        let mut recovery = webgpu::RecoveryBackoff::default();
+        let mut last_stats_emit = Instant::now();
+        // Frames rendered since startup, shown by frame counter overlays
+        let mut frame_number = 0u64;
+        // Additional camera sources by id, re-uploaded when they have a newer frame
+        let mut source_textures = HashMap::<u32, SourceTexture>::new();
+
+        loop {
+            let event = if camera_running {
+                flume::Selector::new()
+                    .recv(&render_rx, |command| command.ok().map(RenderEvent::Command))
+                    .recv(&rx, |frame| {
+                        Some(frame.map_or(RenderEvent::CameraStopped, RenderEvent::Frame))
+                    })
+                    .wait()
+            } else {
+                render_rx.recv().ok().map(RenderEvent::Command)
+            };
+
+            let CapturedFrame {
+                buffer,
+                captured_at,
+            } = match event {
+                Some(RenderEvent::Frame(frame)) => frame,
+                Some(RenderEvent::Command(command)) => {
+                    wgpu_state.handle_render_command(&mut targets, command);
+                    continue;
+                }
+                Some(RenderEvent::CameraStopped) => {
+                    camera_running = false;
+                    continue;
+                }
+                None => break,
+            };
 
-        while let Ok(buffer) = rx.recv() {
             let t = Instant::now();
 
-            if app_state.render_paused.load(Ordering::SeqCst) {
+            // Nothing to present to in hidden mode, the frame is only rendered for a time-lapse
+            // or preview server viewers
+            if targets.is_empty()
+                && !app_state.timelapse.is_running()
+                && !app_state.preview_server.has_viewers()
+            {
                 continue;
             }
 
-            // Check if we need to reconfigure the surface
+            if wgpu_state.device_lost.load(Ordering::SeqCst) {
+                // Frames are dropped until a rebuild succeeds
+                if !recovery.ready(Instant::now()) {
+                    continue;
+                }
+                recover_renderer(&app_handle, &wgpu_state, &mut targets, &mut recovery).await;
+                source_textures.clear();
+                if wgpu_state.device_lost.load(Ordering::SeqCst) {
+                    continue;
+                }
+            }
+
+            // Check if we need to reconfigure the render targets
             {
                 let mut needs_reconfigure = wgpu_state.needs_reconfigure.lock().unwrap();
                 if *needs_reconfigure {
-                    let config = wgpu_state.config.read().unwrap();
-                    let surface = wgpu_state.surface.read().unwrap();
-                    surface.configure(&wgpu_state.device, &config);
+                    wgpu_state.reconfigure_targets(&mut targets);
                     *needs_reconfigure = false;
                 }
             }
 
-            let width = buffer.resolution().width();
-            let height = buffer.resolution().height();
+            // A frozen replay frame is shown instead of the live one, which is still buffered
+            let frozen = app_state.replay.frozen_frame();
+            let (yuyv, width, height, shown_captured_at) = match &frozen {
+                Some(frame) => (
+                    &frame.yuyv[..],
+                    frame.width,
+                    frame.height,
+                    frame.captured_at_local,
+                ),
+                None => (
+                    buffer.buffer(),
+                    buffer.resolution().width(),
+                    buffer.resolution().height(),
+                    Local::now()
+                        - TimeDelta::from_std(captured_at.elapsed()).unwrap_or(TimeDelta::zero()),
+                ),
+            };
 
-            let bytes = camera::yuyv_to_rgba(buffer.buffer(), width as usize, height as usize);
+            let mut bytes = camera::yuyv_to_rgba(yuyv, width as usize, height as usize);
+            // The chain is only locked around the run, commands editing it don't wait for a frame
+            let processors = app_state.processors.lock().unwrap().snapshot();
+            let processor_timings = processors.process(&mut RgbaFrame {
+                pixels: &mut bytes,
+                width,
+                height,
+            });
+            app_state
+                .processors
+                .lock()
+                .unwrap()
+                .record_timings(processor_timings);
+            let decoded_at = Instant::now();
 
-            // Calculate aspect-ratio-preserving camera settings
-            let camera_aspect = width as f32 / height as f32;
-            let config = wgpu_state.config.read().unwrap();
-            let window_aspect = config.width as f32 / config.height as f32;
-            drop(config);
+            let gpu = wgpu_state.gpu();
+            // Drives the GPU timing readback of an earlier frame
+            let _ = gpu.device.poll(wgpu::PollType::Poll);
+            let gpu_durations = gpu.timer.as_ref().and_then(|timer| timer.take_durations());
+            let timer = gpu.timer.as_ref().filter(|timer| timer.is_idle());
 
-            let (size_x, size_y) = if camera_aspect > window_aspect {
-                // Camera is wider than window - fit to width
-                (2.0, 2.0 / camera_aspect * window_aspect)
-            } else {
-                // Camera is taller than window - fit to height
-                (2.0 * camera_aspect / window_aspect, 2.0)
-            };
+            let mut encoder = gpu
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+
+            if let Some(timer) = timer {
+                timer.begin_upload(&mut encoder);
+            }
+            let texture = gpu.upload_frame(&mut encoder, &bytes, width, height);
+            if let Some(timer) = timer {
+                timer.end_upload(&mut encoder);
+                timer.begin_processing(&mut encoder);
+            }
 
-            let camera_settings = CameraSettingsUniform {
-                position: [0.0, 0.0],
-                size: [size_x, size_y],
+            let texture = {
+                let blur_settings = wgpu_state.blur_settings.read().unwrap();
+                let blur_mask = wgpu_state.blur_mask.read().unwrap();
+                gpu.blur
+                    .apply(
+                        &gpu.device,
+                        &gpu.queue,
+                        &gpu.sampler,
+                        &mut encoder,
+                        &texture,
+                        &blur_settings,
+                        blur_mask.as_ref(),
+                    )
+                    .unwrap_or(texture)
             };
-            wgpu_state.update_camera_settings(&camera_settings);
+            let texture = gpu
+                .effects
+                .apply(
+                    &gpu.device,
+                    &gpu.queue,
+                    &gpu.sampler,
+                    &mut encoder,
+                    &texture,
+                )
+                .unwrap_or(texture);
+            gpu.watermark.apply(
+                &gpu.device,
+                &gpu.queue,
+                &gpu.sampler,
+                &mut encoder,
+                &texture,
+                &wgpu_state.watermark.read().unwrap(),
+                wgpu_state.watermark_image.read().unwrap().as_ref(),
+            );
 
-            let texture_size = wgpu::Extent3d {
-                width,
-                height,
-                depth_or_array_layers: 1,
+            frame_number += 1;
+            let overlays = wgpu_state.overlays.read().unwrap().clone();
+            let overlay_frame = OverlayFrame {
+                captured_at: shown_captured_at,
+                frame_number,
             };
+            let (burned_in, on_screen): (Vec<TextOverlay>, Vec<TextOverlay>) =
+                overlays.into_iter().partition(|overlay| overlay.burn_in);
+            overlays::burn_in(
+                &gpu.text,
+                &mut encoder,
+                &texture,
+                &overlays::layout(&burned_in, &overlay_frame, width, height),
+            );
+            if let Some(timer) = timer {
+                timer.end_processing(&mut encoder);
+            }
+            let overlay_quads = overlays::layout(&on_screen, &overlay_frame, width, height);
 
-            let texture = wgpu_state.device.create_texture(&wgpu::TextureDescriptor {
-                label: None,
-                sample_count: 1,
-                mip_level_count: 1,
-                size: texture_size,
-                dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8UnormSrgb,
-                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-                view_formats: &[],
-            });
+            // Snapshots get everything drawn into the frame so far, not the on-screen overlays
+            let snapshot = app_state
+                .timelapse
+                .due(frame_number)
+                .then(|| FrameReadback::record(&gpu.device, &mut encoder, &texture));
+            let preview = app_state
+                .preview_server
+                .wants_frame()
+                .then(|| FrameReadback::record(&gpu.device, &mut encoder, &texture));
 
-            wgpu_state.queue.write_texture(
-                wgpu::TexelCopyTextureInfo {
-                    texture: &texture,
-                    mip_level: 0,
-                    origin: wgpu::Origin3d::ZERO,
-                    aspect: wgpu::TextureAspect::All,
-                },
-                &bytes,
-                wgpu::TexelCopyBufferLayout {
-                    offset: 0,
-                    bytes_per_row: Some(4 * width),
-                    rows_per_image: Some(height),
-                },
-                texture_size,
-            );
+            let bind_group = gpu.frame_bind_group(&texture);
 
-            let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-            let bind_group = wgpu_state
-                .device
-                .create_bind_group(&wgpu::BindGroupDescriptor {
-                    layout: &wgpu_state.bind_group_layout,
-                    entries: &[
-                        wgpu::BindGroupEntry {
-                            binding: 0,
-                            resource: wgpu::BindingResource::TextureView(&texture_view),
-                        },
-                        wgpu::BindGroupEntry {
-                            binding: 1,
-                            resource: wgpu::BindingResource::Sampler(&wgpu_state.sampler),
+            // Additional sources are drawn as captured, without the processing above
+            let composite_layout = *app_state.compositor.layout.read().unwrap();
+            let sources = match composite_layout {
+                CompositeLayout::Single => Vec::new(),
+                _ => app_state.compositor.sources(),
+            };
+            source_textures.retain(|id, _| sources.iter().any(|source| source.id == *id));
+            for source in &sources {
+                // Nothing to draw until the first frame
+                let Some(frame) = source.frame() else {
+                    continue;
+                };
+                let uploaded = source_textures.get(&source.id);
+                if uploaded.is_none_or(|uploaded| uploaded.sequence != frame.sequence) {
+                    let texture =
+                        gpu.upload_frame(&mut encoder, &frame.pixels, frame.width, frame.height);
+                    source_textures.insert(
+                        source.id,
+                        SourceTexture {
+                            sequence: frame.sequence,
+                            aspect: frame.width as f32 / frame.height as f32,
+                            bind_group: gpu.frame_bind_group(&texture),
                         },
-                    ],
-                    label: None,
-                });
-
-            // Attempt to get the surface texture
-            let surface = wgpu_state.surface.read().unwrap();
-            let output = match surface.get_current_texture() {
-                Ok(output) => output,
-                Err(wgpu::SurfaceError::Outdated | wgpu::SurfaceError::Lost) => {
-                    let config = wgpu_state.config.read().unwrap();
-                    surface.configure(&wgpu_state.device, &config);
-
-                    match surface.get_current_texture() {
-                        Ok(output) => output,
-                        Err(_e) => {
-                            continue;
-                        }
-                    }
+                    );
+                }
+            }
+            let drawn_sources: Vec<&SourceTexture> = sources
+                .iter()
+                .filter_map(|source| source_textures.get(&source.id))
+                .collect();
+            let camera_aspects: Vec<f32> = std::iter::once(width as f32 / height as f32)
+                .chain(drawn_sources.iter().map(|source| source.aspect))
+                .collect();
+
+            let configs = wgpu_state.configs.read().unwrap().clone();
+
+            let hud_info = wgpu_state.hud_visible.load(Ordering::Relaxed).then(|| {
+                let pipeline_stats = app_state.pipeline_stats.lock().unwrap();
+                let snapshot = pipeline_stats.snapshot();
+                HudInfo {
+                    fps: snapshot.fps,
+                    frame_times: pipeline_stats.frame_times(),
+                    dropped_frames: snapshot.dropped_frames,
+                    resolution: (width, height),
+                    pixel_format: buffer.source_frame_format().to_string(),
+                    adapter: gpu.adapter.get_info().name,
                 }
-                Err(_e) => {
+            });
+
+            let mut outputs = Vec::with_capacity(targets.len());
+            let mut surface_error = None;
+            // Set when a target could not be presented this frame
+            let mut dropped = false;
+            let mut first_pass = true;
+
+            // The same frame is drawn into every render target
+            for (name, target) in &mut targets {
+                let Some(config) = configs.get(name) else {
                     continue;
+                };
+
+                // Aspect-ratio-preserving quads, the primary camera first
+                let target_aspect = config.width as f32 / config.height as f32;
+                let quads = compositor::layout(composite_layout, &camera_aspects, target_aspect);
+                let camera_settings = quads[0];
+                target.update_camera_settings(&gpu.queue, &camera_settings);
+                for (index, settings) in quads[1..].iter().enumerate() {
+                    target.update_source_camera_settings(&gpu, index, settings);
                 }
-            };
-            drop(surface);
 
-            let view = output
-                .texture
-                .create_view(&wgpu::TextureViewDescriptor::default());
+                let (view, output) = match &target.output {
+                    RenderTargetOutput::Surface(surface) => {
+                        // Attempt to get the surface texture
+                        let output = match surface.get_current_texture() {
+                            Ok(output) => output,
+                            Err(wgpu::SurfaceError::Outdated | wgpu::SurfaceError::Lost) => {
+                                surface.configure(&gpu.device, config);
 
-            let mut encoder = wgpu_state
-                .device
-                .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
-            {
-                let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                    label: None,
-                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                        view: &view,
-                        resolve_target: None,
-                        ops: wgpu::Operations {
-                            // Clear to transparent so main window shows through
-                            load: wgpu::LoadOp::Clear(wgpu::Color {
-                                r: 0.0,
-                                g: 0.0,
-                                b: 0.0,
-                                a: 0.0,
-                            }),
-                            store: wgpu::StoreOp::Store,
-                        },
-                        depth_slice: None,
-                    })],
-                    depth_stencil_attachment: None,
-                    timestamp_writes: None,
-                    occlusion_query_set: None,
-                    multiview_mask: None,
-                });
-                rpass.set_pipeline(&wgpu_state.render_pipeline);
-                rpass.set_bind_group(0, &bind_group, &[]);
-                rpass.set_bind_group(1, &wgpu_state.camera_settings_bind_group, &[]);
-                rpass.draw(0..6, 0..1);
-            }
-
-            wgpu_state.queue.submit(Some(encoder.finish()));
-            output.present();
+                                match surface.get_current_texture() {
+                                    Ok(output) => output,
+                                    Err(e) => {
+                                        surface_error = Some(format!("{name}: {e}"));
+                                        dropped = true;
+                                        continue;
+                                    }
+                                }
+                            }
+                            // A timeout is a slow compositor, not a broken surface
+                            Err(wgpu::SurfaceError::Timeout) => {
+                                dropped = true;
+                                continue;
+                            }
+                            Err(e) => {
+                                surface_error = Some(format!("{name}: {e}"));
+                                dropped = true;
+                                continue;
+                            }
+                        };
+                        let view = output.texture.create_view(&wgpu::TextureViewDescriptor {
+                            format: Some(webgpu::render_format(config)),
+                            ..Default::default()
+                        });
+                        (view, Some(output))
+                    }
+                    RenderTargetOutput::Offscreen(texture) => (
+                        texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                        None,
+                    ),
+                };
+
+                {
+                    let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                        label: None,
+                        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                            view: &view,
+                            resolve_target: None,
+                            ops: wgpu::Operations {
+                                // Clear to transparent so main window shows through
+                                load: wgpu::LoadOp::Clear(wgpu::Color {
+                                    r: 0.0,
+                                    g: 0.0,
+                                    b: 0.0,
+                                    a: 0.0,
+                                }),
+                                store: wgpu::StoreOp::Store,
+                            },
+                            depth_slice: None,
+                        })],
+                        depth_stencil_attachment: None,
+                        timestamp_writes: timer.map(|timer| timer.render_pass_writes(first_pass)),
+                        occlusion_query_set: None,
+                        multiview_mask: None,
+                    });
+                    rpass.set_pipeline(&gpu.render_pipeline(webgpu::render_format(config)));
+                    rpass.set_bind_group(0, &bind_group, &[]);
+                    rpass.set_bind_group(1, &target.camera_settings_bind_group, &[]);
+                    rpass.draw(0..6, 0..1);
+
+                    for (source, (_, source_camera_settings_bind_group)) in
+                        drawn_sources.iter().zip(&target.source_camera_settings)
+                    {
+                        rpass.set_bind_group(0, &source.bind_group, &[]);
+                        rpass.set_bind_group(1, source_camera_settings_bind_group, &[]);
+                        rpass.draw(0..6, 0..1);
+                    }
+
+                    if !overlay_quads.is_empty() {
+                        let mut quads = overlay_quads.clone();
+                        overlays::fit_to_camera_quad(&mut quads, &camera_settings);
+                        gpu.text
+                            .draw(&mut rpass, webgpu::render_format(config), &quads);
+                    }
+
+                    if let Some(hud_info) = &hud_info {
+                        let quads = hud::layout(hud_info, config.width, config.height);
+                        gpu.text
+                            .draw(&mut rpass, webgpu::render_format(config), &quads);
+                    }
+                }
+                first_pass = false;
+
+                outputs.extend(output);
+            }
+
+            // Nothing was timed if every target was skipped
+            let timer = timer.filter(|_| !first_pass);
+            if let Some(timer) = timer {
+                timer.resolve(&mut encoder);
+            }
+
+            gpu.queue.submit(Some(encoder.finish()));
+            if let Some(snapshot) = snapshot {
+                app_state.timelapse.submit(snapshot);
+            }
+            if let Some(preview) = preview {
+                app_state.preview_server.submit(preview);
+            }
+            let submitted_at = Instant::now();
+            if let Some(timer) = timer {
+                timer.request_readback();
+            }
+            for output in outputs {
+                output.present();
+            }
+            let presented_at = Instant::now();
+
+            let mut pipeline_stats = app_state.pipeline_stats.lock().unwrap();
+            pipeline_stats.record(FrameTimestamps {
+                captured: captured_at,
+                decoded: decoded_at,
+                submitted: submitted_at,
+                presented: presented_at,
+            });
+            if dropped {
+                pipeline_stats.record_dropped();
+            }
+            if let Some(gpu_durations) = gpu_durations {
+                pipeline_stats.record_gpu(gpu_durations);
+            }
+            if last_stats_emit.elapsed() >= stats::STATS_EMIT_INTERVAL {
+                last_stats_emit = Instant::now();
+                let snapshot = pipeline_stats.snapshot();
+                // Nobody is listening for events without a window
+                if app_state.headless {
+                    info!(
+                        "{:.1} fps, {} dropped, {:.1} ms p95 latency",
+                        snapshot.fps, snapshot.dropped_frames, snapshot.present.p95_ms
+                    );
+                }
+                AppEvent::StatsUpdated(snapshot).emit(&app_handle);
+            }
+            drop(pipeline_stats);
+
+            match surface_error {
+                Some(e) => {
+                    surface_errors += 1;
+                    warn!(
+                        "Could not acquire surface texture ({} in a row): {}",
+                        surface_errors, e
+                    );
+                    if surface_errors == webgpu::SURFACE_ERROR_RECOVERY_THRESHOLD
+                        && recovery.ready(Instant::now())
+                    {
+                        recover_renderer(&app_handle, &wgpu_state, &mut targets, &mut recovery)
+                            .await;
+                        source_textures.clear();
+                    } else if surface_errors == webgpu::SURFACE_ERROR_ESCALATION_THRESHOLD {
+                        error!("Rendering keeps failing, giving up on recovery: {}", e);
+                        if recovery.report() {
+                            AppEvent::renderer_error(format!("The camera can't be displayed: {e}"))
+                                .emit(&app_handle);
+                        }
+                    }
+                }
+                None => {
+                    surface_errors = 0;
+                    recovery.reset();
+                }
+            }
 
             debug!("Frame rendered in {:?}", t.elapsed());
         }
@@ -218,3 +681,163 @@ pub fn app_setup(app: &mut tauri::App) -> Result<(), Box<dyn std::error::Error>>
 
     Ok(())
 }
+
+/// Streams frames from the camera to the render loop until it stops or fails
+fn run_capture(
+    app_handle: &AppHandle,
+    selector: Option<&CameraSelector>,
+    preferences: &CameraPreferences,
+    tx: flume::Sender<CapturedFrame>,
+) -> Result<(), String> {
+    let max_resolution = Resolution::new(preferences.max_width, preferences.max_height);
+    let mut camera = match (selector, &preferences.device_name) {
+        (Some(selector), _) => {
+            camera::create_camera(Some(selector), max_resolution, preferences.frame_rate)?
+        }
+        // The saved camera may be unplugged, the first one stands in for it then
+        (None, Some(name)) => camera::create_camera(
+            Some(&CameraSelector::Name(name.clone())),
+            max_resolution,
+            preferences.frame_rate,
+        )
+        .or_else(|e| {
+            warn!("{}, opening the first camera instead", e);
+            camera::create_camera(None, max_resolution, preferences.frame_rate)
+        })?,
+        (None, None) => camera::create_camera(None, max_resolution, preferences.frame_rate)?,
+    };
+
+    camera
+        .open_stream()
+        .map_err(|e| format!("Could not open stream: {e}"))?;
+
+    let resolution = camera.resolution();
+    AppEvent::CameraStarted(events::CameraStarted {
+        name: camera.info().human_name(),
+        resolution: events::FrameSize {
+            width: resolution.width(),
+            height: resolution.height(),
+        },
+        frame_rate: camera.frame_rate(),
+        pixel_format: camera.frame_format().to_string(),
+    })
+    .emit(app_handle);
+
+    let app_state = app_handle.state::<Arc<AppState>>();
+    let mut result = Ok(());
+    // Runs for the lifetime of the app, until the camera fails or the render loop is gone
+    loop {
+        let buffer = match camera.frame() {
+            Ok(buffer) => buffer,
+            Err(e) => {
+                result = Err(format!("Could not get frame: {e}"));
+                break;
+            }
+        };
+        let captured_at = Instant::now();
+        publish_camera_resolution(app_handle, buffer.resolution());
+        if app_state.replay.is_enabled() {
+            app_state.replay.push(ReplayFrame {
+                yuyv: buffer.buffer().to_vec(),
+                width: buffer.resolution().width(),
+                height: buffer.resolution().height(),
+                captured_at,
+                captured_at_local: Local::now(),
+            });
+        }
+        if tx
+            .send(CapturedFrame {
+                buffer,
+                captured_at,
+            })
+            .is_err()
+        {
+            break;
+        }
+    }
+
+    // A capture error takes precedence over a failure to stop
+    result.and(
+        camera
+            .stop_stream()
+            .map_err(|e| format!("Could not stop stream: {e}")),
+    )
+}
+
+/// Rebuilds the renderer after a device loss or persistent surface failures, telling the UI
+/// whether it worked. Failures are reported once until a frame renders again
+async fn recover_renderer(
+    app_handle: &AppHandle,
+    wgpu_state: &WgpuState,
+    targets: &mut RenderTargets,
+    recovery: &mut webgpu::RecoveryBackoff,
+) {
+    match wgpu_state.recover(targets).await {
+        Ok(()) => {
+            AppEvent::RendererRecovered.emit(app_handle);
+        }
+        Err(e) => {
+            let delay = recovery.failed(Instant::now());
+            error!(
+                "Could not rebuild the renderer, retrying in {:?}: {}",
+                delay, e
+            );
+            if recovery.report() {
+                AppEvent::renderer_error(e).emit(app_handle);
+            }
+        }
+    }
+}
+
+/// Records the resolution of the active source and refits the overlay when it changes
+fn publish_camera_resolution(app_handle: &AppHandle, resolution: Resolution) {
+    let app_state = app_handle.state::<Arc<AppState>>();
+    let mut camera_resolution = app_state.camera_resolution.lock().unwrap();
+    if *camera_resolution == Some(resolution) {
+        return;
+    }
+    *camera_resolution = Some(resolution);
+    drop(camera_resolution);
+
+    AppEvent::ResolutionChanged(events::FrameSize {
+        width: resolution.width(),
+        height: resolution.height(),
+    })
+    .emit(app_handle);
+
+    let main_thread_handle = app_handle.clone();
+    let _ = app_handle
+        .run_on_main_thread(move || windows_management::refit_overlay(&main_thread_handle));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::windows_management::NoopPlatform;
+
+    fn app_state() -> AppState {
+        AppState::with_platform(Box::new(NoopPlatform))
+    }
+
+    #[test]
+    fn starts_in_thumbnail_mode() {
+        let app_state = app_state();
+
+        assert_eq!(app_state.display_mode(), DisplayMode::Thumbnail);
+        assert!(!app_state.overlay_interactive.load(Ordering::Relaxed));
+        assert!(!app_state.headless);
+    }
+
+    #[test]
+    fn camera_aspect_follows_the_source_resolution() {
+        let app_state = app_state();
+        assert_eq!(app_state.camera_aspect(), camera::DEFAULT_CAMERA_ASPECT);
+
+        *app_state.camera_resolution.lock().unwrap() = Some(Resolution::new(640, 480));
+        assert_eq!(app_state.camera_aspect(), 640.0 / 480.0);
+
+        // A broken resolution keeps the default rather than dividing by zero
+        *app_state.camera_resolution.lock().unwrap() = Some(Resolution::new(640, 0));
+        assert_eq!(app_state.camera_aspect(), camera::DEFAULT_CAMERA_ASPECT);
+    }
+}
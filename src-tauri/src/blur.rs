@@ -0,0 +1,671 @@
+use crate::image::RgbaImage;
+use crate::webgpu::OFFSCREEN_FORMAT;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Taps on each side of the center pixel, bounded by the weights the uniform can hold
+pub const MAX_BLUR_TAPS: usize = 32;
+pub const MAX_BLUR_RADIUS: f32 = 128.0;
+/// Largest factor the frame is scaled down by before blurring
+const MAX_DOWNSCALE: u32 = 4;
+const WEIGHT_VECTORS: usize = (MAX_BLUR_TAPS + 1).div_ceil(4);
+
+/// Which parts of the frame are blurred
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BlurMask {
+    /// The whole frame
+    Whole,
+    /// White, opaque areas of a PNG stretched over the frame
+    Image { path: PathBuf },
+    /// Pixels close to `color` (sRGB, 0 to 1), typically a green screen behind the subject
+    Keyed {
+        color: [f32; 3],
+        tolerance: f32,
+        softness: f32,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlurSettings {
+    pub enabled: bool,
+    /// Blur radius in camera pixels
+    pub radius: f32,
+    /// Mix between the sharp (0) and the fully blurred (1) frame
+    pub strength: f32,
+    pub mask: BlurMask,
+}
+
+impl Default for BlurSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            radius: 24.0,
+            strength: 1.0,
+            mask: BlurMask::Whole,
+        }
+    }
+}
+
+impl BlurSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=MAX_BLUR_RADIUS).contains(&self.radius) {
+            return Err(format!(
+                "Blur radius must be between 0 and {MAX_BLUR_RADIUS}"
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.strength) {
+            return Err("Blur strength must be between 0 and 1".to_string());
+        }
+        if let BlurMask::Keyed {
+            tolerance,
+            softness,
+            ..
+        } = self.mask
+        {
+            if tolerance < 0.0 || softness < 0.0 {
+                return Err("Key tolerance and softness can't be negative".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Factor the frame is scaled down by before blurring with `radius`, so the
+/// tap count stays low for large radii
+pub fn downscale_factor(radius: f32) -> u32 {
+    ((radius / 8.0).ceil() as u32).clamp(1, MAX_DOWNSCALE)
+}
+
+/// Normalized Gaussian weights for a blur of `radius` pixels, center first. The kernel is
+/// symmetric, so the center weight plus twice every other one sums to 1
+pub fn gaussian_weights(radius: f32) -> Vec<f32> {
+    let taps = (radius.max(0.0).ceil() as usize).min(MAX_BLUR_TAPS);
+    // The kernel is cut off at three standard deviations
+    let sigma = (radius / 3.0).max(f32::EPSILON);
+    let mut weights: Vec<f32> = (0..=taps)
+        .map(|i| (-((i * i) as f32) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let sum = weights[0] + 2.0 * weights[1..].iter().sum::<f32>();
+    for weight in &mut weights {
+        *weight /= sum;
+    }
+    weights
+}
+
+/// CPU version of the two blur passes on RGBA `pixels`, clamping at the edges like the
+/// GPU sampler. Runs at the given resolution, the GPU version blurs a downscaled copy
+pub fn blur_reference(pixels: &[f32], width: usize, height: usize, radius: f32) -> Vec<f32> {
+    let weights = gaussian_weights(radius);
+    let pass = |source: &[f32], horizontal: bool| {
+        let mut output = vec![0.0; source.len()];
+        for y in 0..height {
+            for x in 0..width {
+                for (offset, weight) in weights.iter().enumerate() {
+                    let offset = offset as isize;
+                    let taps: &[isize] = if offset == 0 {
+                        &[0]
+                    } else {
+                        &[offset, -offset]
+                    };
+                    for tap in taps {
+                        let (sx, sy) = if horizontal {
+                            ((x as isize + tap).clamp(0, width as isize - 1) as usize, y)
+                        } else {
+                            (x, (y as isize + tap).clamp(0, height as isize - 1) as usize)
+                        };
+                        for channel in 0..4 {
+                            output[(y * width + x) * 4 + channel] +=
+                                source[(sy * width + sx) * 4 + channel] * weight;
+                        }
+                    }
+                }
+            }
+        }
+        output
+    };
+    pass(&pass(pixels, true), false)
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BlurPassUniform {
+    texel_step: [f32; 2],
+    taps: u32,
+    _padding: u32,
+    weights: [[f32; 4]; WEIGHT_VECTORS],
+}
+
+impl BlurPassUniform {
+    fn new(texel_step: [f32; 2], weights: &[f32]) -> Self {
+        let mut packed = [[0.0; 4]; WEIGHT_VECTORS];
+        for (i, weight) in weights.iter().enumerate() {
+            packed[i / 4][i % 4] = *weight;
+        }
+        Self {
+            texel_step,
+            taps: weights.len() as u32 - 1,
+            _padding: 0,
+            weights: packed,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CompositeUniform {
+    mask_mode: u32,
+    strength: f32,
+    tolerance: f32,
+    softness: f32,
+    key_color: [f32; 4],
+}
+
+#[derive(Clone)]
+struct BlurTextures {
+    /// Downscaled frame after the horizontal and the vertical pass
+    reduced: [wgpu::Texture; 2],
+    /// Full resolution result of the composite
+    output: wgpu::Texture,
+}
+
+/// Separable Gaussian blur on the GPU: a horizontal and a vertical pass at reduced resolution,
+/// then a full resolution composite blending the blurred frame in where the mask allows
+pub struct BlurRenderer {
+    blur_bind_group_layout: wgpu::BindGroupLayout,
+    composite_bind_group_layout: wgpu::BindGroupLayout,
+    blur_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+    /// One per pass, both are written before the passes run
+    pass_buffers: [wgpu::Buffer; 2],
+    composite_buffer: wgpu::Buffer,
+    textures: Mutex<Option<BlurTextures>>,
+    /// Uploaded mask image, compared by pointer to notice a new mask
+    mask: Mutex<Option<(Arc<RgbaImage>, wgpu::Texture)>>,
+    /// Bound when there is no mask image, the shader ignores it
+    blank_mask: wgpu::Texture,
+}
+
+impl BlurRenderer {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("blur.wgsl"));
+
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let sampler_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let blur_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[texture_entry(0), sampler_entry(1), uniform_entry(2)],
+                label: Some("blur_bind_group_layout"),
+            });
+        let composite_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    texture_entry(0),
+                    texture_entry(1),
+                    texture_entry(2),
+                    sampler_entry(3),
+                    uniform_entry(4),
+                ],
+                label: Some("blur_composite_bind_group_layout"),
+            });
+
+        let pipeline = |layout: &wgpu::BindGroupLayout, entry_point: &str| {
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[layout],
+                immediate_size: 0,
+            });
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_fullscreen"),
+                    buffers: &[],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some(entry_point),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: OFFSCREEN_FORMAT,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                multisample: wgpu::MultisampleState::default(),
+                depth_stencil: None,
+                multiview_mask: None,
+                cache: None,
+            })
+        };
+        let blur_pipeline = pipeline(&blur_bind_group_layout, "fs_blur");
+        let composite_pipeline = pipeline(&composite_bind_group_layout, "fs_composite");
+
+        let uniform_buffer = |label, size: usize| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: size as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        };
+        let pass_size = std::mem::size_of::<BlurPassUniform>();
+        let pass_buffers = [
+            uniform_buffer("blur_horizontal_pass", pass_size),
+            uniform_buffer("blur_vertical_pass", pass_size),
+        ];
+        let composite_buffer =
+            uniform_buffer("blur_composite", std::mem::size_of::<CompositeUniform>());
+
+        let blank_mask = RgbaImage {
+            width: 1,
+            height: 1,
+            pixels: vec![255; 4],
+        }
+        .create_texture(
+            device,
+            queue,
+            "blur_blank_mask",
+            wgpu::TextureFormat::Rgba8Unorm,
+        );
+
+        Self {
+            blur_bind_group_layout,
+            composite_bind_group_layout,
+            blur_pipeline,
+            composite_pipeline,
+            pass_buffers,
+            composite_buffer,
+            textures: Mutex::new(None),
+            mask: Mutex::new(None),
+            blank_mask,
+        }
+    }
+
+    /// Records the blur of `input` into `encoder`. Returns the blurred texture, or `None`
+    /// when the blur is off and `input` should be used as is
+    #[allow(clippy::too_many_arguments)]
+    pub fn apply(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        sampler: &wgpu::Sampler,
+        encoder: &mut wgpu::CommandEncoder,
+        input: &wgpu::Texture,
+        settings: &BlurSettings,
+        mask_image: Option<&Arc<RgbaImage>>,
+    ) -> Option<wgpu::Texture> {
+        if !settings.enabled || settings.radius <= 0.0 || settings.strength <= 0.0 {
+            return None;
+        }
+
+        let downscale = downscale_factor(settings.radius);
+        let reduced_width = input.width().div_ceil(downscale);
+        let reduced_height = input.height().div_ceil(downscale);
+        let textures = self.textures(device, input, reduced_width, reduced_height);
+
+        let weights = gaussian_weights(settings.radius / downscale as f32);
+        let steps = [
+            [1.0 / reduced_width as f32, 0.0],
+            [0.0, 1.0 / reduced_height as f32],
+        ];
+        for (buffer, step) in self.pass_buffers.iter().zip(steps) {
+            queue.write_buffer(
+                buffer,
+                0,
+                bytemuck::bytes_of(&BlurPassUniform::new(step, &weights)),
+            );
+        }
+
+        let (mask_mode, tolerance, softness, key_color) = match &settings.mask {
+            BlurMask::Whole => (0, 0.0, 0.0, [0.0; 4]),
+            BlurMask::Image { .. } => (1, 0.0, 0.0, [0.0; 4]),
+            BlurMask::Keyed {
+                color,
+                tolerance,
+                softness,
+            } => {
+                // The frame is sampled as linear color
+                let [r, g, b] = color.map(srgb_to_linear);
+                (2, *tolerance, *softness, [r, g, b, 1.0])
+            }
+        };
+        queue.write_buffer(
+            &self.composite_buffer,
+            0,
+            bytemuck::bytes_of(&CompositeUniform {
+                mask_mode,
+                strength: settings.strength,
+                tolerance,
+                softness,
+                key_color,
+            }),
+        );
+
+        let view = |texture: &wgpu::Texture| texture.create_view(&Default::default());
+        let input_view = view(input);
+        let reduced_views = [view(&textures.reduced[0]), view(&textures.reduced[1])];
+        let mask_view = match mask_image {
+            Some(image) if mask_mode == 1 => view(&self.mask_texture(device, queue, image)),
+            _ => view(&self.blank_mask),
+        };
+
+        // Horizontal pass reads the full frame, the vertical one the horizontal result
+        for (pass, source) in [&input_view, &reduced_views[0]].into_iter().enumerate() {
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &self.blur_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(source),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: self.pass_buffers[pass].as_entire_binding(),
+                    },
+                ],
+                label: Some("blur_bind_group"),
+            });
+            draw_fullscreen(
+                encoder,
+                &reduced_views[pass],
+                &self.blur_pipeline,
+                &bind_group,
+            );
+        }
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.composite_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&input_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&reduced_views[1]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&mask_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: self.composite_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("blur_composite_bind_group"),
+        });
+        draw_fullscreen(
+            encoder,
+            &view(&textures.output),
+            &self.composite_pipeline,
+            &bind_group,
+        );
+
+        Some(textures.output)
+    }
+
+    /// Intermediate textures for a frame the size of `input`, reused while the size holds
+    fn textures(
+        &self,
+        device: &wgpu::Device,
+        input: &wgpu::Texture,
+        reduced_width: u32,
+        reduced_height: u32,
+    ) -> BlurTextures {
+        let mut textures = self.textures.lock().unwrap();
+        if let Some(existing) = textures.as_ref() {
+            if existing.output.size() == input.size()
+                && existing.reduced[0].width() == reduced_width
+                && existing.reduced[0].height() == reduced_height
+            {
+                return existing.clone();
+            }
+        }
+
+        let create = |width, height| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("blur_target"),
+                sample_count: 1,
+                mip_level_count: 1,
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                dimension: wgpu::TextureDimension::D2,
+                format: OFFSCREEN_FORMAT,
+                // Copied out when the processed frame is saved
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            })
+        };
+        let created = BlurTextures {
+            reduced: [
+                create(reduced_width, reduced_height),
+                create(reduced_width, reduced_height),
+            ],
+            output: create(input.width(), input.height()),
+        };
+        *textures = Some(created.clone());
+        created
+    }
+
+    fn mask_texture(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: &Arc<RgbaImage>,
+    ) -> wgpu::Texture {
+        let mut mask = self.mask.lock().unwrap();
+        match mask.as_ref() {
+            Some((uploaded, texture)) if Arc::ptr_eq(uploaded, image) => texture.clone(),
+            _ => {
+                let texture = image.create_texture(
+                    device,
+                    queue,
+                    "blur_mask",
+                    wgpu::TextureFormat::Rgba8Unorm,
+                );
+                *mask = Some((image.clone(), texture.clone()));
+                texture
+            }
+        }
+    }
+}
+
+fn draw_fullscreen(
+    encoder: &mut wgpu::CommandEncoder,
+    target: &wgpu::TextureView,
+    pipeline: &wgpu::RenderPipeline,
+    bind_group: &wgpu::BindGroup,
+) {
+    let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("blur_pass"),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: target,
+            depth_slice: None,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        timestamp_writes: None,
+        occlusion_query_set: None,
+        multiview_mask: None,
+    });
+    rpass.set_pipeline(pipeline);
+    rpass.set_bind_group(0, bind_group, &[]);
+    rpass.draw(0..3, 0..1);
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-5, "{a} != {b}");
+    }
+
+    /// `width` x `height` RGBA image, black except for one white pixel
+    fn impulse(width: usize, height: usize, x: usize, y: usize) -> Vec<f32> {
+        let mut pixels = vec![0.0; width * height * 4];
+        pixels[(y * width + x) * 4..][..4].copy_from_slice(&[1.0; 4]);
+        pixels
+    }
+
+    #[test]
+    fn weights_are_normalized() {
+        for radius in [0.5, 1.0, 3.0, 7.5, 16.0, 32.0, 100.0] {
+            let weights = gaussian_weights(radius);
+            assert_close(weights[0] + 2.0 * weights[1..].iter().sum::<f32>(), 1.0);
+        }
+    }
+
+    #[test]
+    fn weights_decrease_away_from_the_center() {
+        let weights = gaussian_weights(10.0);
+        assert_eq!(weights.len(), 11);
+        assert!(weights.windows(2).all(|pair| pair[0] > pair[1]));
+    }
+
+    #[test]
+    fn tap_count_is_bounded() {
+        assert_eq!(gaussian_weights(0.0), vec![1.0]);
+        assert_eq!(gaussian_weights(1000.0).len(), MAX_BLUR_TAPS + 1);
+        assert!(
+            BlurPassUniform::new([0.0; 2], &gaussian_weights(1000.0)).taps as usize
+                <= MAX_BLUR_TAPS
+        );
+    }
+
+    #[test]
+    fn downscale_grows_with_the_radius() {
+        assert_eq!(downscale_factor(0.0), 1);
+        assert_eq!(downscale_factor(8.0), 1);
+        assert_eq!(downscale_factor(16.0), 2);
+        assert_eq!(downscale_factor(MAX_BLUR_RADIUS), MAX_DOWNSCALE);
+        // The reduced radius always fits in the taps the GPU pass supports
+        assert!(MAX_BLUR_RADIUS / MAX_DOWNSCALE as f32 <= MAX_BLUR_TAPS as f32);
+    }
+
+    #[test]
+    fn zero_radius_leaves_the_image_unchanged() {
+        let pixels = impulse(5, 4, 2, 1);
+        assert_eq!(blur_reference(&pixels, 5, 4, 0.0), pixels);
+    }
+
+    #[test]
+    fn uniform_image_stays_uniform() {
+        let pixels = vec![0.25; 8 * 6 * 4];
+        for value in blur_reference(&pixels, 8, 6, 3.0) {
+            assert_close(value, 0.25);
+        }
+    }
+
+    #[test]
+    fn impulse_response_is_the_separable_kernel() {
+        let (width, height, radius) = (15, 15, 4.0);
+        let blurred = blur_reference(&impulse(width, height, 7, 7), width, height, radius);
+        let weights = gaussian_weights(radius);
+
+        for y in 0..height {
+            for x in 0..width {
+                let weight = |offset: usize| weights.get(offset).copied().unwrap_or(0.0);
+                let expected = weight(x.abs_diff(7)) * weight(y.abs_diff(7));
+                for channel in 0..4 {
+                    assert_close(blurred[(y * width + x) * 4 + channel], expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn blur_preserves_energy_away_from_the_edges() {
+        let (width, height) = (21, 21);
+        let blurred = blur_reference(&impulse(width, height, 10, 10), width, height, 5.0);
+        assert_close(blurred.iter().step_by(4).sum(), 1.0);
+    }
+
+    #[test]
+    fn edges_are_clamped() {
+        // A white left column blurred horizontally stays brightest at the edge
+        let (width, height) = (6, 1);
+        let blurred = blur_reference(&impulse(width, height, 0, 0), width, height, 2.0);
+        let weights = gaussian_weights(2.0);
+        // The center tap and the taps past the left edge all read the white pixel
+        let expected = weights[0] + weights[1..].iter().sum::<f32>();
+        assert_close(blurred[0], expected);
+        assert!((1..width).all(|x| blurred[x * 4] < blurred[0]));
+    }
+
+    #[test]
+    fn settings_are_validated() {
+        assert!(BlurSettings::default().validate().is_ok());
+        let too_large = BlurSettings {
+            radius: MAX_BLUR_RADIUS + 1.0,
+            ..BlurSettings::default()
+        };
+        assert!(too_large.validate().is_err());
+        let negative_tolerance = BlurSettings {
+            mask: BlurMask::Keyed {
+                color: [0.0, 1.0, 0.0],
+                tolerance: -0.1,
+                softness: 0.1,
+            },
+            ..BlurSettings::default()
+        };
+        assert!(negative_tolerance.validate().is_err());
+    }
+}
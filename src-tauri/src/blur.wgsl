@@ -0,0 +1,79 @@
+struct BlurPass {
+    // Distance between taps in texture coordinates, along the blur direction
+    texel_step: vec2<f32>,
+    // Taps on each side of the center
+    taps: u32,
+    // Normalized Gaussian weights, center first, packed four per vector
+    weights: array<vec4<f32>, 9>,
+};
+
+struct Composite {
+    // 0: whole frame, 1: mask texture, 2: color key
+    mask_mode: u32,
+    // Mix between the sharp and the blurred frame
+    strength: f32,
+    // Color distance under which a pixel is keyed out, and the width of the transition
+    tolerance: f32,
+    softness: f32,
+    key_color: vec4<f32>,
+};
+
+struct FullscreenVertex {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) in_vertex_index: u32) -> FullscreenVertex {
+    let uv = vec2<f32>(f32((in_vertex_index << 1u) & 2u), f32(in_vertex_index & 2u));
+    var out: FullscreenVertex;
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@group(0) @binding(0) var blur_source: texture_2d<f32>;
+@group(0) @binding(1) var blur_sampler: sampler;
+@group(0) @binding(2) var<uniform> blur_pass: BlurPass;
+
+fn weight(index: u32) -> f32 {
+    return blur_pass.weights[index / 4u][index % 4u];
+}
+
+// One direction of the separable Gaussian, run horizontally then vertically
+@fragment
+fn fs_blur(in: FullscreenVertex) -> @location(0) vec4<f32> {
+    var color = textureSample(blur_source, blur_sampler, in.uv) * weight(0u);
+    for (var i = 1u; i <= blur_pass.taps; i++) {
+        let offset = blur_pass.texel_step * f32(i);
+        color += textureSample(blur_source, blur_sampler, in.uv + offset) * weight(i);
+        color += textureSample(blur_source, blur_sampler, in.uv - offset) * weight(i);
+    }
+    return color;
+}
+
+@group(0) @binding(0) var sharp_texture: texture_2d<f32>;
+@group(0) @binding(1) var blurred_texture: texture_2d<f32>;
+@group(0) @binding(2) var mask_texture: texture_2d<f32>;
+@group(0) @binding(3) var composite_sampler: sampler;
+@group(0) @binding(4) var<uniform> composite: Composite;
+
+// Blends the blurred frame back over the full resolution one where the mask asks for it
+@fragment
+fn fs_composite(in: FullscreenVertex) -> @location(0) vec4<f32> {
+    let sharp = textureSample(sharp_texture, composite_sampler, in.uv);
+    let blurred = textureSample(blurred_texture, composite_sampler, in.uv);
+
+    var amount = 1.0;
+    if composite.mask_mode == 1u {
+        // White, opaque areas of the mask are blurred
+        let mask = textureSample(mask_texture, composite_sampler, in.uv);
+        amount = dot(mask.rgb, vec3<f32>(0.299, 0.587, 0.114)) * mask.a;
+    } else if composite.mask_mode == 2u {
+        // Pixels close to the key color are background and get blurred
+        let key_distance = distance(sharp.rgb, composite.key_color.rgb);
+        amount = 1.0 - smoothstep(composite.tolerance, composite.tolerance + composite.softness, key_distance);
+    }
+
+    return mix(sharp, blurred, amount * composite.strength);
+}
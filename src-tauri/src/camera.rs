@@ -1,22 +1,94 @@
 use nokhwa::pixel_format::RgbAFormat;
-use nokhwa::utils::{RequestedFormat, RequestedFormatType, Resolution};
+use nokhwa::utils::{CameraFormat, FrameFormat, RequestedFormat, RequestedFormatType, Resolution};
 use nokhwa::{native_api_backend, query, Camera};
 use rayon::prelude::*;
 
-const MAX_FRAME_SIZE: (u32, u32) = (1280, 720);
+/// Aspect ratio assumed until the first frame tells us the real one
+pub const DEFAULT_CAMERA_ASPECT: f32 = 16.0 / 9.0;
 
-pub fn create_camera() -> Camera {
-    let backend = native_api_backend().expect("Could not get backend");
-    let devices = query(backend).expect("Could not query backend");
-    let device = devices.first().expect("No devices found");
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CameraSelector {
+    /// Position in the list of cameras reported by the backend
+    Index(u32),
+    /// Human readable name of the camera
+    Name(String),
+}
+
+impl std::fmt::Display for CameraSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Index(index) => write!(f, "index {index}"),
+            Self::Name(name) => write!(f, "\"{name}\""),
+        }
+    }
+}
+
+impl CameraSelector {
+    /// A number is an index, anything else a name
+    pub fn parse(value: &str) -> Self {
+        match value.parse() {
+            Ok(index) => Self::Index(index),
+            Err(_) => Self::Name(value.to_string()),
+        }
+    }
+}
+
+/// Opens the camera matching `selector`, or the first one without a selector. A selector
+/// that matches no camera is an error rather than opening some other camera.
+/// Without a `frame_rate`, the highest resolution up to `max_resolution` is picked,
+/// otherwise the format closest to both
+pub fn create_camera(
+    selector: Option<&CameraSelector>,
+    max_resolution: Resolution,
+    frame_rate: Option<u32>,
+) -> Result<Camera, String> {
+    let backend = native_api_backend().ok_or("Could not get backend")?;
+    let devices = query(backend).map_err(|e| format!("Could not query backend: {e}"))?;
+    let names: Vec<String> = devices.iter().map(|device| device.human_name()).collect();
+    let device = &devices[select_camera(&names, selector)?];
 
-    let format =
-        RequestedFormat::new::<RgbAFormat>(RequestedFormatType::HighestResolution(Resolution {
-            width_x: MAX_FRAME_SIZE.0,
-            height_y: MAX_FRAME_SIZE.1,
-        }));
+    // Frames are decoded as YUYV by the render loop
+    let format_type = match frame_rate {
+        Some(frame_rate) => RequestedFormatType::Closest(CameraFormat::new(
+            max_resolution,
+            FrameFormat::YUYV,
+            frame_rate,
+        )),
+        None => RequestedFormatType::HighestResolution(max_resolution),
+    };
+    let format = RequestedFormat::new::<RgbAFormat>(format_type);
 
-    Camera::new(device.index().to_owned(), format).expect("Could not create camera")
+    Camera::new(device.index().to_owned(), format)
+        .map_err(|e| format!("Could not create camera: {e}"))
+}
+
+/// Position of the camera `selector` picks among cameras named `names`, the first one without
+/// a selector. The error lists the cameras to choose from
+fn select_camera(names: &[String], selector: Option<&CameraSelector>) -> Result<usize, String> {
+    if names.is_empty() {
+        return Err("No devices found".to_string());
+    }
+    let Some(selector) = selector else {
+        return Ok(0);
+    };
+    names
+        .iter()
+        .enumerate()
+        .position(|(index, name)| match selector {
+            CameraSelector::Index(wanted) => index as u32 == *wanted,
+            CameraSelector::Name(wanted) => name == wanted,
+        })
+        .ok_or_else(|| {
+            let available: Vec<String> = names
+                .iter()
+                .enumerate()
+                .map(|(index, name)| format!("{index}: \"{name}\""))
+                .collect();
+            format!(
+                "No camera matches {selector}, available cameras are {}",
+                available.join(", ")
+            )
+        })
 }
 
 // TODO: this is fast enough (run `make bench`)
@@ -54,3 +126,47 @@ pub fn yuyv_to_rgba(yuyv: &[u8], width: usize, height: usize) -> Vec<u8> {
 
     rgba
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names() -> Vec<String> {
+        vec![
+            "FaceTime HD Camera".to_string(),
+            "Studio Camera".to_string(),
+        ]
+    }
+
+    #[test]
+    fn selectors_pick_by_index_or_name() {
+        assert_eq!(select_camera(&names(), None), Ok(0));
+        assert_eq!(
+            select_camera(&names(), Some(&CameraSelector::Index(1))),
+            Ok(1)
+        );
+        assert_eq!(
+            select_camera(&names(), Some(&CameraSelector::parse("Studio Camera"))),
+            Ok(1)
+        );
+    }
+
+    #[test]
+    fn unknown_selector_lists_the_cameras() {
+        assert_eq!(
+            select_camera(&names(), Some(&CameraSelector::parse("Studio Cam"))),
+            Err("No camera matches \"Studio Cam\", available cameras are 0: \"FaceTime HD Camera\", 1: \"Studio Camera\"".to_string())
+        );
+        assert!(select_camera(&names(), Some(&CameraSelector::Index(2)))
+            .unwrap_err()
+            .starts_with("No camera matches index 2,"));
+    }
+
+    #[test]
+    fn no_cameras_is_an_error_even_without_a_selector() {
+        assert_eq!(
+            select_camera(&[], None),
+            Err("No devices found".to_string())
+        );
+    }
+}
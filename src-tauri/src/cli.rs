@@ -0,0 +1,70 @@
+use crate::app::DisplayMode;
+use crate::camera::CameraSelector;
+use clap::Parser;
+use std::path::PathBuf;
+
+/// Command line options, they take precedence over the saved settings for this run only
+#[derive(Debug, Clone, Default, Parser)]
+#[command(version, about)]
+pub struct CliOptions {
+    /// Camera to open, by index or by name
+    #[arg(long, value_parser = parse_camera)]
+    pub camera: Option<CameraSelector>,
+
+    /// Initial display mode: hidden, thumbnail, background, floating or fullscreen[:MONITOR]
+    #[arg(long, value_parser = parse_display_mode)]
+    pub mode: Option<DisplayMode>,
+
+    /// Highest capture resolution to request, e.g. 1920x1080
+    #[arg(long, value_parser = parse_resolution)]
+    pub resolution: Option<(u32, u32)>,
+
+    /// Capture frame rate to request
+    #[arg(long)]
+    pub fps: Option<u32>,
+
+    /// Settings file to use instead of the one in the app config directory
+    #[arg(long)]
+    pub settings: Option<PathBuf>,
+
+    /// Capture and process frames without opening any window
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Recompile shader.wgsl from the source tree when it changes
+    #[cfg(debug_assertions)]
+    #[arg(long)]
+    pub watch_shader: bool,
+}
+
+fn parse_camera(value: &str) -> Result<CameraSelector, String> {
+    Ok(CameraSelector::parse(value))
+}
+
+fn parse_display_mode(value: &str) -> Result<DisplayMode, String> {
+    match value {
+        "hidden" => Ok(DisplayMode::Hidden),
+        "thumbnail" => Ok(DisplayMode::Thumbnail),
+        "background" => Ok(DisplayMode::Background),
+        "floating" => Ok(DisplayMode::Floating),
+        "fullscreen" => Ok(DisplayMode::Fullscreen { monitor: 0 }),
+        _ => {
+            let monitor = value
+                .strip_prefix("fullscreen:")
+                .ok_or_else(|| format!("Unknown display mode \"{value}\""))?;
+            monitor
+                .parse()
+                .map(|monitor| DisplayMode::Fullscreen { monitor })
+                .map_err(|_| format!("Invalid monitor index \"{monitor}\""))
+        }
+    }
+}
+
+fn parse_resolution(value: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("Invalid resolution \"{value}\", expected WIDTHxHEIGHT");
+    let (width, height) = value.split_once(['x', 'X']).ok_or_else(invalid)?;
+    match (width.parse(), height.parse()) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(invalid()),
+    }
+}
@@ -1,85 +1,442 @@
-use crate::app;
-use crate::webgpu;
-use crate::windows_management;
+use crate::app::{self, DisplayMode};
+use crate::blur::BlurSettings;
+use crate::camera::CameraSelector;
+use crate::compositor::{CameraSourceInfo, CompositeLayout};
+use crate::effects::{self, EffectInfo, EffectSource, EFFECT_PARAM_COUNT};
+use crate::overlays::TextOverlay;
+use crate::preview_server::{PreviewServerSettings, PreviewServerStatus};
+use crate::processing::ProcessorInfo;
+use crate::replay::{self, FrozenFrameInfo, ReplayInfo, ReplaySettings};
+use crate::settings::{self, Settings, SettingsStore};
+use crate::stats::PipelineStats;
+use crate::timelapse::{TimelapseSettings, TimelapseStatus};
+use crate::watermark::WatermarkSettings;
+use crate::webgpu::{PresentModeSetting, RenderingSettings, WgpuState};
+use crate::windows_management::{self, MonitorInfo};
+use chrono::Local;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use tauri::{Manager, State};
-use webgpu::WgpuState;
+use tauri::{Manager, PhysicalSize, State};
+use tracing::info;
 
 #[tauri::command]
-pub fn toggle_camera_mode(
+pub async fn set_camera_mode(
+    app_handle: tauri::AppHandle,
+    mode: DisplayMode,
+) -> Result<DisplayMode, String> {
+    windows_management::switch_display_mode(&app_handle, mode).await
+}
+
+#[tauri::command]
+pub fn get_camera_mode(app_state: State<'_, Arc<app::AppState>>) -> DisplayMode {
+    app_state.display_mode()
+}
+
+#[tauri::command]
+pub fn get_monitors(app_handle: tauri::AppHandle) -> Result<Vec<MonitorInfo>, String> {
+    windows_management::list_monitors(&app_handle)
+}
+
+#[tauri::command]
+pub fn set_overlay_interactive(
     app_handle: tauri::AppHandle,
     app_state: State<'_, Arc<app::AppState>>,
-    wgpu_state: State<'_, Arc<WgpuState>>,
+    interactive: bool,
 ) -> bool {
-    let current_mode = app_state.is_background_mode.load(Ordering::SeqCst);
-    let new_mode_is_background = !current_mode;
     app_state
-        .is_background_mode
-        .store(new_mode_is_background, Ordering::SeqCst);
-
-    // Pause rendering during surface switch to avoid race conditions
-    app_state.render_paused.store(true, Ordering::SeqCst);
-    std::thread::sleep(std::time::Duration::from_millis(10));
-    // In background mode, the main window needs to be transparent
-    // In thumbnail mode, the main window needs to be opaque
-    windows_management::set_main_window_background_transparency(
-        &app_handle,
-        new_mode_is_background,
-    );
-
-    if new_mode_is_background {
-        // 1 - Destroy overlay window
-        // 2 - Switch wgpu surface
-        // 3 - Restore focus to main window
-        if let Some(overlay_window) =
-            app_handle.get_window(windows_management::CAMERA_OVERLAY_WINDOW_LABEL)
-        {
-            let _ = overlay_window.close();
-        }
-
-        // Switch to main window and restore focus
-        if let Some(main_window) = app_handle.get_window("main") {
-            wgpu_state.switch_surface(main_window.clone());
-            // This covers a weird edge case on MacOs where the Metal layer is not moved to the back
-            // after the very first thumbnail-> background mode switch
-            #[cfg(target_os = "macos")]
-            windows_management::move_metal_layer_to_back(&app_handle);
-
-            // Restore focus to main window after closing overlay
-            let _ = main_window.set_focus();
-        }
-    } else {
-        // Thumbnail mode
-        // 1 - Clear main window surface
-        // 2 - Create overlay window
-        // 3 - Sync position and size of overlay window with main window
-        // 4 - Switch wgpu surface to overlay window
-        // 5 - Restore focus to main window
-        if let Some(main_window) = app_handle.get_window("main") {
-            windows_management::clear_main_window_surface(&wgpu_state);
-            let overlay_window =
-                windows_management::create_overlay_window(&app_handle, &main_window);
-            if let Some(main_webview_window) = app_handle.get_webview_window("main") {
-                windows_management::adjust_overlay_geometry(
-                    &main_webview_window,
-                    &overlay_window,
-                    &wgpu_state,
-                );
-            }
-
-            wgpu_state.switch_surface(overlay_window);
-
-            let _ = main_window.set_focus();
-        }
+        .overlay_interactive
+        .store(interactive, Ordering::SeqCst);
+
+    if let Some(overlay_window) =
+        app_handle.get_window(windows_management::CAMERA_OVERLAY_WINDOW_LABEL)
+    {
+        windows_management::set_overlay_interactivity(&overlay_window, interactive);
     }
 
-    app_state.render_paused.store(false, Ordering::SeqCst);
+    interactive
+}
+
+#[tauri::command]
+pub fn reset_overlay_layout(
+    app_handle: tauri::AppHandle,
+    app_state: State<'_, Arc<app::AppState>>,
+) {
+    let layout = windows_management::OverlayLayout::default();
+    *app_state.overlay_layout.lock().unwrap() = layout;
+    windows_management::save_overlay_layout(&app_handle, &layout);
+    windows_management::refit_overlay(&app_handle);
+}
+
+#[tauri::command]
+pub async fn add_preview_window(app_handle: tauri::AppHandle, name: String) -> Result<(), String> {
+    windows_management::add_preview_window(&app_handle, &name).await
+}
+
+#[tauri::command]
+pub async fn add_offscreen_target(
+    wgpu_state: State<'_, Arc<WgpuState>>,
+    name: String,
+    width: u32,
+    height: u32,
+) -> Result<(), String> {
+    wgpu_state
+        .add_offscreen_target(&name, PhysicalSize::new(width, height))
+        .await
+}
+
+#[tauri::command]
+pub async fn remove_render_target(
+    app_handle: tauri::AppHandle,
+    name: String,
+) -> Result<(), String> {
+    windows_management::remove_render_target(&app_handle, &name).await
+}
+
+#[tauri::command]
+pub fn list_render_targets(wgpu_state: State<'_, Arc<WgpuState>>) -> Vec<String> {
+    wgpu_state.render_target_names()
+}
+
+#[tauri::command]
+pub fn get_rendering_settings(wgpu_state: State<'_, Arc<WgpuState>>) -> RenderingSettings {
+    *wgpu_state.rendering_settings.read().unwrap()
+}
+
+/// Present modes `set_rendering_settings` accepts with the current render targets
+#[tauri::command]
+pub fn get_supported_present_modes(
+    wgpu_state: State<'_, Arc<WgpuState>>,
+) -> Vec<PresentModeSetting> {
+    wgpu_state.supported_present_modes()
+}
+
+#[tauri::command]
+pub fn set_rendering_settings(
+    app_handle: tauri::AppHandle,
+    wgpu_state: State<'_, Arc<WgpuState>>,
+    settings: RenderingSettings,
+) -> Result<RenderingSettings, String> {
+    wgpu_state.check_present_mode(settings.present_mode)?;
+    wgpu_state.set_rendering_settings(settings)?;
+    settings::persist(&app_handle, |saved| saved.rendering = settings);
+    Ok(settings)
+}
+
+#[tauri::command]
+pub fn get_pipeline_stats(app_state: State<'_, Arc<app::AppState>>) -> PipelineStats {
+    app_state.pipeline_stats.lock().unwrap().snapshot()
+}
+
+#[tauri::command]
+pub fn set_hud_visible(
+    app_handle: tauri::AppHandle,
+    wgpu_state: State<'_, Arc<WgpuState>>,
+    visible: bool,
+) -> bool {
+    wgpu_state.hud_visible.store(visible, Ordering::Relaxed);
+    settings::persist(&app_handle, |settings| settings.hud_visible = visible);
+    visible
+}
+
+#[tauri::command]
+pub fn get_settings(settings_store: State<'_, Arc<SettingsStore>>) -> Settings {
+    settings_store.get()
+}
+
+/// Validates and applies `settings` to the running app, then saves them
+#[tauri::command]
+pub async fn update_settings(
+    app_handle: tauri::AppHandle,
+    settings: Settings,
+) -> Result<Settings, String> {
+    let settings_store = app_handle.state::<Arc<SettingsStore>>().inner().clone();
+    settings_store
+        .replace(settings, |settings| async move {
+            settings::apply(&app_handle, &settings).await
+        })
+        .await
+}
+
+#[tauri::command]
+pub fn get_frame_processors(app_state: State<'_, Arc<app::AppState>>) -> Vec<ProcessorInfo> {
+    app_state.processors.lock().unwrap().infos()
+}
+
+#[tauri::command]
+pub fn set_frame_processor_enabled(
+    app_state: State<'_, Arc<app::AppState>>,
+    name: String,
+    enabled: bool,
+) -> Result<Vec<ProcessorInfo>, String> {
+    let mut processors = app_state.processors.lock().unwrap();
+    processors.set_enabled(&name, enabled)?;
+    Ok(processors.infos())
+}
+
+/// `order` lists every processor name, first to run first
+#[tauri::command]
+pub fn reorder_frame_processors(
+    app_state: State<'_, Arc<app::AppState>>,
+    order: Vec<String>,
+) -> Result<Vec<ProcessorInfo>, String> {
+    let mut processors = app_state.processors.lock().unwrap();
+    processors.reorder(&order)?;
+    Ok(processors.infos())
+}
+
+#[tauri::command]
+pub fn get_effects(wgpu_state: State<'_, Arc<WgpuState>>) -> Vec<EffectInfo> {
+    wgpu_state.gpu().effects.infos()
+}
+
+/// Compiles the WGSL fragment shader at `path` and adds it to the end of the effect chain,
+/// or replaces the effect called `name`. Compilation errors are returned with their line numbers
+#[tauri::command]
+pub async fn load_effect(
+    wgpu_state: State<'_, Arc<WgpuState>>,
+    name: String,
+    path: PathBuf,
+) -> Result<EffectInfo, String> {
+    let source = std::fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+    let gpu = wgpu_state.gpu();
+    gpu.effects
+        .load(
+            &gpu.device,
+            EffectSource {
+                name: name.clone(),
+                path: Some(path),
+                source,
+                enabled: true,
+                params: [0.0; EFFECT_PARAM_COUNT],
+            },
+        )
+        .await
+        .map_err(|diagnostics| effects::format_diagnostics(&name, &diagnostics))
+}
+
+#[tauri::command]
+pub fn remove_effect(wgpu_state: State<'_, Arc<WgpuState>>, name: String) -> Result<(), String> {
+    wgpu_state.gpu().effects.remove(&name)
+}
+
+#[tauri::command]
+pub fn set_effect_enabled(
+    wgpu_state: State<'_, Arc<WgpuState>>,
+    name: String,
+    enabled: bool,
+) -> Result<EffectInfo, String> {
+    wgpu_state.gpu().effects.set_enabled(&name, enabled)
+}
+
+#[tauri::command]
+pub fn set_effect_params(
+    wgpu_state: State<'_, Arc<WgpuState>>,
+    name: String,
+    params: Vec<f32>,
+) -> Result<EffectInfo, String> {
+    wgpu_state.gpu().effects.set_params(&name, &params)
+}
+
+#[tauri::command]
+pub fn get_blur_settings(wgpu_state: State<'_, Arc<WgpuState>>) -> BlurSettings {
+    wgpu_state.blur_settings.read().unwrap().clone()
+}
+
+/// Radius, strength and mask of the background blur, an image mask is loaded from its path
+#[tauri::command]
+pub fn set_blur_settings(
+    wgpu_state: State<'_, Arc<WgpuState>>,
+    settings: BlurSettings,
+) -> Result<BlurSettings, String> {
+    wgpu_state.set_blur_settings(settings.clone())?;
+    Ok(settings)
+}
+
+#[tauri::command]
+pub fn get_overlays(wgpu_state: State<'_, Arc<WgpuState>>) -> Vec<TextOverlay> {
+    wgpu_state.overlays.read().unwrap().clone()
+}
+
+/// Adds a text overlay, or updates the one with the same id
+#[tauri::command]
+pub fn set_overlay(
+    wgpu_state: State<'_, Arc<WgpuState>>,
+    overlay: TextOverlay,
+) -> Result<Vec<TextOverlay>, String> {
+    wgpu_state.set_overlay(overlay)?;
+    Ok(wgpu_state.overlays.read().unwrap().clone())
+}
+
+#[tauri::command]
+pub fn remove_overlay(
+    wgpu_state: State<'_, Arc<WgpuState>>,
+    id: String,
+) -> Result<Vec<TextOverlay>, String> {
+    wgpu_state.remove_overlay(&id)?;
+    Ok(wgpu_state.overlays.read().unwrap().clone())
+}
+
+#[tauri::command]
+pub fn get_watermark(wgpu_state: State<'_, Arc<WgpuState>>) -> WatermarkSettings {
+    wgpu_state.watermark.read().unwrap().clone()
+}
+
+/// Image, placement and opacity of the watermark drawn into every frame, saved for the next launch
+#[tauri::command]
+pub fn set_watermark(
+    app_handle: tauri::AppHandle,
+    wgpu_state: State<'_, Arc<WgpuState>>,
+    settings: WatermarkSettings,
+) -> Result<WatermarkSettings, String> {
+    wgpu_state.set_watermark(settings.clone())?;
+    let saved = settings.clone();
+    settings::persist(&app_handle, |current| current.watermark = saved);
+    Ok(settings)
+}
 
-    new_mode_is_background
+#[tauri::command]
+pub fn get_camera_sources(app_state: State<'_, Arc<app::AppState>>) -> Vec<CameraSourceInfo> {
+    app_state.compositor.infos()
+}
+
+/// Starts capturing an additional camera, by index or by name, with the saved camera preferences
+#[tauri::command]
+pub async fn add_camera_source(
+    app_handle: tauri::AppHandle,
+    camera: String,
+) -> Result<CameraSourceInfo, String> {
+    let preferences = app_handle.state::<Arc<SettingsStore>>().get().camera;
+    let app_state = app_handle.state::<Arc<app::AppState>>().inner().clone();
+    app_state
+        .compositor
+        .add_source(CameraSelector::parse(&camera), &preferences)
+        .await
+}
+
+#[tauri::command]
+pub fn remove_camera_source(
+    app_state: State<'_, Arc<app::AppState>>,
+    id: u32,
+) -> Result<Vec<CameraSourceInfo>, String> {
+    app_state.compositor.remove_source(id)?;
+    Ok(app_state.compositor.infos())
 }
 
 #[tauri::command]
-pub fn get_camera_mode(app_state: State<'_, Arc<app::AppState>>) -> bool {
-    app_state.is_background_mode.load(Ordering::SeqCst)
+pub fn get_composite_layout(app_state: State<'_, Arc<app::AppState>>) -> CompositeLayout {
+    *app_state.compositor.layout.read().unwrap()
+}
+
+#[tauri::command]
+pub fn set_composite_layout(
+    app_state: State<'_, Arc<app::AppState>>,
+    layout: CompositeLayout,
+) -> CompositeLayout {
+    *app_state.compositor.layout.write().unwrap() = layout;
+    layout
+}
+
+#[tauri::command]
+pub fn get_replay_info(app_state: State<'_, Arc<app::AppState>>) -> ReplayInfo {
+    app_state.replay.info()
+}
+
+/// Turns instant replay on or off, and sets how many seconds of frames it keeps and the
+/// memory they may take
+#[tauri::command]
+pub fn set_replay_settings(
+    app_state: State<'_, Arc<app::AppState>>,
+    settings: ReplaySettings,
+) -> Result<ReplayInfo, String> {
+    app_state.replay.set_settings(settings)?;
+    Ok(app_state.replay.info())
+}
+
+/// Writes the buffered frames as a Y4M clip to `path`, or to the videos directory.
+/// Returns where the clip was saved
+#[tauri::command]
+pub async fn save_replay(
+    app_handle: tauri::AppHandle,
+    path: Option<PathBuf>,
+) -> Result<PathBuf, String> {
+    let path = match path {
+        Some(path) => path,
+        None => app_handle
+            .path()
+            .video_dir()
+            .map_err(|e| format!("No videos directory: {e}"))?
+            .join(format!(
+                "replay-{}.y4m",
+                Local::now().format("%Y%m%d-%H%M%S")
+            )),
+    };
+    let frames = app_handle.state::<Arc<app::AppState>>().replay.frames();
+
+    tauri::async_runtime::spawn_blocking(move || {
+        let file =
+            File::create(&path).map_err(|e| format!("Failed to create {}: {e}", path.display()))?;
+        let mut out = BufWriter::new(file);
+        let written = replay::write_y4m(&frames, &mut out)
+            .and_then(|written| out.flush().map(|()| written))
+            .map_err(|e| format!("Failed to write {}: {e}", path.display()))?;
+        info!("Saved {} replay frames to {}", written, path.display());
+        Ok(path)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Pauses the display on a buffered frame, the newest by default. Calling it again while
+/// frozen scrubs to another frame of the same buffer
+#[tauri::command]
+pub fn freeze_frame(
+    app_state: State<'_, Arc<app::AppState>>,
+    index: Option<usize>,
+) -> Result<FrozenFrameInfo, String> {
+    app_state.replay.freeze(index)
+}
+
+#[tauri::command]
+pub fn resume_live(app_state: State<'_, Arc<app::AppState>>) {
+    app_state.replay.resume();
+}
+
+/// Starts saving a snapshot of the processed frames at every interval
+#[tauri::command]
+pub fn start_timelapse(
+    app_state: State<'_, Arc<app::AppState>>,
+    settings: TimelapseSettings,
+) -> Result<TimelapseStatus, String> {
+    app_state.timelapse.start(settings)
+}
+
+#[tauri::command]
+pub fn stop_timelapse(app_state: State<'_, Arc<app::AppState>>) -> TimelapseStatus {
+    app_state.timelapse.stop()
+}
+
+#[tauri::command]
+pub fn get_timelapse_status(app_state: State<'_, Arc<app::AppState>>) -> TimelapseStatus {
+    app_state.timelapse.status()
+}
+
+#[tauri::command]
+pub fn get_preview_server(app_state: State<'_, Arc<app::AppState>>) -> PreviewServerStatus {
+    app_state.preview_server.status()
+}
+
+/// Starts, reconfigures or stops the MJPEG preview server, saved for the next launch
+#[tauri::command]
+pub fn set_preview_server(
+    app_handle: tauri::AppHandle,
+    app_state: State<'_, Arc<app::AppState>>,
+    settings: PreviewServerSettings,
+) -> Result<PreviewServerStatus, String> {
+    let status = app_state.preview_server.apply(settings)?;
+    settings::persist(&app_handle, |current| current.preview_server = settings);
+    Ok(status)
 }
@@ -0,0 +1,362 @@
+use crate::camera::{self, CameraSelector};
+use crate::events::FrameSize;
+use crate::settings::CameraPreferences;
+use crate::webgpu::CameraSettingsUniform;
+use nokhwa::utils::Resolution;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use tracing::{error, info};
+
+/// Width of a picture-in-picture inset, relative to the target width
+const PIP_SIZE: f32 = 0.3;
+/// Space between insets and the edges of the target, in NDC
+const PIP_MARGIN: f32 = 0.05;
+
+/// How the primary camera and the additional sources share a render target
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CompositeLayout {
+    /// Only the primary camera
+    #[default]
+    Single,
+    /// Every camera in a row of equal columns
+    SideBySide,
+    /// The primary camera fills the target, the others are insets in a row along the bottom right
+    PictureInPicture,
+    /// Every camera in a grid as square as possible
+    Grid,
+}
+
+/// Quad placement of each camera on a target of `target_aspect`, in the order of
+/// `camera_aspects`, the primary camera first. Later quads are drawn on top
+pub fn layout(
+    layout: CompositeLayout,
+    camera_aspects: &[f32],
+    target_aspect: f32,
+) -> Vec<CameraSettingsUniform> {
+    let count = camera_aspects.len();
+    match layout {
+        CompositeLayout::Single => camera_aspects
+            .iter()
+            .take(1)
+            .map(|&aspect| CameraSettingsUniform::fit(aspect, target_aspect))
+            .collect(),
+        CompositeLayout::SideBySide => grid(camera_aspects, target_aspect, count.max(1), 1),
+        CompositeLayout::Grid => {
+            let columns = (count as f32).sqrt().ceil().max(1.0) as usize;
+            let rows = count.div_ceil(columns).max(1);
+            grid(camera_aspects, target_aspect, columns, rows)
+        }
+        CompositeLayout::PictureInPicture => {
+            let mut quads = Vec::with_capacity(count);
+            let mut insets = camera_aspects.iter();
+            if let Some(&primary) = insets.next() {
+                quads.push(CameraSettingsUniform::fit(primary, target_aspect));
+            }
+            // Insets are lined up from the right edge towards the left, and shrink once a row
+            // of full size ones doesn't fit
+            let inset_count = insets.len().max(1) as f32;
+            let width =
+                (2.0 * PIP_SIZE).min((2.0 - PIP_MARGIN * (inset_count + 1.0)) / inset_count);
+            let mut right = 1.0 - PIP_MARGIN;
+            for &aspect in insets {
+                let height = width * target_aspect / aspect;
+                quads.push(CameraSettingsUniform {
+                    position: [right - width / 2.0, -1.0 + PIP_MARGIN + height / 2.0],
+                    size: [width, height],
+                });
+                right -= width + PIP_MARGIN;
+            }
+            quads
+        }
+    }
+}
+
+/// Fits each camera into the cells of a `columns` x `rows` grid, filled row by row
+fn grid(
+    camera_aspects: &[f32],
+    target_aspect: f32,
+    columns: usize,
+    rows: usize,
+) -> Vec<CameraSettingsUniform> {
+    let cell_width = 2.0 / columns as f32;
+    let cell_height = 2.0 / rows as f32;
+    let cell_aspect = target_aspect * cell_width / cell_height;
+    camera_aspects
+        .iter()
+        .enumerate()
+        .map(|(index, &aspect)| {
+            let (column, row) = (index % columns, index / columns);
+            // Sizes from `fit` are relative to the whole target, which spans 2 in NDC
+            let [width, height] = CameraSettingsUniform::fit(aspect, cell_aspect).size;
+            CameraSettingsUniform {
+                position: [
+                    -1.0 + cell_width * (column as f32 + 0.5),
+                    1.0 - cell_height * (row as f32 + 0.5),
+                ],
+                size: [width * cell_width / 2.0, height * cell_height / 2.0],
+            }
+        })
+        .collect()
+}
+
+/// Latest decoded frame of a source, replaced as new ones arrive
+pub struct SourceFrame {
+    pub pixels: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    /// Incremented with every frame, so the render loop only uploads new ones
+    pub sequence: u64,
+}
+
+/// Camera captured alongside the primary one, on its own worker thread
+pub struct CameraSource {
+    pub id: u32,
+    pub name: String,
+    frame: Mutex<Option<Arc<SourceFrame>>>,
+    /// Set when the source is removed, the worker stops at its next frame
+    stop: AtomicBool,
+    running: AtomicBool,
+    error: Mutex<Option<String>>,
+}
+
+impl CameraSource {
+    pub fn frame(&self) -> Option<Arc<SourceFrame>> {
+        self.frame.lock().unwrap().clone()
+    }
+
+    pub fn info(&self) -> CameraSourceInfo {
+        let resolution = self.frame().map(|frame| FrameSize {
+            width: frame.width,
+            height: frame.height,
+        });
+        CameraSourceInfo {
+            id: self.id,
+            name: self.name.clone(),
+            resolution,
+            running: self.running.load(Ordering::Relaxed),
+            error: self.error.lock().unwrap().clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CameraSourceInfo {
+    pub id: u32,
+    pub name: String,
+    /// Unknown until the first frame
+    pub resolution: Option<FrameSize>,
+    pub running: bool,
+    /// Why the capture stopped, if it failed
+    pub error: Option<String>,
+}
+
+/// Additional camera sources and the layout they are composited with. The render loop
+/// is paced by the primary camera and draws the latest frame of every other source
+#[derive(Default)]
+pub struct Compositor {
+    sources: Mutex<Vec<Arc<CameraSource>>>,
+    next_id: AtomicU32,
+    pub layout: RwLock<CompositeLayout>,
+}
+
+impl Compositor {
+    pub fn sources(&self) -> Vec<Arc<CameraSource>> {
+        self.sources.lock().unwrap().clone()
+    }
+
+    pub fn infos(&self) -> Vec<CameraSourceInfo> {
+        self.sources().iter().map(|source| source.info()).collect()
+    }
+
+    /// Opens the camera matching `selector` on a new capture thread, returning once it is
+    /// open or failed to
+    pub async fn add_source(
+        &self,
+        selector: CameraSelector,
+        preferences: &CameraPreferences,
+    ) -> Result<CameraSourceInfo, String> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let preferences = preferences.clone();
+        // The camera stays on the thread that opened it
+        let (opened_tx, opened_rx) = flume::bounded(1);
+        std::thread::Builder::new()
+            .name(format!("camera-source-{id}"))
+            .spawn(move || {
+                let camera = camera::create_camera(
+                    Some(&selector),
+                    Resolution::new(preferences.max_width, preferences.max_height),
+                    preferences.frame_rate,
+                );
+                let camera = match camera {
+                    Ok(camera) => camera,
+                    Err(e) => {
+                        let _ = opened_tx.send(Err(e));
+                        return;
+                    }
+                };
+                let source = Arc::new(CameraSource {
+                    id,
+                    name: camera.info().human_name(),
+                    frame: Mutex::new(None),
+                    stop: AtomicBool::new(false),
+                    running: AtomicBool::new(true),
+                    error: Mutex::new(None),
+                });
+                let _ = opened_tx.send(Ok(source.clone()));
+
+                if let Err(e) = run_source(camera, &source) {
+                    error!("Camera source {} failed: {}", source.id, e);
+                    *source.error.lock().unwrap() = Some(e);
+                }
+                source.running.store(false, Ordering::Relaxed);
+            })
+            .map_err(|e| format!("Could not start the capture thread: {e}"))?;
+
+        let source = opened_rx
+            .recv_async()
+            .await
+            .map_err(|_| "The capture thread exited without opening the camera".to_string())??;
+        info!("Added camera source {} ({})", source.id, source.name);
+        self.sources.lock().unwrap().push(source.clone());
+        Ok(source.info())
+    }
+
+    pub fn remove_source(&self, id: u32) -> Result<(), String> {
+        let mut sources = self.sources.lock().unwrap();
+        let index = sources
+            .iter()
+            .position(|source| source.id == id)
+            .ok_or_else(|| format!("No camera source with id {id}"))?;
+        sources.remove(index).stop.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// Decodes frames from `camera` into `source` until it is removed or the camera fails
+fn run_source(mut camera: nokhwa::Camera, source: &CameraSource) -> Result<(), String> {
+    camera
+        .open_stream()
+        .map_err(|e| format!("Could not open stream: {e}"))?;
+
+    let mut result = Ok(());
+    let mut sequence = 0;
+    while !source.stop.load(Ordering::Relaxed) {
+        let buffer = match camera.frame() {
+            Ok(buffer) => buffer,
+            Err(e) => {
+                result = Err(format!("Could not get frame: {e}"));
+                break;
+            }
+        };
+        let width = buffer.resolution().width();
+        let height = buffer.resolution().height();
+        sequence += 1;
+        let frame = SourceFrame {
+            pixels: camera::yuyv_to_rgba(buffer.buffer(), width as usize, height as usize),
+            width,
+            height,
+            sequence,
+        };
+        *source.frame.lock().unwrap() = Some(Arc::new(frame));
+    }
+
+    result.and(
+        camera
+            .stop_stream()
+            .map_err(|e| format!("Could not stop stream: {e}")),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDE: f32 = 16.0 / 9.0;
+
+    fn assert_close(a: [f32; 2], b: [f32; 2]) {
+        assert!(
+            (a[0] - b[0]).abs() < 1e-5 && (a[1] - b[1]).abs() < 1e-5,
+            "{a:?} != {b:?}"
+        );
+    }
+
+    #[test]
+    fn single_draws_only_the_primary_camera() {
+        let quads = layout(CompositeLayout::Single, &[WIDE, WIDE], WIDE);
+
+        assert_eq!(quads.len(), 1);
+        assert_close(quads[0].position, [0.0, 0.0]);
+        assert_close(quads[0].size, [2.0, 2.0]);
+    }
+
+    #[test]
+    fn side_by_side_splits_the_width() {
+        let quads = layout(CompositeLayout::SideBySide, &[WIDE, WIDE], 2.0 * WIDE);
+
+        assert_close(quads[0].position, [-0.5, 0.0]);
+        assert_close(quads[1].position, [0.5, 0.0]);
+        for quad in &quads {
+            assert_close(quad.size, [1.0, 2.0]);
+        }
+    }
+
+    #[test]
+    fn grid_is_filled_row_by_row() {
+        let quads = layout(CompositeLayout::Grid, &[WIDE; 3], WIDE);
+
+        assert_eq!(quads.len(), 3);
+        assert_close(quads[0].position, [-0.5, 0.5]);
+        assert_close(quads[1].position, [0.5, 0.5]);
+        assert_close(quads[2].position, [-0.5, -0.5]);
+        assert_close(quads[2].size, [1.0, 1.0]);
+    }
+
+    #[test]
+    fn cells_keep_the_camera_aspect() {
+        // A 4:3 camera in a 16:9 half is letterboxed within its column
+        let quads = layout(CompositeLayout::SideBySide, &[4.0 / 3.0, WIDE], 2.0 * WIDE);
+
+        let [width, height] = quads[0].size;
+        assert!((width / height * 2.0 * WIDE - 4.0 / 3.0).abs() < 1e-5);
+        assert!(height <= 2.0 && width <= 1.0);
+    }
+
+    #[test]
+    fn insets_sit_over_the_primary_camera() {
+        let quads = layout(CompositeLayout::PictureInPicture, &[WIDE; 3], WIDE);
+
+        assert_close(quads[0].size, [2.0, 2.0]);
+        for inset in &quads[1..] {
+            let [x, y] = inset.position;
+            let [width, height] = inset.size;
+            assert!(x + width / 2.0 <= 1.0 && x - width / 2.0 >= -1.0);
+            assert!(y - height / 2.0 >= -1.0 && y < 0.0);
+        }
+        // Lined up without overlapping
+        assert!(
+            quads[2].position[0] + quads[2].size[0] / 2.0
+                < quads[1].position[0] - quads[1].size[0] / 2.0
+        );
+    }
+
+    #[test]
+    fn many_insets_shrink_to_fit_the_row() {
+        let quads = layout(CompositeLayout::PictureInPicture, &[WIDE; 7], WIDE);
+
+        assert_eq!(quads.len(), 7);
+        let widths: Vec<f32> = quads[1..].iter().map(|inset| inset.size[0]).collect();
+        assert!(widths.iter().all(|&width| width < 2.0 * PIP_SIZE));
+        assert!(widths.windows(2).all(|pair| pair[0] == pair[1]));
+        let leftmost = &quads[6];
+        assert!(leftmost.position[0] - leftmost.size[0] / 2.0 >= -1.0 + PIP_MARGIN - 1e-5);
+        for pair in quads[1..].windows(2) {
+            assert!(
+                pair[1].position[0] + pair[1].size[0] / 2.0
+                    < pair[0].position[0] - pair[0].size[0] / 2.0
+            );
+        }
+    }
+}
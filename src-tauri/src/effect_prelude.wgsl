@@ -0,0 +1,34 @@
+// Prepended to every effect shader, effects only provide `fs_main`
+struct EffectGlobals {
+    // Size of the camera frame in pixels
+    resolution: vec2<f32>,
+    // Seconds since the effect chain was created
+    time: f32,
+    // Frames processed since the effect chain was created
+    frame: u32,
+};
+
+struct EffectParams {
+    // User uniforms, set with the `set_effect_params` command
+    values: array<vec4<f32>, 4>,
+};
+
+@group(0) @binding(0) var input_texture: texture_2d<f32>;
+@group(0) @binding(1) var input_sampler: sampler;
+@group(0) @binding(2) var<uniform> globals: EffectGlobals;
+@group(0) @binding(3) var<uniform> params: EffectParams;
+
+struct EffectVertex {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+// Single triangle covering the whole frame
+@vertex
+fn effect_vs(@builtin(vertex_index) in_vertex_index: u32) -> EffectVertex {
+    let uv = vec2<f32>(f32((in_vertex_index << 1u) & 2u), f32(in_vertex_index & 2u));
+    var out: EffectVertex;
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
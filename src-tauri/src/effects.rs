@@ -0,0 +1,552 @@
+use crate::webgpu::OFFSCREEN_FORMAT;
+use serde::Serialize;
+use std::path::PathBuf;
+use std::sync::{Mutex, RwLock};
+use std::time::Instant;
+use tracing::{info, warn};
+
+/// Bindings and vertex stage shared by every effect, see `effect_prelude.wgsl`
+const EFFECT_PRELUDE: &str = include_str!("effect_prelude.wgsl");
+
+/// Number of `f32` user uniforms an effect can read from `params.values`
+pub const EFFECT_PARAM_COUNT: usize = 16;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct EffectGlobalsUniform {
+    resolution: [f32; 2],
+    time: f32,
+    frame: u32,
+}
+
+/// Compiler message, with the position in the effect source rather than in the prelude
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShaderDiagnostic {
+    /// 1-based, absent when the message is not tied to the effect source
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    pub message: String,
+    pub is_error: bool,
+}
+
+impl std::fmt::Display for ShaderDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(f, "{line}:{column}: {}", self.message),
+            (Some(line), None) => write!(f, "{line}: {}", self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Joins the diagnostics of a failed compilation into one error message
+pub fn format_diagnostics(name: &str, diagnostics: &[ShaderDiagnostic]) -> String {
+    let messages: Vec<String> = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.is_error)
+        .map(|diagnostic| diagnostic.to_string())
+        .collect();
+    format!(
+        "Effect \"{name}\" failed to compile:\n{}",
+        messages.join("\n")
+    )
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EffectInfo {
+    pub name: String,
+    pub path: Option<PathBuf>,
+    pub enabled: bool,
+    pub params: [f32; EFFECT_PARAM_COUNT],
+    /// Messages from the last compilation, the effect keeps running its previous
+    /// shader if that compilation failed
+    pub diagnostics: Vec<ShaderDiagnostic>,
+}
+
+/// What is needed to rebuild an effect on another device
+#[derive(Debug, Clone)]
+pub struct EffectSource {
+    pub name: String,
+    pub path: Option<PathBuf>,
+    pub source: String,
+    pub enabled: bool,
+    pub params: [f32; EFFECT_PARAM_COUNT],
+}
+
+struct EffectPass {
+    source: EffectSource,
+    pipeline: wgpu::RenderPipeline,
+    params_buffer: wgpu::Buffer,
+    diagnostics: Vec<ShaderDiagnostic>,
+}
+
+impl EffectPass {
+    fn info(&self) -> EffectInfo {
+        EffectInfo {
+            name: self.source.name.clone(),
+            path: self.source.path.clone(),
+            enabled: self.source.enabled,
+            params: self.source.params,
+            diagnostics: self.diagnostics.clone(),
+        }
+    }
+}
+
+/// Post-processing passes run on the camera frame before it is drawn to the render targets.
+/// Each pass reads the previous output and renders into one of two ping-pong textures
+pub struct EffectChain {
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    globals_buffer: wgpu::Buffer,
+    passes: RwLock<Vec<EffectPass>>,
+    /// Sized to the camera frame, recreated when the resolution changes
+    ping_pong: Mutex<Option<[wgpu::Texture; 2]>>,
+    started_at: Instant,
+    frame: Mutex<u32>,
+}
+
+impl EffectChain {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                uniform_entry(2),
+                uniform_entry(3),
+            ],
+            label: Some("effect_bind_group_layout"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("effect_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            immediate_size: 0,
+        });
+
+        let globals_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("effect_globals"),
+            size: std::mem::size_of::<EffectGlobalsUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            bind_group_layout,
+            pipeline_layout,
+            globals_buffer,
+            passes: RwLock::new(Vec::new()),
+            ping_pong: Mutex::new(None),
+            started_at: Instant::now(),
+            frame: Mutex::new(0),
+        }
+    }
+
+    pub fn infos(&self) -> Vec<EffectInfo> {
+        self.passes
+            .read()
+            .unwrap()
+            .iter()
+            .map(EffectPass::info)
+            .collect()
+    }
+
+    /// Everything needed to rebuild the chain with `restore`
+    pub fn sources(&self) -> Vec<EffectSource> {
+        self.passes
+            .read()
+            .unwrap()
+            .iter()
+            .map(|pass| pass.source.clone())
+            .collect()
+    }
+
+    /// Compiles `source` and adds it at the end of the chain, or replaces the effect with the
+    /// same name in place. On failure the chain is left untouched, apart from recording the
+    /// diagnostics on the existing effect
+    pub async fn load(
+        &self,
+        device: &wgpu::Device,
+        source: EffectSource,
+    ) -> Result<EffectInfo, Vec<ShaderDiagnostic>> {
+        let compiled = self.compile(device, &source.name, &source.source).await;
+
+        let mut passes = self.passes.write().unwrap();
+        let existing = passes
+            .iter()
+            .position(|pass| pass.source.name == source.name);
+        let (pipeline, diagnostics) = match compiled {
+            Ok(compiled) => compiled,
+            Err(diagnostics) => {
+                if let Some(index) = existing {
+                    passes[index].diagnostics = diagnostics.clone();
+                }
+                return Err(diagnostics);
+            }
+        };
+
+        let pass = EffectPass {
+            params_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("effect_params"),
+                size: std::mem::size_of::<[f32; EFFECT_PARAM_COUNT]>() as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            source,
+            pipeline,
+            diagnostics,
+        };
+        let info = pass.info();
+        info!("Loaded effect \"{}\"", info.name);
+        match existing {
+            Some(index) => passes[index] = pass,
+            None => passes.push(pass),
+        }
+        Ok(info)
+    }
+
+    /// Rebuilds effects taken from another chain with `sources`, skipping any that fail
+    pub async fn restore(&self, device: &wgpu::Device, sources: Vec<EffectSource>) {
+        for source in sources {
+            let name = source.name.clone();
+            if let Err(diagnostics) = self.load(device, source).await {
+                warn!("{}", format_diagnostics(&name, &diagnostics));
+            }
+        }
+    }
+
+    pub fn remove(&self, name: &str) -> Result<(), String> {
+        let mut passes = self.passes.write().unwrap();
+        let index = position(&passes, name)?;
+        passes.remove(index);
+        Ok(())
+    }
+
+    pub fn set_enabled(&self, name: &str, enabled: bool) -> Result<EffectInfo, String> {
+        let mut passes = self.passes.write().unwrap();
+        let index = position(&passes, name)?;
+        passes[index].source.enabled = enabled;
+        Ok(passes[index].info())
+    }
+
+    /// Sets the leading user uniforms of an effect, the rest are zeroed
+    pub fn set_params(&self, name: &str, values: &[f32]) -> Result<EffectInfo, String> {
+        if values.len() > EFFECT_PARAM_COUNT {
+            return Err(format!(
+                "Effects take at most {EFFECT_PARAM_COUNT} parameters, got {}",
+                values.len()
+            ));
+        }
+
+        let mut passes = self.passes.write().unwrap();
+        let index = position(&passes, name)?;
+        let mut params = [0.0; EFFECT_PARAM_COUNT];
+        params[..values.len()].copy_from_slice(values);
+        passes[index].source.params = params;
+        Ok(passes[index].info())
+    }
+
+    /// Records every enabled effect into `encoder`, starting from `input`. Returns the texture
+    /// holding the result, or `None` when no effect is enabled and `input` should be drawn as is
+    pub fn apply(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        sampler: &wgpu::Sampler,
+        encoder: &mut wgpu::CommandEncoder,
+        input: &wgpu::Texture,
+    ) -> Option<wgpu::Texture> {
+        let passes = self.passes.read().unwrap();
+        let enabled: Vec<&EffectPass> = passes.iter().filter(|pass| pass.source.enabled).collect();
+        if enabled.is_empty() {
+            return None;
+        }
+
+        let frame = {
+            let mut frame = self.frame.lock().unwrap();
+            *frame = frame.wrapping_add(1);
+            *frame
+        };
+        queue.write_buffer(
+            &self.globals_buffer,
+            0,
+            bytemuck::bytes_of(&EffectGlobalsUniform {
+                resolution: [input.width() as f32, input.height() as f32],
+                time: self.started_at.elapsed().as_secs_f32(),
+                frame,
+            }),
+        );
+
+        let ping_pong = self.ping_pong_textures(device, input.width(), input.height());
+        let mut source_view = input.create_view(&wgpu::TextureViewDescriptor::default());
+        let mut output = None;
+
+        for (index, pass) in enabled.into_iter().enumerate() {
+            queue.write_buffer(
+                &pass.params_buffer,
+                0,
+                bytemuck::cast_slice(&pass.source.params),
+            );
+
+            let target = &ping_pong[index % 2];
+            let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &self.bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&source_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: self.globals_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: pass.params_buffer.as_entire_binding(),
+                    },
+                ],
+                label: Some("effect_bind_group"),
+            });
+
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("effect_pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &target_view,
+                    depth_slice: None,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+                multiview_mask: None,
+            });
+            rpass.set_pipeline(&pass.pipeline);
+            rpass.set_bind_group(0, &bind_group, &[]);
+            rpass.draw(0..3, 0..1);
+            drop(rpass);
+
+            source_view = target_view;
+            output = Some(target.clone());
+        }
+
+        output
+    }
+
+    fn ping_pong_textures(
+        &self,
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+    ) -> [wgpu::Texture; 2] {
+        let mut ping_pong = self.ping_pong.lock().unwrap();
+        if let Some(textures) = ping_pong.as_ref() {
+            if textures[0].width() == width && textures[0].height() == height {
+                return textures.clone();
+            }
+        }
+
+        let create = || {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("effect_ping_pong"),
+                sample_count: 1,
+                mip_level_count: 1,
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                dimension: wgpu::TextureDimension::D2,
+                format: OFFSCREEN_FORMAT,
+                // Copied out when the processed frame is saved
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            })
+        };
+        let textures = [create(), create()];
+        *ping_pong = Some(textures.clone());
+        textures
+    }
+
+    /// Validation errors are caught in an error scope rather than panicking through the
+    /// uncaptured error handler, and the compiler messages are mapped back to `source`
+    async fn compile(
+        &self,
+        device: &wgpu::Device,
+        name: &str,
+        source: &str,
+    ) -> Result<(wgpu::RenderPipeline, Vec<ShaderDiagnostic>), Vec<ShaderDiagnostic>> {
+        let scope = device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(name),
+            source: wgpu::ShaderSource::Wgsl(format!("{EFFECT_PRELUDE}{source}").into()),
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(name),
+            layout: Some(&self.pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &module,
+                entry_point: Some("effect_vs"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &module,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: OFFSCREEN_FORMAT,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            multisample: wgpu::MultisampleState::default(),
+            depth_stencil: None,
+            multiview_mask: None,
+            cache: None,
+        });
+        let error = scope.pop();
+
+        let mut diagnostics: Vec<ShaderDiagnostic> = module
+            .get_compilation_info()
+            .await
+            .messages
+            .into_iter()
+            .map(diagnostic)
+            .collect();
+
+        match error.await {
+            None => Ok((pipeline, diagnostics)),
+            Some(error) => {
+                // Pipeline errors, such as a missing `fs_main`, have no compiler message
+                if !diagnostics.iter().any(|diagnostic| diagnostic.is_error) {
+                    diagnostics.push(ShaderDiagnostic {
+                        line: None,
+                        column: None,
+                        message: error.to_string(),
+                        is_error: true,
+                    });
+                }
+                Err(diagnostics)
+            }
+        }
+    }
+}
+
+fn position(passes: &[EffectPass], name: &str) -> Result<usize, String> {
+    passes
+        .iter()
+        .position(|pass| pass.source.name == name)
+        .ok_or_else(|| format!("No effect named \"{name}\""))
+}
+
+fn diagnostic(message: wgpu::CompilationMessage) -> ShaderDiagnostic {
+    let prelude_lines = EFFECT_PRELUDE.lines().count() as u32;
+    // Messages pointing into the prelude are reported without a position
+    let location = message
+        .location
+        .filter(|location| location.line_number > prelude_lines);
+    ShaderDiagnostic {
+        line: location.map(|location| location.line_number - prelude_lines),
+        column: location.map(|location| location.line_position),
+        message: message.message,
+        is_error: message.message_type == wgpu::CompilationMessageType::Error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses `source` after the prelude and reports its first error the way wgpu does
+    fn compile_error(source: &str) -> ShaderDiagnostic {
+        let full = format!("{EFFECT_PRELUDE}{source}");
+        let error = wgpu::naga::front::wgsl::parse_str(&full).unwrap_err();
+        diagnostic(wgpu::CompilationMessage {
+            message: error.message().to_string(),
+            message_type: wgpu::CompilationMessageType::Error,
+            location: error.location(&full).map(Into::into),
+        })
+    }
+
+    #[test]
+    fn errors_point_at_the_effect_source() {
+        let diagnostic = compile_error(
+            "@fragment
+fn fs_main(in: EffectVertex) -> @location(0) vec4<f32> {
+    let color = textureSample(input_texture, input_sampler, in.uv)
+    return color;
+}
+",
+        );
+
+        assert_eq!((diagnostic.line, diagnostic.column), (Some(4), Some(5)));
+        assert!(diagnostic.is_error);
+        assert_eq!(
+            format_diagnostics("tint", &[diagnostic]),
+            "Effect \"tint\" failed to compile:\n4:5: expected `;`, found \"return\""
+        );
+    }
+
+    #[test]
+    fn errors_on_the_first_line_are_not_shifted_into_the_prelude() {
+        let diagnostic = compile_error("fn fs_main( {}\n");
+
+        assert_eq!(diagnostic.line, Some(1));
+    }
+
+    #[test]
+    fn prelude_messages_have_no_position() {
+        let diagnostic = diagnostic(wgpu::CompilationMessage {
+            message: "unused variable".to_string(),
+            message_type: wgpu::CompilationMessageType::Warning,
+            location: Some(wgpu::SourceLocation {
+                line_number: 3,
+                line_position: 5,
+                offset: 40,
+                length: 4,
+            }),
+        });
+
+        assert_eq!((diagnostic.line, diagnostic.column), (None, None));
+        assert!(!diagnostic.is_error);
+        assert_eq!(diagnostic.to_string(), "unused variable");
+    }
+}
@@ -0,0 +1,77 @@
+use crate::app::DisplayMode;
+use crate::stats::PipelineStats;
+use serde::Serialize;
+use tauri::{AppHandle, Emitter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct FrameSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CameraStarted {
+    pub name: String,
+    pub resolution: FrameSize,
+    pub frame_rate: u32,
+    pub pixel_format: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorMessage {
+    pub message: String,
+}
+
+/// State changes pushed to every webview, so windows stay in sync without polling
+#[derive(Debug, Clone)]
+pub enum AppEvent {
+    CameraModeChanged(DisplayMode),
+    CameraStarted(CameraStarted),
+    CameraStopped,
+    CameraError(ErrorMessage),
+    ResolutionChanged(FrameSize),
+    StatsUpdated(PipelineStats),
+    RendererRecovered,
+    RendererError(ErrorMessage),
+}
+
+impl AppEvent {
+    pub fn camera_error(message: impl Into<String>) -> Self {
+        Self::CameraError(ErrorMessage {
+            message: message.into(),
+        })
+    }
+
+    pub fn renderer_error(message: impl Into<String>) -> Self {
+        Self::RendererError(ErrorMessage {
+            message: message.into(),
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::CameraModeChanged(_) => "camera-mode-changed",
+            Self::CameraStarted(_) => "camera-started",
+            Self::CameraStopped => "camera-stopped",
+            Self::CameraError(_) => "camera-error",
+            Self::ResolutionChanged(_) => "resolution-changed",
+            Self::StatsUpdated(_) => "stats-updated",
+            Self::RendererRecovered => "renderer-recovered",
+            Self::RendererError(_) => "renderer-error",
+        }
+    }
+
+    /// Sends the event to every webview, a closed webview is not an error
+    pub fn emit(self, app_handle: &AppHandle) {
+        let name = self.name();
+        let _ = match self {
+            Self::CameraModeChanged(mode) => app_handle.emit(name, mode),
+            Self::CameraStarted(camera) => app_handle.emit(name, camera),
+            Self::CameraStopped | Self::RendererRecovered => app_handle.emit(name, ()),
+            Self::CameraError(error) | Self::RendererError(error) => app_handle.emit(name, error),
+            Self::ResolutionChanged(size) => app_handle.emit(name, size),
+            Self::StatsUpdated(stats) => app_handle.emit(name, stats),
+        };
+    }
+}
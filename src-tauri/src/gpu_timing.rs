@@ -0,0 +1,247 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Query slots, the upload and processing are timed between passes and the render passes
+// by their own writes
+const UPLOAD_START: u32 = 0;
+const UPLOAD_END: u32 = 1;
+const PROCESSING_START: u32 = 2;
+const PROCESSING_END: u32 = 3;
+const RENDER_START: u32 = 4;
+const RENDER_END: u32 = 5;
+const QUERY_COUNT: u32 = 6;
+
+const RESOLVE_SIZE: wgpu::BufferAddress =
+    QUERY_COUNT as wgpu::BufferAddress * wgpu::QUERY_SIZE as wgpu::BufferAddress;
+
+/// GPU execution time of one frame
+#[derive(Debug, Clone, Copy)]
+pub struct GpuDurations {
+    /// Only measured when timestamps can be written outside of passes
+    pub upload: Option<Duration>,
+    /// Blur, effects, watermark and burned-in overlays, measured like the upload
+    pub processing: Option<Duration>,
+    /// From the start of the first render pass to the end of the last one
+    pub render: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Readback {
+    Idle,
+    Mapping,
+    Mapped,
+    Failed,
+}
+
+/// Timestamp queries for one frame at a time. While a frame's results are being read back
+/// the following frames are not measured, so timing never stalls the render loop
+pub struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    /// Nanoseconds per timestamp tick
+    period: f32,
+    times_between_passes: bool,
+    readback: Arc<Mutex<Readback>>,
+}
+
+impl GpuTimer {
+    /// Features to request from `adapter` for GPU timing, empty when it can't be supported
+    pub fn features(adapter: &wgpu::Adapter) -> wgpu::Features {
+        let supported = adapter.features();
+        if !supported.contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return wgpu::Features::empty();
+        }
+        supported
+            & (wgpu::Features::TIMESTAMP_QUERY | wgpu::Features::TIMESTAMP_QUERY_INSIDE_ENCODERS)
+    }
+
+    /// Returns `None` if `device` was created without timestamp queries
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("frame_timestamps"),
+            ty: wgpu::QueryType::Timestamp,
+            count: QUERY_COUNT,
+        });
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("frame_timestamps_resolve"),
+            size: RESOLVE_SIZE,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("frame_timestamps_readback"),
+            size: RESOLVE_SIZE,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+            period: queue.get_timestamp_period(),
+            times_between_passes: device
+                .features()
+                .contains(wgpu::Features::TIMESTAMP_QUERY_INSIDE_ENCODERS),
+            readback: Arc::new(Mutex::new(Readback::Idle)),
+        })
+    }
+
+    /// Whether the next frame can be measured
+    pub fn is_idle(&self) -> bool {
+        *self.readback.lock().unwrap() == Readback::Idle
+    }
+
+    pub fn begin_upload(&self, encoder: &mut wgpu::CommandEncoder) {
+        self.write_between_passes(encoder, UPLOAD_START);
+    }
+
+    pub fn end_upload(&self, encoder: &mut wgpu::CommandEncoder) {
+        self.write_between_passes(encoder, UPLOAD_END);
+    }
+
+    pub fn begin_processing(&self, encoder: &mut wgpu::CommandEncoder) {
+        self.write_between_passes(encoder, PROCESSING_START);
+    }
+
+    pub fn end_processing(&self, encoder: &mut wgpu::CommandEncoder) {
+        self.write_between_passes(encoder, PROCESSING_END);
+    }
+
+    fn write_between_passes(&self, encoder: &mut wgpu::CommandEncoder, index: u32) {
+        if self.times_between_passes {
+            encoder.write_timestamp(&self.query_set, index);
+        }
+    }
+
+    /// Timestamp writes for a render pass, only the first pass of the frame records its start
+    pub fn render_pass_writes(&self, first_pass: bool) -> wgpu::RenderPassTimestampWrites<'_> {
+        wgpu::RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: first_pass.then_some(RENDER_START),
+            end_of_pass_write_index: Some(RENDER_END),
+        }
+    }
+
+    /// Copies the frame's timestamps to the readback buffer, recorded after the last pass
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.resolve_query_set(&self.query_set, 0..QUERY_COUNT, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            RESOLVE_SIZE,
+        );
+    }
+
+    /// Starts mapping the readback buffer, to be called after the frame is submitted.
+    /// The result is picked up by `take_durations` once the device has been polled
+    pub fn request_readback(&self) {
+        *self.readback.lock().unwrap() = Readback::Mapping;
+        let readback = self.readback.clone();
+        self.readback_buffer
+            .map_async(wgpu::MapMode::Read, .., move |result| {
+                *readback.lock().unwrap() = match result {
+                    Ok(()) => Readback::Mapped,
+                    Err(_) => Readback::Failed,
+                };
+            });
+    }
+
+    /// Returns the durations of the last measured frame once they are available
+    pub fn take_durations(&self) -> Option<GpuDurations> {
+        let mut readback = self.readback.lock().unwrap();
+        match *readback {
+            Readback::Idle | Readback::Mapping => return None,
+            Readback::Failed => {
+                *readback = Readback::Idle;
+                return None;
+            }
+            Readback::Mapped => {}
+        }
+
+        let timestamps: [u64; QUERY_COUNT as usize] = {
+            let data = self.readback_buffer.get_mapped_range(..);
+            let mut timestamps = [0; QUERY_COUNT as usize];
+            for (timestamp, bytes) in timestamps.iter_mut().zip(data.chunks_exact(8)) {
+                *timestamp = bytemuck::pod_read_unaligned(bytes);
+            }
+            timestamps
+        };
+        self.readback_buffer.unmap();
+        *readback = Readback::Idle;
+
+        durations(&timestamps, self.period, self.times_between_passes)
+    }
+}
+
+/// Converts the resolved timestamps, `period` nanoseconds per tick, into durations. Nothing
+/// is measured if the render passes went backwards
+fn durations(
+    timestamps: &[u64; QUERY_COUNT as usize],
+    period: f32,
+    times_between_passes: bool,
+) -> Option<GpuDurations> {
+    let duration = |start: u32, end: u32| {
+        let ticks = timestamps[end as usize].checked_sub(timestamps[start as usize])?;
+        Some(Duration::from_nanos((ticks as f64 * period as f64) as u64))
+    };
+    let between_passes = |start: u32, end: u32| {
+        if times_between_passes {
+            duration(start, end)
+        } else {
+            None
+        }
+    };
+
+    Some(GpuDurations {
+        upload: between_passes(UPLOAD_START, UPLOAD_END),
+        processing: between_passes(PROCESSING_START, PROCESSING_END),
+        render: duration(RENDER_START, RENDER_END)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ticks_are_scaled_by_the_period() {
+        let timestamps = [1_000, 1_400, 1_500, 3_500, 4_000, 5_000];
+
+        let durations = durations(&timestamps, 2.5, true).unwrap();
+
+        assert_eq!(durations.upload, Some(Duration::from_nanos(1_000)));
+        assert_eq!(durations.processing, Some(Duration::from_nanos(5_000)));
+        assert_eq!(durations.render, Duration::from_nanos(2_500));
+    }
+
+    #[test]
+    fn passes_alone_are_timed_without_timestamps_between_them() {
+        let timestamps = [0, 0, 0, 0, 100, 300];
+
+        let durations = durations(&timestamps, 1.0, false).unwrap();
+
+        assert_eq!(durations.upload, None);
+        assert_eq!(durations.processing, None);
+        assert_eq!(durations.render, Duration::from_nanos(200));
+    }
+
+    #[test]
+    fn timestamps_going_backwards_are_not_durations() {
+        // A counter that was reset between two writes
+        let stale_processing = [100, 200, 900, 300, 400, 500];
+        let durations = durations(&stale_processing, 1.0, true).unwrap();
+        assert_eq!(durations.processing, None);
+        assert_eq!(durations.render, Duration::from_nanos(100));
+
+        let backwards_render = [100, 200, 200, 300, 500, 400];
+        assert!(super::durations(&backwards_render, 1.0, true).is_none());
+    }
+}
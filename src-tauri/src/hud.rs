@@ -0,0 +1,175 @@
+use crate::text::{TextLayout, TextQuad};
+use std::time::Duration;
+
+/// Frame times shown in the graph, oldest first
+pub const GRAPH_SAMPLES: usize = 120;
+/// Frame time at the top of the graph
+const GRAPH_MAX_MS: f64 = 50.0;
+
+const MARGIN: f32 = 8.0;
+const PADDING: f32 = 6.0;
+/// Line and graph heights at scale 1
+const LINE_HEIGHT: f32 = crate::text::GLYPH_HEIGHT as f32 + 3.0;
+const GRAPH_HEIGHT: f32 = 20.0;
+/// Smallest the HUD gets on targets too small for it, it is cut off below that
+const MIN_SCALE: f32 = 0.25;
+
+const BACKGROUND: [f32; 4] = [0.0, 0.0, 0.0, 0.6];
+const TEXT: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const GOOD: [f32; 4] = [0.2, 0.9, 0.3, 1.0];
+const SLOW: [f32; 4] = [0.95, 0.8, 0.2, 1.0];
+const BAD: [f32; 4] = [0.95, 0.25, 0.2, 1.0];
+
+/// What the performance HUD shows, gathered once per frame
+pub struct HudInfo {
+    pub fps: f64,
+    /// Time between presented frames, oldest first
+    pub frame_times: Vec<Duration>,
+    pub dropped_frames: u64,
+    pub resolution: (u32, u32),
+    pub pixel_format: String,
+    pub adapter: String,
+}
+
+/// Lays the HUD out in the top left corner of a target. Smaller targets get smaller text,
+/// and targets too small for that get a HUD shrunk to fit
+pub fn layout(info: &HudInfo, target_width: u32, target_height: u32) -> Vec<TextQuad> {
+    let last_frame_ms = info
+        .frame_times
+        .last()
+        .map_or(0.0, |frame_time| frame_time.as_secs_f64() * 1000.0);
+    let mut adapter = info.adapter.clone();
+    if adapter.chars().count() > 30 {
+        adapter = adapter
+            .chars()
+            .take(29)
+            .chain(std::iter::once('.'))
+            .collect();
+    }
+    let lines = [
+        format!("FPS {:.1}  {:.1} MS", info.fps, last_frame_ms),
+        format!("DROPPED {}", info.dropped_frames),
+        format!(
+            "{}X{} {}",
+            info.resolution.0, info.resolution.1, info.pixel_format
+        ),
+        adapter,
+    ];
+
+    // Size of the lines and the graph at scale 1, one pixel per bar
+    let text_width = lines
+        .iter()
+        .map(|line| TextLayout::measure(line, 1.0).0)
+        .fold(0.0, f32::max);
+    let content_width = text_width.max(GRAPH_SAMPLES as f32);
+    let content_height = lines.len() as f32 * LINE_HEIGHT + GRAPH_HEIGHT;
+    let inset = 2.0 * (MARGIN + PADDING);
+    let preferred_scale: f32 = if target_width < 480 { 1.0 } else { 2.0 };
+    let scale = preferred_scale
+        .min((target_width as f32 - inset) / content_width)
+        .min((target_height as f32 - inset) / content_height)
+        .max(MIN_SCALE);
+
+    let line_height = LINE_HEIGHT * scale;
+    let graph_height = GRAPH_HEIGHT * scale;
+    let bar_width = scale;
+    let panel_width = content_width * scale + 2.0 * PADDING;
+    let panel_height = content_height * scale + 2.0 * PADDING;
+
+    let mut hud = TextLayout::new(target_width, target_height);
+    hud.push_rect(MARGIN, MARGIN, panel_width, panel_height, BACKGROUND);
+
+    let x = MARGIN + PADDING;
+    let mut y = MARGIN + PADDING;
+    hud.push_text(x, y, &lines[0], scale, TEXT);
+    y += line_height;
+
+    // Frame time graph, newest frame on the right
+    let graph_bottom = y + graph_height;
+    let skipped = GRAPH_SAMPLES.saturating_sub(info.frame_times.len());
+    let recent = info.frame_times.len().saturating_sub(GRAPH_SAMPLES);
+    for (i, frame_time) in info.frame_times[recent..].iter().enumerate() {
+        let ms = frame_time.as_secs_f64() * 1000.0;
+        let height = ((ms / GRAPH_MAX_MS).min(1.0) as f32 * graph_height).max(1.0);
+        let color = if ms < 20.0 {
+            GOOD
+        } else if ms < 40.0 {
+            SLOW
+        } else {
+            BAD
+        };
+        hud.push_rect(
+            x + (skipped + i) as f32 * bar_width,
+            graph_bottom - height,
+            bar_width,
+            height,
+            color,
+        );
+    }
+    y = graph_bottom + (line_height - crate::text::GLYPH_HEIGHT as f32 * scale);
+
+    for line in &lines[1..] {
+        hud.push_text(x, y, line, scale, TEXT);
+        y += line_height;
+    }
+
+    hud.quads
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(frame_count: usize) -> HudInfo {
+        HudInfo {
+            fps: 29.97,
+            frame_times: vec![Duration::from_millis(33); frame_count],
+            dropped_frames: 12345,
+            resolution: (1920, 1080),
+            pixel_format: "YUYV".to_string(),
+            adapter: "A Discrete Graphics Adapter With A Long Name".to_string(),
+        }
+    }
+
+    #[test]
+    fn quads_stay_inside_small_targets() {
+        for (width, height) in [(120, 68), (200, 112), (320, 180), (479, 270), (480, 270)] {
+            for quad in layout(&info(GRAPH_SAMPLES), width, height) {
+                let [left, top, right, bottom] = quad.rect;
+                assert!(
+                    left >= -1.0 && right <= 1.0 && top <= 1.0 && bottom >= -1.0,
+                    "{:?} outside {width}x{height}",
+                    quad.rect
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn large_targets_get_the_larger_text() {
+        let quads = layout(&info(0), 1280, 720);
+
+        // The panel comes first, then the "F" of the first line
+        let [_, top, _, bottom] = quads[1].rect;
+        let glyph_height = (top - bottom) / 2.0 * 720.0;
+        assert!((glyph_height - 2.0 * crate::text::GLYPH_HEIGHT as f32).abs() < 1e-3);
+    }
+
+    #[test]
+    fn graph_shows_the_most_recent_frames() {
+        let mut info = info(GRAPH_SAMPLES + 30);
+        info.frame_times[GRAPH_SAMPLES + 29] = Duration::from_millis(45);
+
+        let quads = layout(&info, 1280, 720);
+
+        let bars: Vec<&TextQuad> = quads
+            .iter()
+            .filter(|quad| [GOOD, SLOW, BAD].contains(&quad.color))
+            .collect();
+        assert_eq!(bars.len(), GRAPH_SAMPLES);
+        assert_eq!(bars.last().unwrap().color, BAD);
+        assert!(bars[..GRAPH_SAMPLES - 1]
+            .iter()
+            .all(|bar| bar.color == SLOW));
+    }
+}
@@ -0,0 +1,169 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use wgpu::util::DeviceExt;
+
+/// 8-bit RGBA pixels, rows top to bottom without padding
+#[derive(Debug, Clone)]
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl RgbaImage {
+    /// Creates a texture holding the image. Pictures want an sRGB `format`,
+    /// masks and other data a linear one
+    pub fn create_texture(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        label: &str,
+        format: wgpu::TextureFormat,
+    ) -> wgpu::Texture {
+        device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: self.width,
+                    height: self.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::LayerMajor,
+            &self.pixels,
+        )
+    }
+}
+
+/// Decodes the PNG at `path`, whatever its bit depth and color type
+pub fn load_png(path: &Path) -> Result<RgbaImage, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {e}", path.display()))?;
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    // Palettes are expanded and 16-bit channels reduced, leaving 8-bit gray or RGB, maybe with alpha
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder
+        .read_info()
+        .map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader
+        .next_frame(&mut buffer)
+        .map_err(|e| format!("Failed to decode {}: {e}", path.display()))?;
+    let bytes = &buffer[..info.buffer_size()];
+
+    let pixels = match info.color_type {
+        png::ColorType::Rgba => bytes.to_vec(),
+        png::ColorType::Rgb => bytes
+            .chunks_exact(3)
+            .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => bytes
+            .chunks_exact(2)
+            .flat_map(|ga| [ga[0], ga[0], ga[0], ga[1]])
+            .collect(),
+        png::ColorType::Grayscale => bytes.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+        png::ColorType::Indexed => {
+            return Err(format!("Unexpected palette image in {}", path.display()))
+        }
+    };
+
+    Ok(RgbaImage {
+        width: info.width,
+        height: info.height,
+        pixels,
+    })
+}
+
+/// Encodes `image` as an 8-bit RGBA PNG at `path`
+pub fn save_png(image: &RgbaImage, path: &Path) -> Result<(), String> {
+    let file =
+        File::create(path).map_err(|e| format!("Failed to create {}: {e}", path.display()))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), image.width, image.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&image.pixels))
+        .map_err(|e| format!("Failed to write {}: {e}", path.display()))
+}
+
+/// Copy of an RGBA8 frame texture on its way back to the CPU
+pub struct FrameReadback {
+    buffer: wgpu::Buffer,
+    width: u32,
+    height: u32,
+    padded_row_bytes: u32,
+}
+
+impl FrameReadback {
+    /// Records a copy of `texture` into a buffer that can be mapped once `encoder` is submitted
+    pub fn record(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+    ) -> Self {
+        let (width, height) = (texture.width(), texture.height());
+        let padded_row_bytes = (4 * width).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("frame_readback"),
+            size: (padded_row_bytes * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_bytes),
+                    rows_per_image: Some(height),
+                },
+            },
+            texture.size(),
+        );
+
+        Self {
+            buffer,
+            width,
+            height,
+            padded_row_bytes,
+        }
+    }
+
+    /// Starts mapping the buffer, to be called after the copy is submitted. `done` runs on
+    /// the thread polling the device, and should hand the readback over to another thread
+    pub fn map(self, done: impl FnOnce(Result<Self, String>) + Send + 'static) {
+        let buffer = self.buffer.clone();
+        buffer.map_async(wgpu::MapMode::Read, .., move |result| {
+            done(result.map(|()| self).map_err(|e| e.to_string()))
+        });
+    }
+
+    /// Pixels of the mapped buffer, without the row padding
+    pub fn into_image(self) -> RgbaImage {
+        let row_bytes = 4 * self.width as usize;
+        let pixels = {
+            let data = self.buffer.get_mapped_range(..);
+            data.chunks_exact(self.padded_row_bytes as usize)
+                .flat_map(|row| &row[..row_bytes])
+                .copied()
+                .collect()
+        };
+        self.buffer.unmap();
+
+        RgbaImage {
+            width: self.width,
+            height: self.height,
+            pixels,
+        }
+    }
+}
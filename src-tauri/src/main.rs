@@ -2,19 +2,85 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 mod app;
+mod blur;
 mod camera;
+mod cli;
 mod commands;
+mod compositor;
+mod effects;
+mod events;
+mod gpu_timing;
+mod hud;
+mod image;
+mod overlays;
+mod preview_server;
+mod processing;
+mod replay;
+mod settings;
+#[cfg(debug_assertions)]
+mod shader_reload;
+mod stats;
+mod text;
+mod timelapse;
+mod watermark;
 mod webgpu;
 mod windows_management;
 
+use clap::Parser;
+
 fn main() {
     tracing_subscriber::fmt::init();
+    let options = cli::CliOptions::parse();
 
     tauri::Builder::default()
-        .setup(app::app_setup)
+        .setup(move |app| app::app_setup(app, options))
         .invoke_handler(tauri::generate_handler![
-            commands::toggle_camera_mode,
-            commands::get_camera_mode
+            commands::set_camera_mode,
+            commands::get_camera_mode,
+            commands::get_monitors,
+            commands::set_overlay_interactive,
+            commands::reset_overlay_layout,
+            commands::add_preview_window,
+            commands::add_offscreen_target,
+            commands::remove_render_target,
+            commands::list_render_targets,
+            commands::get_rendering_settings,
+            commands::get_supported_present_modes,
+            commands::set_rendering_settings,
+            commands::get_pipeline_stats,
+            commands::set_hud_visible,
+            commands::get_settings,
+            commands::update_settings,
+            commands::get_frame_processors,
+            commands::set_frame_processor_enabled,
+            commands::reorder_frame_processors,
+            commands::get_effects,
+            commands::load_effect,
+            commands::remove_effect,
+            commands::set_effect_enabled,
+            commands::set_effect_params,
+            commands::get_blur_settings,
+            commands::set_blur_settings,
+            commands::get_overlays,
+            commands::set_overlay,
+            commands::remove_overlay,
+            commands::get_watermark,
+            commands::set_watermark,
+            commands::get_camera_sources,
+            commands::add_camera_source,
+            commands::remove_camera_source,
+            commands::get_composite_layout,
+            commands::set_composite_layout,
+            commands::get_replay_info,
+            commands::set_replay_settings,
+            commands::save_replay,
+            commands::freeze_frame,
+            commands::resume_live,
+            commands::start_timelapse,
+            commands::stop_timelapse,
+            commands::get_timelapse_status,
+            commands::get_preview_server,
+            commands::set_preview_server
         ])
         .build(tauri::generate_context!())
         .expect("Error while building tauri application")
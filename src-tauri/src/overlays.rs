@@ -0,0 +1,311 @@
+use crate::text::{TextLayout, TextQuad, TextRenderer, GLYPH_HEIGHT};
+use crate::webgpu::{CameraSettingsUniform, OFFSCREEN_FORMAT};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+const MIN_FONT_SIZE: f32 = GLYPH_HEIGHT as f32;
+const MAX_FONT_SIZE: f32 = 200.0;
+/// Space between the text and the edge of its background box, relative to the font size
+const BOX_PADDING: f32 = 0.4;
+
+/// Corner or edge of the frame an overlay is placed against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OverlayAnchor {
+    TopLeft,
+    TopCenter,
+    TopRight,
+    BottomLeft,
+    /// Lower third
+    BottomCenter,
+    BottomRight,
+}
+
+impl OverlayAnchor {
+    /// Top left corner of a box of `size` placed `offset` away from the anchor, towards
+    /// the center of a `frame`, everything in pixels. Offsets can't push the box out of the
+    /// frame, a box larger than the frame sticks to its top left corner
+    pub fn place(self, offset: [f32; 2], size: (f32, f32), frame: (f32, f32)) -> (f32, f32) {
+        let [offset_x, offset_y] = offset;
+        let x = match self {
+            Self::TopLeft | Self::BottomLeft => offset_x,
+            Self::TopCenter | Self::BottomCenter => (frame.0 - size.0) / 2.0 + offset_x,
+            Self::TopRight | Self::BottomRight => frame.0 - offset_x - size.0,
+        };
+        let y = match self {
+            Self::TopLeft | Self::TopCenter | Self::TopRight => offset_y,
+            Self::BottomLeft | Self::BottomCenter | Self::BottomRight => {
+                frame.1 - offset_y - size.1
+            }
+        };
+        (
+            x.clamp(0.0, (frame.0 - size.0).max(0.0)),
+            y.clamp(0.0, (frame.1 - size.1).max(0.0)),
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OverlayContent {
+    /// Local date and time at which the frame was captured
+    Timestamp,
+    Caption {
+        text: String,
+    },
+    /// Frames rendered since startup
+    FrameCounter,
+}
+
+/// Text drawn over the camera frame, sizes and offsets are in camera pixels
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextOverlay {
+    pub id: String,
+    pub content: OverlayContent,
+    pub anchor: OverlayAnchor,
+    /// Distance from the anchor, towards the center of the frame
+    pub offset: [f32; 2],
+    /// Line height, the glyphs of the font are a bit smaller
+    pub font_size: f32,
+    pub color: [f32; 4],
+    /// Box drawn behind the text, none when absent
+    pub background: Option<[f32; 4]>,
+    /// Drawn into the frame itself, so it shows up in recordings and snapshots,
+    /// rather than only over the camera quad on screen
+    pub burn_in: bool,
+}
+
+impl TextOverlay {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.id.is_empty() {
+            return Err("Overlay id can't be empty".to_string());
+        }
+        if !(MIN_FONT_SIZE..=MAX_FONT_SIZE).contains(&self.font_size) {
+            return Err(format!(
+                "Font size must be between {MIN_FONT_SIZE} and {MAX_FONT_SIZE}"
+            ));
+        }
+        Ok(())
+    }
+
+    fn text(&self, frame: &OverlayFrame) -> String {
+        match &self.content {
+            OverlayContent::Timestamp => frame.captured_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+            OverlayContent::Caption { text } => text.clone(),
+            OverlayContent::FrameCounter => format!("FRAME {}", frame.frame_number),
+        }
+    }
+}
+
+/// What the overlays of one frame may show
+pub struct OverlayFrame {
+    pub captured_at: DateTime<Local>,
+    pub frame_number: u64,
+}
+
+/// Lays `overlays` out over a `width` x `height` camera frame
+pub fn layout<'a>(
+    overlays: impl IntoIterator<Item = &'a TextOverlay>,
+    frame: &OverlayFrame,
+    width: u32,
+    height: u32,
+) -> Vec<TextQuad> {
+    let mut layout = TextLayout::new(width, height);
+    for overlay in overlays {
+        let text = overlay.text(frame);
+        let scale = overlay.font_size / GLYPH_HEIGHT as f32;
+        let (text_width, text_height) = TextLayout::measure(&text, scale);
+        let padding = if overlay.background.is_some() {
+            (overlay.font_size * BOX_PADDING).round()
+        } else {
+            0.0
+        };
+        let box_width = text_width + 2.0 * padding;
+        let box_height = text_height + 2.0 * padding;
+
+        let (x, y) = overlay.anchor.place(
+            overlay.offset,
+            (box_width, box_height),
+            (width as f32, height as f32),
+        );
+
+        if let Some(background) = overlay.background {
+            layout.push_rect(x, y, box_width, box_height, background);
+        }
+        layout.push_text(x + padding, y + padding, &text, scale, overlay.color);
+    }
+    layout.quads
+}
+
+/// Moves quads laid out over the whole frame onto the camera quad of a render target
+pub fn fit_to_camera_quad(quads: &mut [TextQuad], camera_settings: &CameraSettingsUniform) {
+    let [center_x, center_y] = camera_settings.position;
+    let [size_x, size_y] = camera_settings.size;
+    for quad in quads {
+        let [left, top, right, bottom] = quad.rect;
+        quad.rect = [
+            center_x + left * size_x / 2.0,
+            center_y + top * size_y / 2.0,
+            center_x + right * size_x / 2.0,
+            center_y + bottom * size_y / 2.0,
+        ];
+    }
+}
+
+/// Draws `quads` straight into the processed camera frame `texture`
+pub fn burn_in(
+    text: &TextRenderer,
+    encoder: &mut wgpu::CommandEncoder,
+    texture: &wgpu::Texture,
+    quads: &[TextQuad],
+) {
+    if quads.is_empty() {
+        return;
+    }
+
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("burned_in_overlays"),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: &view,
+            depth_slice: None,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Load,
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        timestamp_writes: None,
+        occlusion_query_set: None,
+        multiview_mask: None,
+    });
+    text.draw(&mut rpass, OFFSCREEN_FORMAT, quads);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: (f32, f32) = (640.0, 480.0);
+
+    fn caption(text: &str, anchor: OverlayAnchor, background: Option<[f32; 4]>) -> TextOverlay {
+        TextOverlay {
+            id: "caption".to_string(),
+            content: OverlayContent::Caption {
+                text: text.to_string(),
+            },
+            anchor,
+            offset: [0.0, 0.0],
+            font_size: 24.0,
+            color: [1.0; 4],
+            background,
+            burn_in: false,
+        }
+    }
+
+    fn overlay_frame() -> OverlayFrame {
+        OverlayFrame {
+            captured_at: Local::now(),
+            frame_number: 7,
+        }
+    }
+
+    fn assert_close<const N: usize>(actual: [f32; N], expected: [f32; N]) {
+        assert!(
+            actual
+                .iter()
+                .zip(expected)
+                .all(|(a, b)| (a - b).abs() < 1e-5),
+            "{actual:?} != {expected:?}"
+        );
+    }
+
+    #[test]
+    fn anchors_place_the_box_against_their_edge() {
+        let place = |anchor: OverlayAnchor| anchor.place([10.0, 20.0], (100.0, 30.0), FRAME);
+
+        assert_eq!(place(OverlayAnchor::TopLeft), (10.0, 20.0));
+        assert_eq!(place(OverlayAnchor::TopCenter), (280.0, 20.0));
+        assert_eq!(place(OverlayAnchor::TopRight), (530.0, 20.0));
+        assert_eq!(place(OverlayAnchor::BottomLeft), (10.0, 430.0));
+        assert_eq!(place(OverlayAnchor::BottomCenter), (280.0, 430.0));
+        assert_eq!(place(OverlayAnchor::BottomRight), (530.0, 430.0));
+    }
+
+    #[test]
+    fn offsets_are_clamped_to_the_frame() {
+        assert_eq!(
+            OverlayAnchor::TopLeft.place([1000.0, -50.0], (100.0, 30.0), FRAME),
+            (540.0, 0.0)
+        );
+        assert_eq!(
+            OverlayAnchor::BottomRight.place([1000.0, 1000.0], (100.0, 30.0), FRAME),
+            (0.0, 0.0)
+        );
+        // Too wide to fit, the start of the text stays visible
+        assert_eq!(
+            OverlayAnchor::TopRight.place([0.0, 0.0], (800.0, 30.0), FRAME),
+            (0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn font_size_is_the_line_height() {
+        let overlay = caption("A", OverlayAnchor::TopLeft, None);
+
+        let quads = layout([&overlay], &overlay_frame(), 640, 480);
+
+        assert_eq!(quads.len(), 1);
+        let [_, top, _, bottom] = quads[0].rect;
+        assert!(((top - bottom) / 2.0 * 480.0 - 24.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn background_box_pads_the_text() {
+        let overlay = caption("HI", OverlayAnchor::BottomRight, Some([0.0, 0.0, 0.0, 0.5]));
+
+        let quads = layout([&overlay], &overlay_frame(), 640, 480);
+
+        // The box comes first, so the text is drawn over it
+        assert_eq!(quads.len(), 3);
+        let [box_left, box_top, box_right, box_bottom] = quads[0].rect;
+        assert_close([box_right, box_bottom], [1.0, -1.0]);
+        let (text_width, text_height) = TextLayout::measure("HI", 2.0);
+        let padding = (24.0 * BOX_PADDING).round();
+        assert!(((box_right - box_left) / 2.0 * 640.0 - text_width - 2.0 * padding).abs() < 1.0);
+        assert!(((box_top - box_bottom) / 2.0 * 480.0 - text_height - 2.0 * padding).abs() < 1.0);
+        for glyph in &quads[1..] {
+            let [left, top, right, bottom] = glyph.rect;
+            assert!(left > box_left && right < box_right && top < box_top && bottom > box_bottom);
+        }
+    }
+
+    #[test]
+    fn frame_counter_shows_the_frame_number() {
+        let overlay = TextOverlay {
+            content: OverlayContent::FrameCounter,
+            ..caption("", OverlayAnchor::TopLeft, None)
+        };
+
+        assert_eq!(overlay.text(&overlay_frame()), "FRAME 7");
+    }
+
+    #[test]
+    fn quads_are_mapped_into_the_camera_quad() {
+        let mut layout = TextLayout::new(640, 480);
+        layout.push_rect(0.0, 0.0, 640.0, 480.0, [1.0; 4]);
+        layout.push_rect(0.0, 0.0, 320.0, 240.0, [1.0; 4]);
+        // Right half of the target, letterboxed to half its height
+        let camera_settings = CameraSettingsUniform {
+            position: [0.5, 0.0],
+            size: [1.0, 1.0],
+        };
+
+        fit_to_camera_quad(&mut layout.quads, &camera_settings);
+
+        assert_close(layout.quads[0].rect, [0.0, 0.5, 1.0, -0.5]);
+        assert_close(layout.quads[1].rect, [0.0, 0.5, 0.5, 0.0]);
+    }
+}
@@ -0,0 +1,560 @@
+use crate::image::{FrameReadback, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+const MAX_FRAME_RATE: u32 = 60;
+/// How often the listener checks whether it should stop
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// How long a request waits for a frame before giving up
+const FRAME_TIMEOUT: Duration = Duration::from_secs(2);
+/// A frame encoded this recently is served as is by `/snapshot.jpg`
+const SNAPSHOT_MAX_AGE: Duration = Duration::from_millis(500);
+const BOUNDARY: &str = "frame";
+/// Clients served at once, more are turned away
+const MAX_CLIENTS: usize = 16;
+/// A client that doesn't send its request or stops reading for this long is dropped
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Local HTTP server streaming the processed frames as MJPEG
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PreviewServerSettings {
+    pub enabled: bool,
+    /// Bound on localhost only
+    pub port: u16,
+    /// Frames are encoded at most this often, whatever the camera frame rate
+    pub max_frame_rate: u32,
+    /// JPEG quality from 1 to 100
+    pub quality: u8,
+}
+
+impl Default for PreviewServerSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 8765,
+            max_frame_rate: 15,
+            quality: 80,
+        }
+    }
+}
+
+impl PreviewServerSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(1..=MAX_FRAME_RATE).contains(&self.max_frame_rate) {
+            return Err(format!(
+                "Preview frame rate must be between 1 and {MAX_FRAME_RATE}"
+            ));
+        }
+        if !(1..=100).contains(&self.quality) {
+            return Err("JPEG quality must be between 1 and 100".to_string());
+        }
+        Ok(())
+    }
+
+    fn frame_interval(&self) -> Duration {
+        Duration::from_secs_f32(1.0 / self.max_frame_rate as f32)
+    }
+}
+
+/// Encoded frame shared by every client
+pub struct JpegFrame {
+    pub sequence: u64,
+    pub jpeg: Vec<u8>,
+    pub encoded_at: Instant,
+}
+
+/// Latest encoded frame, and how many clients are connected and waiting for frames
+#[derive(Default)]
+struct FrameFeed {
+    latest: Mutex<Option<Arc<JpegFrame>>>,
+    new_frame: Condvar,
+    clients: AtomicUsize,
+    viewers: AtomicUsize,
+    stop: AtomicBool,
+}
+
+impl FrameFeed {
+    fn publish(&self, jpeg: Vec<u8>) {
+        let mut latest = self.latest.lock().unwrap();
+        let sequence = latest.as_ref().map_or(0, |frame| frame.sequence) + 1;
+        *latest = Some(Arc::new(JpegFrame {
+            sequence,
+            jpeg,
+            encoded_at: Instant::now(),
+        }));
+        self.new_frame.notify_all();
+    }
+
+    /// Waits for a frame after `sequence`, `None` on timeout or when the server stops
+    fn next_after(&self, sequence: u64) -> Option<Arc<JpegFrame>> {
+        let latest = self.latest.lock().unwrap();
+        let (latest, _) = self
+            .new_frame
+            .wait_timeout_while(latest, FRAME_TIMEOUT, |latest| {
+                !self.stop.load(Ordering::Relaxed)
+                    && latest
+                        .as_ref()
+                        .is_none_or(|frame| frame.sequence <= sequence)
+            })
+            .unwrap();
+        latest
+            .clone()
+            .filter(|frame| frame.sequence > sequence && !self.stop.load(Ordering::Relaxed))
+    }
+
+    /// Counts a client as watching until the returned guard is dropped
+    fn watch(self: &Arc<Self>) -> ViewerGuard {
+        self.viewers.fetch_add(1, Ordering::Relaxed);
+        ViewerGuard(self.clone())
+    }
+
+    /// Counts a connection until the returned guard is dropped, `None` when there are
+    /// already `MAX_CLIENTS`
+    fn connect(self: &Arc<Self>) -> Option<ClientGuard> {
+        self.clients
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |clients| {
+                (clients < MAX_CLIENTS).then_some(clients + 1)
+            })
+            .ok()
+            .map(|_| ClientGuard(self.clone()))
+    }
+}
+
+struct ViewerGuard(Arc<FrameFeed>);
+
+impl Drop for ViewerGuard {
+    fn drop(&mut self) {
+        self.0.viewers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+struct ClientGuard(Arc<FrameFeed>);
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        self.0.clients.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+struct RunningServer {
+    settings: PreviewServerSettings,
+    feed: Arc<FrameFeed>,
+    /// Frames read back from the GPU, dropped to stop the encoder thread
+    frames: flume::Sender<FrameReadback>,
+    /// Set while a frame is read back or encoded, so at most one is in flight
+    busy: Arc<AtomicBool>,
+    last_frame_at: Option<Instant>,
+    /// Owns the listener, joined on drop so the port is free again afterwards
+    accept_thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for RunningServer {
+    fn drop(&mut self) {
+        self.feed.stop.store(true, Ordering::Relaxed);
+        self.feed.new_frame.notify_all();
+        if let Some(accept_thread) = self.accept_thread.take() {
+            let _ = accept_thread.join();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewServerStatus {
+    pub settings: PreviewServerSettings,
+    /// Where the stream is served while the server runs
+    pub url: Option<String>,
+    pub viewers: usize,
+}
+
+/// Serves `/stream.mjpg` and `/snapshot.jpg` on localhost. The render loop only reads frames
+/// back while someone is watching, encoding happens on a thread of its own
+#[derive(Default)]
+pub struct PreviewServer {
+    running: Mutex<Option<RunningServer>>,
+    settings: Mutex<PreviewServerSettings>,
+}
+
+impl PreviewServer {
+    /// Starts, restarts or stops the server to match `settings`. Clients stay connected
+    /// when nothing changed
+    pub fn apply(&self, settings: PreviewServerSettings) -> Result<PreviewServerStatus, String> {
+        settings.validate()?;
+        let mut running = self.running.lock().unwrap();
+        if running.as_ref().map(|server| server.settings) != Some(settings) {
+            // Dropping the old server stops it, freeing the port
+            *running = None;
+            if settings.enabled {
+                *running = Some(start(settings)?);
+            }
+        }
+        *self.settings.lock().unwrap() = settings;
+        drop(running);
+        Ok(self.status())
+    }
+
+    pub fn status(&self) -> PreviewServerStatus {
+        let running = self.running.lock().unwrap();
+        PreviewServerStatus {
+            settings: *self.settings.lock().unwrap(),
+            url: running
+                .as_ref()
+                .map(|server| format!("http://127.0.0.1:{}/stream.mjpg", server.settings.port)),
+            viewers: running
+                .as_ref()
+                .map_or(0, |server| server.feed.viewers.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Whether a client is connected, frames have to be rendered for it even when nothing is
+    /// on screen
+    pub fn has_viewers(&self) -> bool {
+        self.running
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|server| server.feed.viewers.load(Ordering::Relaxed) > 0)
+    }
+
+    /// Whether the render loop should read the current frame back, called once per frame
+    pub fn wants_frame(&self) -> bool {
+        let mut running = self.running.lock().unwrap();
+        let Some(server) = running.as_mut() else {
+            return false;
+        };
+        let now = Instant::now();
+        let wanted = server.feed.viewers.load(Ordering::Relaxed) > 0
+            && !server.busy.load(Ordering::Relaxed)
+            && server
+                .last_frame_at
+                .is_none_or(|last| now.duration_since(last) >= server.settings.frame_interval());
+        if wanted {
+            server.busy.store(true, Ordering::Relaxed);
+            server.last_frame_at = Some(now);
+        }
+        wanted
+    }
+
+    /// Hands a frame over to the encoder once it has been read back from the GPU
+    pub fn submit(&self, readback: FrameReadback) {
+        let Some((frames, busy)) = self
+            .running
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|server| (server.frames.clone(), server.busy.clone()))
+        else {
+            return;
+        };
+        readback.map(move |result| match result {
+            Ok(readback) => {
+                if frames.send(readback).is_err() {
+                    busy.store(false, Ordering::Relaxed);
+                }
+            }
+            Err(e) => {
+                warn!("Could not read a preview frame back: {}", e);
+                busy.store(false, Ordering::Relaxed);
+            }
+        });
+    }
+}
+
+fn start(settings: PreviewServerSettings) -> Result<RunningServer, String> {
+    let address = SocketAddr::from((Ipv4Addr::LOCALHOST, settings.port));
+    let listener =
+        TcpListener::bind(address).map_err(|e| format!("Could not listen on {address}: {e}"))?;
+    let feed = Arc::new(FrameFeed::default());
+    let accept_thread = serve(listener, feed.clone())?;
+
+    let (frames, frames_rx) = flume::bounded::<FrameReadback>(1);
+    let busy = Arc::new(AtomicBool::new(false));
+    let encoder_feed = feed.clone();
+    let encoder_busy = busy.clone();
+    std::thread::Builder::new()
+        .name("preview-encoder".to_string())
+        .spawn(move || {
+            for readback in frames_rx.iter() {
+                match encode_jpeg(&readback.into_image(), settings.quality) {
+                    Ok(jpeg) => encoder_feed.publish(jpeg),
+                    Err(e) => warn!("Could not encode a preview frame: {}", e),
+                }
+                encoder_busy.store(false, Ordering::Relaxed);
+            }
+        })
+        .map_err(|e| format!("Could not start the preview encoder: {e}"))?;
+
+    info!("Preview server listening on http://{}", address);
+    Ok(RunningServer {
+        settings,
+        feed,
+        frames,
+        busy,
+        last_frame_at: None,
+        accept_thread: Some(accept_thread),
+    })
+}
+
+pub fn encode_jpeg(image: &RgbaImage, quality: u8) -> Result<Vec<u8>, String> {
+    let (width, height) = (
+        u16::try_from(image.width).map_err(|e| e.to_string())?,
+        u16::try_from(image.height).map_err(|e| e.to_string())?,
+    );
+    let mut jpeg = Vec::new();
+    jpeg_encoder::Encoder::new(&mut jpeg, quality)
+        .encode(&image.pixels, width, height, jpeg_encoder::ColorType::Rgba)
+        .map_err(|e| e.to_string())?;
+    Ok(jpeg)
+}
+
+/// Accepts connections on a thread until the feed stops, each client on a thread of its own,
+/// up to `MAX_CLIENTS`
+fn serve(listener: TcpListener, feed: Arc<FrameFeed>) -> Result<JoinHandle<()>, String> {
+    // Polled, so the thread notices when the server stops
+    listener
+        .set_nonblocking(true)
+        .map_err(|e| format!("Could not configure the preview server: {e}"))?;
+    std::thread::Builder::new()
+        .name("preview-server".to_string())
+        .spawn(move || {
+            while !feed.stop.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((mut stream, peer)) => {
+                        let Some(client) = feed.connect() else {
+                            debug!("Turning preview client {} away, too many clients", peer);
+                            let _ = respond(
+                                &mut stream,
+                                "503 Service Unavailable",
+                                "text/plain",
+                                b"Too many clients\n",
+                            );
+                            continue;
+                        };
+                        let _ = std::thread::Builder::new()
+                            .name("preview-client".to_string())
+                            .spawn(move || {
+                                if let Err(e) = handle_client(stream, &client.0) {
+                                    debug!("Preview client {} disconnected: {}", peer, e);
+                                }
+                            });
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                        std::thread::sleep(ACCEPT_POLL_INTERVAL);
+                    }
+                    Err(e) => warn!("Preview server could not accept a client: {}", e),
+                }
+            }
+            info!("Preview server stopped");
+        })
+        .map_err(|e| format!("Could not start the preview server: {e}"))
+}
+
+fn handle_client(stream: TcpStream, feed: &Arc<FrameFeed>) -> std::io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Headers are not needed, but are read so the client isn't reset
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut stream = stream;
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    if method != "GET" {
+        return respond(
+            &mut stream,
+            "405 Method Not Allowed",
+            "text/plain",
+            b"GET only\n",
+        );
+    }
+    match path {
+        "/" | "/stream.mjpg" => stream_frames(&mut stream, feed),
+        "/snapshot.jpg" => {
+            let _viewer = feed.watch();
+            let latest = feed.latest.lock().unwrap().clone();
+            let frame = match latest {
+                Some(frame) if frame.encoded_at.elapsed() < SNAPSHOT_MAX_AGE => Some(frame),
+                latest => feed.next_after(latest.map_or(0, |frame| frame.sequence)),
+            };
+            match frame {
+                Some(frame) => respond(&mut stream, "200 OK", "image/jpeg", &frame.jpeg),
+                None => respond(
+                    &mut stream,
+                    "503 Service Unavailable",
+                    "text/plain",
+                    b"No frame available\n",
+                ),
+            }
+        }
+        _ => respond(&mut stream, "404 Not Found", "text/plain", b"Not found\n"),
+    }
+}
+
+fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+/// Sends every new frame as a part of a `multipart/x-mixed-replace` response, until the
+/// client goes away or the server stops
+fn stream_frames(stream: &mut TcpStream, feed: &Arc<FrameFeed>) -> std::io::Result<()> {
+    let _viewer = feed.watch();
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: multipart/x-mixed-replace; boundary={BOUNDARY}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n"
+    )?;
+    let mut sequence = 0;
+    while !feed.stop.load(Ordering::Relaxed) {
+        // Nothing is sent while the camera is stalled, the connection stays open
+        let Some(frame) = feed.next_after(sequence) else {
+            continue;
+        };
+        sequence = frame.sequence;
+        write!(
+            stream,
+            "--{BOUNDARY}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+            frame.jpeg.len()
+        )?;
+        stream.write_all(&frame.jpeg)?;
+        stream.write_all(b"\r\n")?;
+        stream.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn serve_feed() -> (SocketAddr, Arc<FrameFeed>, JoinHandle<()>) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let feed = Arc::new(FrameFeed::default());
+        let accept_thread = serve(listener, feed.clone()).unwrap();
+        (address, feed, accept_thread)
+    }
+
+    fn stop(feed: &FrameFeed, accept_thread: JoinHandle<()>) {
+        feed.stop.store(true, Ordering::Relaxed);
+        feed.new_frame.notify_all();
+        accept_thread.join().unwrap();
+    }
+
+    fn get(address: SocketAddr, path: &str) -> TcpStream {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        stream
+    }
+
+    fn read_all(mut stream: TcpStream) -> Vec<u8> {
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn snapshot_is_the_latest_frame() {
+        let (address, feed, accept_thread) = serve_feed();
+        feed.publish(b"first".to_vec());
+        feed.publish(b"second".to_vec());
+
+        let response = read_all(get(address, "/snapshot.jpg"));
+
+        assert!(response.starts_with(b"HTTP/1.1 200 OK\r\nContent-Type: image/jpeg\r\n"));
+        assert!(response.ends_with(b"\r\n\r\nsecond"));
+        stop(&feed, accept_thread);
+    }
+
+    #[test]
+    fn stream_sends_new_frames_as_parts() {
+        let (address, feed, accept_thread) = serve_feed();
+        let mut stream = get(address, "/stream.mjpg");
+        // Published once the client is watching
+        while feed.viewers.load(Ordering::Relaxed) == 0 {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        feed.publish(b"jpeg".to_vec());
+
+        let expected = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: multipart/x-mixed-replace; boundary={BOUNDARY}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n--{BOUNDARY}\r\nContent-Type: image/jpeg\r\nContent-Length: 4\r\n\r\njpeg\r\n"
+        );
+        let mut response = vec![0; expected.len()];
+        stream.read_exact(&mut response).unwrap();
+        assert_eq!(String::from_utf8(response).unwrap(), expected);
+
+        stop(&feed, accept_thread);
+        assert!(read_all(stream).is_empty());
+        assert_eq!(feed.viewers.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn unknown_paths_are_not_found() {
+        let (address, feed, accept_thread) = serve_feed();
+
+        let response = read_all(get(address, "/nothing"));
+
+        assert!(response.starts_with(b"HTTP/1.1 404 Not Found\r\n"));
+        stop(&feed, accept_thread);
+    }
+
+    #[test]
+    fn clients_over_the_limit_are_turned_away() {
+        let (address, feed, accept_thread) = serve_feed();
+        // Connected, but the request never comes
+        let idle: Vec<TcpStream> = (0..MAX_CLIENTS)
+            .map(|_| TcpStream::connect(address).unwrap())
+            .collect();
+        while feed.clients.load(Ordering::Relaxed) < MAX_CLIENTS {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        let response = read_all(TcpStream::connect(address).unwrap());
+
+        assert!(response.starts_with(b"HTTP/1.1 503 Service Unavailable\r\n"));
+        drop(idle);
+        while feed.clients.load(Ordering::Relaxed) > 0 {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        let response = read_all(get(address, "/nothing"));
+        assert!(response.starts_with(b"HTTP/1.1 404 Not Found\r\n"));
+        stop(&feed, accept_thread);
+    }
+
+    #[test]
+    fn frames_encode_as_jpeg() {
+        let image = RgbaImage {
+            width: 16,
+            height: 8,
+            pixels: [200, 100, 50, 255].repeat(16 * 8),
+        };
+
+        let jpeg = encode_jpeg(&image, 80).unwrap();
+
+        assert!(jpeg.starts_with(&[0xff, 0xd8]));
+        assert!(jpeg.ends_with(&[0xff, 0xd9]));
+    }
+}
@@ -0,0 +1,252 @@
+use crate::stats::LatencyPercentiles;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Runs kept per processor for the rolling timings
+const TIMING_WINDOW: usize = 300;
+
+/// Decoded RGBA8 camera frame, processed in place before upload
+pub struct RgbaFrame<'a> {
+    pub pixels: &'a mut [u8],
+    pub width: u32,
+    pub height: u32,
+}
+
+/// CPU step between decoding and upload. Processors run on the render loop,
+/// so anything slow here directly adds to the frame latency
+pub trait FrameProcessor: Send {
+    /// Unique within a chain, used by the commands to address the processor
+    fn name(&self) -> &str;
+    fn process(&mut self, frame: &mut RgbaFrame);
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProcessorInfo {
+    pub name: String,
+    pub enabled: bool,
+    /// Time spent in the processor over the last runs
+    pub timing: LatencyPercentiles,
+}
+
+type SharedProcessor = Arc<Mutex<Box<dyn FrameProcessor>>>;
+
+struct ProcessorSlot {
+    /// Kept apart from the processor, which is locked while it runs
+    name: String,
+    processor: SharedProcessor,
+    enabled: bool,
+    timings: VecDeque<Duration>,
+}
+
+/// Enabled processors of a chain, taken with the chain locked and run without it
+pub struct ProcessorRun {
+    processors: Vec<(String, SharedProcessor)>,
+}
+
+impl ProcessorRun {
+    /// Runs every processor on `frame` in order, returning how long each one took
+    pub fn process(&self, frame: &mut RgbaFrame) -> Vec<(String, Duration)> {
+        self.processors
+            .iter()
+            .map(|(name, processor)| {
+                let start = Instant::now();
+                processor.lock().unwrap().process(frame);
+                (name.clone(), start.elapsed())
+            })
+            .collect()
+    }
+}
+
+/// Ordered list of processors, every enabled one runs on each frame
+#[derive(Default)]
+pub struct ProcessorChain {
+    slots: Vec<ProcessorSlot>,
+}
+
+impl ProcessorChain {
+    /// Chain of the built-in processors, all disabled
+    pub fn with_builtins() -> Self {
+        let mut chain = Self::default();
+        chain.push(Box::new(Mirror), false);
+        chain.push(Box::new(Grayscale), false);
+        chain
+    }
+
+    pub fn push(&mut self, processor: Box<dyn FrameProcessor>, enabled: bool) {
+        self.slots.push(ProcessorSlot {
+            name: processor.name().to_string(),
+            processor: Arc::new(Mutex::new(processor)),
+            enabled,
+            timings: VecDeque::with_capacity(TIMING_WINDOW),
+        });
+    }
+
+    /// The processors a frame goes through, so the chain can be edited while it does
+    pub fn snapshot(&self) -> ProcessorRun {
+        ProcessorRun {
+            processors: self
+                .slots
+                .iter()
+                .filter(|slot| slot.enabled)
+                .map(|slot| (slot.name.clone(), slot.processor.clone()))
+                .collect(),
+        }
+    }
+
+    /// Adds the timings of a `ProcessorRun` to the processors that are still enabled
+    pub fn record_timings(&mut self, timings: Vec<(String, Duration)>) {
+        for (name, duration) in timings {
+            let Some(slot) = self
+                .slots
+                .iter_mut()
+                .find(|slot| slot.enabled && slot.name == name)
+            else {
+                continue;
+            };
+            if slot.timings.len() == TIMING_WINDOW {
+                slot.timings.pop_front();
+            }
+            slot.timings.push_back(duration);
+        }
+    }
+
+    pub fn infos(&self) -> Vec<ProcessorInfo> {
+        self.slots
+            .iter()
+            .map(|slot| ProcessorInfo {
+                name: slot.name.clone(),
+                enabled: slot.enabled,
+                timing: LatencyPercentiles::from_durations(slot.timings.iter().copied().collect()),
+            })
+            .collect()
+    }
+
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> Result<(), String> {
+        let slot = self
+            .slots
+            .iter_mut()
+            .find(|slot| slot.name == name)
+            .ok_or_else(|| format!("No frame processor named \"{name}\""))?;
+        slot.enabled = enabled;
+        // Timings from an earlier run would skew the percentiles
+        slot.timings.clear();
+        Ok(())
+    }
+
+    /// Reorders the chain to match `order`, which must name every processor exactly once
+    pub fn reorder(&mut self, order: &[String]) -> Result<(), String> {
+        if order.len() != self.slots.len() {
+            return Err(format!(
+                "Expected {} processor names, got {}",
+                self.slots.len(),
+                order.len()
+            ));
+        }
+
+        for (index, name) in order.iter().enumerate() {
+            if order[..index].contains(name) {
+                return Err(format!("Frame processor \"{name}\" is listed twice"));
+            }
+            if !self.slots.iter().any(|slot| slot.name == *name) {
+                return Err(format!("No frame processor named \"{name}\""));
+            }
+        }
+
+        self.slots
+            .sort_by_key(|slot| order.iter().position(|name| *name == slot.name));
+        Ok(())
+    }
+}
+
+/// Flips the frame horizontally, like a mirror
+pub struct Mirror;
+
+impl FrameProcessor for Mirror {
+    fn name(&self) -> &str {
+        "mirror"
+    }
+
+    fn process(&mut self, frame: &mut RgbaFrame) {
+        let row_len = frame.width as usize * 4;
+        if row_len == 0 {
+            return;
+        }
+        frame.pixels.par_chunks_exact_mut(row_len).for_each(|row| {
+            let (mut left, mut right) = (0, row_len - 4);
+            while left < right {
+                for channel in 0..4 {
+                    row.swap(left + channel, right + channel);
+                }
+                left += 4;
+                right -= 4;
+            }
+        });
+    }
+}
+
+/// Replaces colors with their Rec. 601 luma
+pub struct Grayscale;
+
+impl FrameProcessor for Grayscale {
+    fn name(&self) -> &str {
+        "grayscale"
+    }
+
+    fn process(&mut self, frame: &mut RgbaFrame) {
+        frame.pixels.par_chunks_exact_mut(4).for_each(|pixel| {
+            let luma = (77 * pixel[0] as u32 + 150 * pixel[1] as u32 + 29 * pixel[2] as u32) >> 8;
+            pixel[0] = luma as u8;
+            pixel[1] = luma as u8;
+            pixel[2] = luma as u8;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame_pixels(chain: &mut ProcessorChain, pixels: &mut [u8]) {
+        let run = chain.snapshot();
+        let timings = run.process(&mut RgbaFrame {
+            pixels,
+            width: 2,
+            height: 1,
+        });
+        chain.record_timings(timings);
+    }
+
+    #[test]
+    fn only_enabled_processors_run() {
+        let mut chain = ProcessorChain::with_builtins();
+        chain.set_enabled("mirror", true).unwrap();
+        let mut pixels = [1, 2, 3, 255, 4, 5, 6, 255];
+
+        frame_pixels(&mut chain, &mut pixels);
+
+        assert_eq!(pixels, [4, 5, 6, 255, 1, 2, 3, 255]);
+        assert_eq!(chain.slots[0].timings.len(), 1);
+        assert!(chain.slots[1].timings.is_empty());
+    }
+
+    #[test]
+    fn timings_of_a_processor_disabled_mid_frame_are_dropped() {
+        let mut chain = ProcessorChain::with_builtins();
+        chain.set_enabled("grayscale", true).unwrap();
+        let run = chain.snapshot();
+
+        chain.set_enabled("grayscale", false).unwrap();
+        let timings = run.process(&mut RgbaFrame {
+            pixels: &mut [0; 4],
+            width: 1,
+            height: 1,
+        });
+        chain.record_timings(timings);
+
+        assert!(chain.slots[1].timings.is_empty());
+    }
+}
@@ -0,0 +1,440 @@
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const MAX_REPLAY_SECONDS: f32 = 120.0;
+/// Frame rate written to clips too short to measure one
+const DEFAULT_CLIP_FRAME_RATE: f64 = 30.0;
+
+/// How much of the recent capture is kept for replays
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ReplaySettings {
+    /// Frames are only copied into the buffer while enabled
+    pub enabled: bool,
+    pub seconds: f32,
+    /// Older frames are dropped early to stay under this size
+    pub max_megabytes: u32,
+}
+
+impl Default for ReplaySettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            seconds: 10.0,
+            max_megabytes: 512,
+        }
+    }
+}
+
+impl ReplaySettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.seconds > 0.0 && self.seconds <= MAX_REPLAY_SECONDS) {
+            return Err(format!(
+                "Replay length must be above 0 and at most {MAX_REPLAY_SECONDS} seconds"
+            ));
+        }
+        if self.max_megabytes == 0 {
+            return Err("Replay memory limit must not be zero".to_string());
+        }
+        Ok(())
+    }
+
+    fn max_bytes(&self) -> usize {
+        self.max_megabytes as usize * 1024 * 1024
+    }
+}
+
+/// Camera frame as captured, YUYV at two bytes per pixel
+pub struct ReplayFrame {
+    pub yuyv: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    pub captured_at: Instant,
+    pub captured_at_local: DateTime<Local>,
+}
+
+/// Frames of the last `ReplaySettings::seconds`, oldest first
+#[derive(Default)]
+pub struct ReplayBuffer {
+    frames: VecDeque<Arc<ReplayFrame>>,
+    bytes: usize,
+    settings: ReplaySettings,
+}
+
+impl ReplayBuffer {
+    pub fn new(settings: ReplaySettings) -> Self {
+        Self {
+            settings,
+            ..Default::default()
+        }
+    }
+
+    /// Adds the newest frame, dropping the ones that fell out of the window or the memory
+    /// limit. The newest frame is always kept
+    pub fn push(&mut self, frame: ReplayFrame) {
+        self.bytes += frame.yuyv.len();
+        self.frames.push_back(Arc::new(frame));
+        self.evict();
+    }
+
+    /// Disabling replays frees the buffered frames
+    pub fn set_settings(&mut self, settings: ReplaySettings) {
+        self.settings = settings;
+        if !settings.enabled {
+            self.frames.clear();
+            self.bytes = 0;
+        }
+        self.evict();
+    }
+
+    pub fn frames(&self) -> Vec<Arc<ReplayFrame>> {
+        self.frames.iter().cloned().collect()
+    }
+
+    pub fn info(&self) -> ReplayInfo {
+        ReplayInfo {
+            settings: self.settings,
+            frames: self.frames.len(),
+            seconds: span(self.frames.iter()).as_secs_f32(),
+            megabytes: self.bytes as f32 / (1024.0 * 1024.0),
+        }
+    }
+
+    fn evict(&mut self) {
+        let Some(newest) = self.frames.back().map(|frame| frame.captured_at) else {
+            return;
+        };
+        let window = Duration::from_secs_f32(self.settings.seconds);
+        while self.frames.len() > 1 {
+            let oldest = &self.frames[0];
+            if newest.duration_since(oldest.captured_at) <= window
+                && self.bytes <= self.settings.max_bytes()
+            {
+                break;
+            }
+            self.bytes -= oldest.yuyv.len();
+            self.frames.pop_front();
+        }
+    }
+}
+
+fn span<'a>(mut frames: impl DoubleEndedIterator<Item = &'a Arc<ReplayFrame>>) -> Duration {
+    match (frames.next(), frames.next_back()) {
+        (Some(first), Some(last)) => last.captured_at.duration_since(first.captured_at),
+        _ => Duration::ZERO,
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayInfo {
+    pub settings: ReplaySettings,
+    pub frames: usize,
+    /// Time between the oldest and the newest buffered frame
+    pub seconds: f32,
+    pub megabytes: f32,
+}
+
+/// Past frame the display is paused on
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FrozenFrameInfo {
+    /// Position in the frozen buffer, 0 being the oldest frame
+    pub index: usize,
+    pub frames: usize,
+    /// How long before the most recent frozen frame it was captured
+    pub seconds_back: f32,
+    pub captured_at: DateTime<Local>,
+}
+
+/// The buffer as it was when the display was frozen, so scrubbing isn't disturbed by eviction
+struct FrozenReplay {
+    frames: Vec<Arc<ReplayFrame>>,
+    index: usize,
+}
+
+/// Recent frames for instant replays, and the frame the display is frozen on, if any
+#[derive(Default)]
+pub struct Replay {
+    buffer: Mutex<ReplayBuffer>,
+    frozen: Mutex<Option<FrozenReplay>>,
+}
+
+impl Replay {
+    pub fn is_enabled(&self) -> bool {
+        self.buffer.lock().unwrap().settings.enabled
+    }
+
+    pub fn push(&self, frame: ReplayFrame) {
+        self.buffer.lock().unwrap().push(frame);
+    }
+
+    pub fn frames(&self) -> Vec<Arc<ReplayFrame>> {
+        self.buffer.lock().unwrap().frames()
+    }
+
+    pub fn info(&self) -> ReplayInfo {
+        self.buffer.lock().unwrap().info()
+    }
+
+    pub fn set_settings(&self, settings: ReplaySettings) -> Result<(), String> {
+        settings.validate()?;
+        self.buffer.lock().unwrap().set_settings(settings);
+        Ok(())
+    }
+
+    /// Frame shown instead of the live one while frozen
+    pub fn frozen_frame(&self) -> Option<Arc<ReplayFrame>> {
+        let frozen = self.frozen.lock().unwrap();
+        frozen
+            .as_ref()
+            .map(|frozen| frozen.frames[frozen.index].clone())
+    }
+
+    /// Pauses the display on frame `index` of the buffer, the newest one by default.
+    /// The buffer is captured on the first call, later calls scrub through it
+    pub fn freeze(&self, index: Option<usize>) -> Result<FrozenFrameInfo, String> {
+        let mut frozen = self.frozen.lock().unwrap();
+        if frozen.is_none() {
+            let frames = self.frames();
+            if frames.is_empty() && !self.is_enabled() {
+                return Err("Instant replay is not enabled".to_string());
+            }
+            if frames.is_empty() {
+                return Err("No frames to freeze on yet".to_string());
+            }
+            *frozen = Some(FrozenReplay {
+                index: frames.len() - 1,
+                frames,
+            });
+        }
+        let frozen = frozen.as_mut().unwrap();
+        if let Some(index) = index {
+            if index >= frozen.frames.len() {
+                return Err(format!(
+                    "Frame {index} is out of the {} frozen frames",
+                    frozen.frames.len()
+                ));
+            }
+            frozen.index = index;
+        }
+
+        let newest = frozen.frames.last().unwrap().captured_at;
+        let shown = &frozen.frames[frozen.index];
+        Ok(FrozenFrameInfo {
+            index: frozen.index,
+            frames: frozen.frames.len(),
+            seconds_back: newest.duration_since(shown.captured_at).as_secs_f32(),
+            captured_at: shown.captured_at_local,
+        })
+    }
+
+    /// Goes back to showing live frames
+    pub fn resume(&self) {
+        *self.frozen.lock().unwrap() = None;
+    }
+}
+
+/// Writes `frames` as a YUV4MPEG2 clip, readable by ffmpeg and most players. Only the frames
+/// at the resolution of the newest one are kept. Returns the number of frames written
+pub fn write_y4m(frames: &[Arc<ReplayFrame>], out: &mut impl Write) -> io::Result<usize> {
+    let Some(newest) = frames.last() else {
+        return Err(io::Error::other("The replay buffer is empty"));
+    };
+    let (width, height) = (newest.width, newest.height);
+    let frames: Vec<&Arc<ReplayFrame>> = frames
+        .iter()
+        .filter(|frame| frame.width == width && frame.height == height)
+        .collect();
+
+    // Clips have a constant frame rate, the average of the captured one
+    let seconds = span(frames.iter().copied()).as_secs_f64();
+    let frame_rate = if frames.len() > 1 && seconds > 0.0 {
+        (frames.len() - 1) as f64 / seconds
+    } else {
+        DEFAULT_CLIP_FRAME_RATE
+    };
+    writeln!(
+        out,
+        "YUV4MPEG2 W{width} H{height} F{}:1000 Ip A1:1 C422 XCOLORRANGE=LIMITED",
+        (frame_rate * 1000.0).round() as u64
+    )?;
+
+    // YUYV is packed, Y4M wants the Y, U and V planes one after the other
+    let pixels = (width * height) as usize;
+    let mut planes = vec![0; pixels * 2];
+    for frame in &frames {
+        let (luma, chroma) = planes.split_at_mut(pixels);
+        let (u, v) = chroma.split_at_mut(pixels / 2);
+        for (i, yuyv) in frame.yuyv.chunks_exact(4).enumerate() {
+            luma[2 * i] = yuyv[0];
+            u[i] = yuyv[1];
+            luma[2 * i + 1] = yuyv[2];
+            v[i] = yuyv[3];
+        }
+        out.write_all(b"FRAME\n")?;
+        out.write_all(&planes)?;
+    }
+    Ok(frames.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(start: Instant, millis: u64, width: u32, height: u32) -> ReplayFrame {
+        ReplayFrame {
+            yuyv: vec![0; (width * height * 2) as usize],
+            width,
+            height,
+            captured_at: start + Duration::from_millis(millis),
+            captured_at_local: Local::now(),
+        }
+    }
+
+    fn buffer(seconds: f32, max_megabytes: u32) -> ReplayBuffer {
+        ReplayBuffer::new(ReplaySettings {
+            enabled: true,
+            seconds,
+            max_megabytes,
+        })
+    }
+
+    fn enabled_replay() -> Replay {
+        let replay = Replay::default();
+        replay
+            .set_settings(ReplaySettings {
+                enabled: true,
+                ..ReplaySettings::default()
+            })
+            .unwrap();
+        replay
+    }
+
+    #[test]
+    fn old_frames_are_dropped() {
+        let start = Instant::now();
+        let mut buffer = buffer(1.0, 512);
+
+        for millis in (0..=2000).step_by(100) {
+            buffer.push(frame(start, millis, 4, 2));
+        }
+
+        let info = buffer.info();
+        assert_eq!(info.frames, 11);
+        assert_eq!(info.seconds, 1.0);
+    }
+
+    #[test]
+    fn memory_limit_drops_frames_early() {
+        let start = Instant::now();
+        let mut buffer = buffer(10.0, 1);
+        // Half a megabyte each
+        for millis in 0..5 {
+            buffer.push(frame(start, millis, 512, 512));
+        }
+
+        assert_eq!(buffer.info().frames, 2);
+        assert_eq!(buffer.info().megabytes, 1.0);
+    }
+
+    #[test]
+    fn newest_frame_is_always_kept() {
+        let start = Instant::now();
+        let mut buffer = buffer(10.0, 1);
+
+        buffer.push(frame(start, 0, 2048, 1024));
+
+        assert_eq!(buffer.info().frames, 1);
+    }
+
+    #[test]
+    fn shorter_window_applies_immediately() {
+        let start = Instant::now();
+        let mut buffer = buffer(10.0, 512);
+        for millis in (0..=5000).step_by(500) {
+            buffer.push(frame(start, millis, 4, 2));
+        }
+
+        buffer.set_settings(ReplaySettings {
+            enabled: true,
+            seconds: 2.0,
+            max_megabytes: 512,
+        });
+
+        assert_eq!(buffer.info().frames, 5);
+    }
+
+    #[test]
+    fn replay_is_off_by_default() {
+        let replay = Replay::default();
+
+        assert!(!replay.is_enabled());
+        assert_eq!(
+            replay.freeze(None).unwrap_err(),
+            "Instant replay is not enabled"
+        );
+    }
+
+    #[test]
+    fn disabling_frees_the_buffered_frames() {
+        let start = Instant::now();
+        let replay = enabled_replay();
+        for millis in [0, 100, 200] {
+            replay.push(frame(start, millis, 4, 2));
+        }
+
+        replay.set_settings(ReplaySettings::default()).unwrap();
+
+        assert!(!replay.is_enabled());
+        assert_eq!(replay.info().frames, 0);
+        assert_eq!(replay.info().megabytes, 0.0);
+    }
+
+    #[test]
+    fn freezing_scrubs_a_stable_copy() {
+        let start = Instant::now();
+        let replay = enabled_replay();
+        assert!(replay.freeze(None).is_err());
+        for millis in [0, 100, 200] {
+            replay.push(frame(start, millis, 4, 2));
+        }
+
+        let frozen = replay.freeze(None).unwrap();
+        assert_eq!((frozen.index, frozen.frames), (2, 3));
+        replay.push(frame(start, 300, 4, 2));
+        let frozen = replay.freeze(Some(0)).unwrap();
+        assert_eq!((frozen.index, frozen.frames), (0, 3));
+        assert!((frozen.seconds_back - 0.2).abs() < 1e-6);
+        assert!(replay.freeze(Some(3)).is_err());
+
+        replay.resume();
+        assert!(replay.frozen_frame().is_none());
+    }
+
+    #[test]
+    fn clip_has_planar_frames_at_the_measured_rate() {
+        let start = Instant::now();
+        let mut first = frame(start, 0, 4, 1);
+        first.yuyv = vec![1, 2, 3, 4, 5, 6, 7, 8];
+        let frames = vec![
+            Arc::new(frame(start, 0, 8, 8)),
+            Arc::new(first),
+            Arc::new(frame(start, 50, 4, 1)),
+        ];
+        let mut out = Vec::new();
+
+        let written = write_y4m(&frames, &mut out).unwrap();
+
+        assert_eq!(written, 2);
+        let header = b"YUV4MPEG2 W4 H1 F20000:1000 Ip A1:1 C422 XCOLORRANGE=LIMITED\n";
+        assert!(out.starts_with(header));
+        let first_frame = &out[header.len()..header.len() + 6 + 8];
+        assert_eq!(first_frame, b"FRAME\n\x01\x03\x05\x07\x02\x06\x04\x08");
+        assert_eq!(out.len(), header.len() + 2 * (6 + 8));
+    }
+}
@@ -0,0 +1,444 @@
+use crate::app::{AppState, DisplayMode};
+use crate::preview_server::PreviewServerSettings;
+use crate::watermark::WatermarkSettings;
+use crate::webgpu::{RenderingSettings, WgpuState};
+use crate::windows_management::{self, OverlayLayout};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Manager};
+use tracing::{info, warn};
+
+pub const SETTINGS_FILE: &str = "settings.json";
+/// Bumped whenever a change to `Settings` needs a step in `migrate`
+pub const SETTINGS_VERSION: u32 = 1;
+/// Before version 1 only the overlay layout was saved, on its own
+const LEGACY_OVERLAY_LAYOUT_FILE: &str = "overlay_layout.json";
+
+/// Source the camera loop opens at startup
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CameraPreferences {
+    /// Human readable name of the camera, the first camera is used when unset or missing
+    pub device_name: Option<String>,
+    /// Highest resolution requested from the camera
+    pub max_width: u32,
+    pub max_height: u32,
+    /// Frame rate requested from the camera, the highest resolution wins when unset
+    pub frame_rate: Option<u32>,
+}
+
+impl Default for CameraPreferences {
+    fn default() -> Self {
+        Self {
+            device_name: None,
+            max_width: 1280,
+            max_height: 720,
+            frame_rate: None,
+        }
+    }
+}
+
+/// Everything restored on the next launch
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Settings {
+    pub version: u32,
+    pub display_mode: DisplayMode,
+    pub overlay_layout: OverlayLayout,
+    pub camera: CameraPreferences,
+    pub rendering: RenderingSettings,
+    pub hud_visible: bool,
+    pub watermark: WatermarkSettings,
+    pub preview_server: PreviewServerSettings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            version: SETTINGS_VERSION,
+            display_mode: DisplayMode::Thumbnail,
+            overlay_layout: OverlayLayout::default(),
+            camera: CameraPreferences::default(),
+            rendering: RenderingSettings::default(),
+            hud_visible: false,
+            watermark: WatermarkSettings::default(),
+            preview_server: PreviewServerSettings::default(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.version != SETTINGS_VERSION {
+            return Err(format!(
+                "Unsupported settings version {}, expected {}",
+                self.version, SETTINGS_VERSION
+            ));
+        }
+
+        let layout = &self.overlay_layout;
+        if !(layout.width > 0.0 && layout.width <= 1.0) {
+            return Err(format!(
+                "Overlay width must be a fraction of the window between 0 and 1, got {}",
+                layout.width
+            ));
+        }
+        if !(0.0..=1.0).contains(&layout.x) || !(0.0..=1.0).contains(&layout.y) {
+            return Err(format!(
+                "Overlay position must be within the window, got ({}, {})",
+                layout.x, layout.y
+            ));
+        }
+
+        if self.camera.max_width == 0 || self.camera.max_height == 0 {
+            return Err("Camera resolution must not be zero".to_string());
+        }
+        if self.camera.frame_rate == Some(0) {
+            return Err("Camera frame rate must not be zero".to_string());
+        }
+
+        self.rendering.validate()?;
+        self.watermark.validate()?;
+        self.preview_server.validate()
+    }
+}
+
+/// Brings a settings document from `version` up to `SETTINGS_VERSION`, one version at a time
+fn migrate(mut value: Value, mut version: u32) -> Result<Value, String> {
+    while version < SETTINGS_VERSION {
+        value = match version {
+            // The legacy overlay layout file holds a bare `OverlayLayout`
+            0 => serde_json::json!({ "overlayLayout": value }),
+            _ => return Err(format!("No migration from settings version {version}")),
+        };
+        version += 1;
+        value["version"] = Value::from(version);
+    }
+
+    if version > SETTINGS_VERSION {
+        return Err(format!(
+            "Settings were written by a newer version ({version}), this one understands up to {SETTINGS_VERSION}"
+        ));
+    }
+
+    Ok(value)
+}
+
+fn parse(bytes: &[u8], version: Option<u32>) -> Result<Settings, String> {
+    let value: Value = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+    let version = match version {
+        Some(version) => version,
+        None => value
+            .get("version")
+            .and_then(Value::as_u64)
+            .ok_or("Settings have no version")? as u32,
+    };
+
+    let settings: Settings =
+        serde_json::from_value(migrate(value, version)?).map_err(|e| e.to_string())?;
+    settings.validate()?;
+    Ok(settings)
+}
+
+/// Writes through a temporary file, so a crash never leaves a half written settings file
+fn write_atomically(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    let tmp_path = path.with_extension("json.tmp");
+    std::fs::write(&tmp_path, bytes).map_err(|e| e.to_string())?;
+    std::fs::rename(&tmp_path, path).map_err(|e| e.to_string())
+}
+
+/// Settings file and its in-memory copy, every update is validated and saved right away
+pub struct SettingsStore {
+    path: PathBuf,
+    settings: Mutex<Settings>,
+}
+
+impl SettingsStore {
+    /// Loads `settings.json` from `dir`, falling back to defaults when it is missing or invalid.
+    /// An invalid file is kept aside rather than overwritten by the next save
+    pub fn load(dir: &Path) -> Self {
+        Self::load_file(dir.join(SETTINGS_FILE))
+    }
+
+    pub fn load_file(path: PathBuf) -> Self {
+        let settings = match std::fs::read(&path) {
+            Ok(bytes) => parse(&bytes, None).unwrap_or_else(|e| {
+                let backup_path = path.with_extension("json.invalid");
+                warn!(
+                    "Ignoring invalid settings in {}, moved to {}: {}",
+                    path.display(),
+                    backup_path.display(),
+                    e
+                );
+                let _ = std::fs::rename(&path, backup_path);
+                Settings::default()
+            }),
+            Err(_) => Self::load_legacy(&path),
+        };
+
+        Self {
+            path,
+            settings: Mutex::new(settings),
+        }
+    }
+
+    /// Picks up the overlay layout saved before the settings file existed
+    fn load_legacy(path: &Path) -> Settings {
+        let Some(legacy_path) = path
+            .parent()
+            .map(|dir| dir.join(LEGACY_OVERLAY_LAYOUT_FILE))
+        else {
+            return Settings::default();
+        };
+        let Ok(bytes) = std::fs::read(&legacy_path) else {
+            return Settings::default();
+        };
+
+        match parse(&bytes, Some(0)) {
+            Ok(settings) => {
+                info!("Migrated {} to {}", legacy_path.display(), path.display());
+                settings
+            }
+            Err(e) => {
+                warn!("Ignoring invalid {}: {}", legacy_path.display(), e);
+                Settings::default()
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn get(&self) -> Settings {
+        self.settings.lock().unwrap().clone()
+    }
+
+    /// Applies `update`, then validates and saves the result. Nothing changes if either fails
+    pub fn update(&self, update: impl FnOnce(&mut Settings)) -> Result<Settings, String> {
+        let mut settings = self.settings.lock().unwrap();
+        let mut updated = settings.clone();
+        update(&mut updated);
+        updated.validate()?;
+
+        let json = serde_json::to_vec_pretty(&updated).map_err(|e| e.to_string())?;
+        write_atomically(&self.path, &json)
+            .map_err(|e| format!("Could not save {}: {e}", self.path.display()))?;
+
+        *settings = updated.clone();
+        Ok(updated)
+    }
+
+    /// Validates `settings` and hands them to `apply`, saving them only once that succeeded,
+    /// so settings that can't be applied aren't restored on the next launch either
+    pub async fn replace<F>(
+        &self,
+        settings: Settings,
+        apply: impl FnOnce(Settings) -> F,
+    ) -> Result<Settings, String>
+    where
+        F: Future<Output = Result<(), String>>,
+    {
+        settings.validate()?;
+        apply(settings.clone()).await?;
+        self.update(|current| *current = settings)
+    }
+}
+
+/// Saves a change that already took effect elsewhere in the app, so failures are only logged
+pub fn persist(app_handle: &AppHandle, update: impl FnOnce(&mut Settings)) {
+    let store = app_handle.state::<Arc<SettingsStore>>();
+    if let Err(e) = store.update(update) {
+        warn!("Could not save settings: {}", e);
+    }
+}
+
+/// Brings the running app in line with `settings`. Camera preferences apply on the next launch
+pub async fn apply(app_handle: &AppHandle, settings: &Settings) -> Result<(), String> {
+    let app_state = app_handle.state::<Arc<AppState>>().inner().clone();
+    let wgpu_state = app_handle.state::<Arc<WgpuState>>().inner().clone();
+
+    wgpu_state.check_present_mode(settings.rendering.present_mode)?;
+    wgpu_state.set_rendering_settings(settings.rendering)?;
+    wgpu_state
+        .hud_visible
+        .store(settings.hud_visible, Ordering::Relaxed);
+    wgpu_state.set_watermark(settings.watermark.clone())?;
+    app_state.preview_server.apply(settings.preview_server)?;
+
+    let layout = settings.overlay_layout;
+    let layout_changed = {
+        let mut overlay_layout = app_state.overlay_layout.lock().unwrap();
+        std::mem::replace(&mut *overlay_layout, layout) != layout
+    };
+    if layout_changed {
+        let main_thread_handle = app_handle.clone();
+        app_handle
+            .run_on_main_thread(move || windows_management::refit_overlay(&main_thread_handle))
+            .map_err(|e| e.to_string())?;
+    }
+
+    windows_management::apply_display_mode(app_handle, settings.display_mode).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::windows_management::OverlayCorner;
+
+    #[test]
+    fn missing_file_loads_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SettingsStore::load(dir.path());
+        assert_eq!(store.get(), Settings::default());
+        assert!(!dir.path().join(SETTINGS_FILE).exists());
+    }
+
+    #[test]
+    fn updates_survive_a_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SettingsStore::load(dir.path());
+        let updated = store
+            .update(|settings| {
+                settings.display_mode = DisplayMode::Floating;
+                settings.hud_visible = true;
+                settings.camera.device_name = Some("Studio Camera".to_string());
+            })
+            .unwrap();
+
+        assert_eq!(SettingsStore::load(dir.path()).get(), updated);
+    }
+
+    #[test]
+    fn writes_leave_no_temporary_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SettingsStore::load(dir.path());
+        store
+            .update(|settings| settings.hud_visible = true)
+            .unwrap();
+
+        let files: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(files, vec![SETTINGS_FILE]);
+    }
+
+    #[test]
+    fn invalid_update_is_rejected_and_not_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SettingsStore::load(dir.path());
+
+        let result = store.update(|settings| settings.overlay_layout.width = 1.5);
+
+        assert!(result.is_err());
+        assert_eq!(store.get(), Settings::default());
+        assert!(!dir.path().join(SETTINGS_FILE).exists());
+    }
+
+    #[test]
+    fn failed_apply_leaves_the_saved_settings_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SettingsStore::load(dir.path());
+        store
+            .update(|settings| settings.hud_visible = true)
+            .unwrap();
+        let saved = std::fs::read(dir.path().join(SETTINGS_FILE)).unwrap();
+
+        let replaced =
+            tauri::async_runtime::block_on(store.replace(Settings::default(), |_| async {
+                Err("Could not listen on 127.0.0.1:8765".to_string())
+            }));
+
+        assert_eq!(
+            replaced,
+            Err("Could not listen on 127.0.0.1:8765".to_string())
+        );
+        assert!(store.get().hud_visible);
+        assert_eq!(
+            std::fs::read(dir.path().join(SETTINGS_FILE)).unwrap(),
+            saved
+        );
+    }
+
+    #[test]
+    fn invalid_settings_are_not_applied() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SettingsStore::load(dir.path());
+        let invalid = Settings {
+            version: SETTINGS_VERSION + 1,
+            ..Settings::default()
+        };
+
+        let replaced = tauri::async_runtime::block_on(store.replace(invalid, |_| async {
+            panic!("invalid settings were applied")
+        }));
+
+        assert!(replaced.is_err());
+        assert!(!dir.path().join(SETTINGS_FILE).exists());
+    }
+
+    #[test]
+    fn invalid_file_is_set_aside() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(SETTINGS_FILE), b"{ not json").unwrap();
+
+        let store = SettingsStore::load(dir.path());
+
+        assert_eq!(store.get(), Settings::default());
+        assert!(dir.path().join("settings.json.invalid").exists());
+    }
+
+    #[test]
+    fn newer_version_is_not_loaded() {
+        let dir = tempfile::tempdir().unwrap();
+        let newer = serde_json::json!({ "version": SETTINGS_VERSION + 1 });
+        std::fs::write(dir.path().join(SETTINGS_FILE), newer.to_string()).unwrap();
+
+        assert_eq!(SettingsStore::load(dir.path()).get(), Settings::default());
+    }
+
+    #[test]
+    fn legacy_overlay_layout_is_migrated() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OverlayLayout {
+            corner: Some(OverlayCorner::BottomLeft),
+            x: 0.0,
+            y: 0.0,
+            width: 0.25,
+        };
+        std::fs::write(
+            dir.path().join(LEGACY_OVERLAY_LAYOUT_FILE),
+            serde_json::to_vec(&layout).unwrap(),
+        )
+        .unwrap();
+
+        let settings = SettingsStore::load(dir.path()).get();
+
+        assert_eq!(settings.version, SETTINGS_VERSION);
+        assert_eq!(settings.overlay_layout, layout);
+        assert_eq!(settings.display_mode, DisplayMode::Thumbnail);
+    }
+
+    #[test]
+    fn missing_fields_take_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        let partial = serde_json::json!({ "version": SETTINGS_VERSION, "hudVisible": true });
+        std::fs::write(dir.path().join(SETTINGS_FILE), partial.to_string()).unwrap();
+
+        let settings = SettingsStore::load(dir.path()).get();
+
+        assert!(settings.hud_visible);
+        assert_eq!(settings.rendering, RenderingSettings::default());
+        assert_eq!(settings.watermark, WatermarkSettings::default());
+        assert_eq!(settings.preview_server, PreviewServerSettings::default());
+    }
+}
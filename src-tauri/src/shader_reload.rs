@@ -0,0 +1,115 @@
+//! Development only: recompiles `shader.wgsl` from the source tree whenever it changes,
+//! so visuals can be tweaked without restarting the app
+use crate::webgpu::WgpuState;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tauri::{async_runtime, AppHandle, Manager};
+use tracing::{error, info};
+use wgpu::naga;
+
+const SHADER_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shader.wgsl");
+
+/// Editors save in bursts, polling the modification time is enough and catches atomic renames
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Watches `shader.wgsl` on a background thread for as long as the app runs
+pub fn watch(app_handle: AppHandle) {
+    std::thread::spawn(move || {
+        let path = Path::new(SHADER_PATH);
+        info!("Watching {} for changes", path.display());
+        let mut last_modified = modified(path);
+
+        loop {
+            std::thread::sleep(POLL_INTERVAL);
+            let current = modified(path);
+            if current == last_modified {
+                continue;
+            }
+            last_modified = current;
+
+            let source = match load(path) {
+                Ok(source) => source,
+                Err(e) => {
+                    error!("Keeping the previous shader, {}", e);
+                    continue;
+                }
+            };
+
+            let wgpu_state = app_handle.state::<Arc<WgpuState>>();
+            match async_runtime::block_on(wgpu_state.gpu().reload_shader(source)) {
+                Ok(()) => info!("Reloaded {}", path.display()),
+                Err(e) => error!("Keeping the previous shader, {}", e),
+            }
+        }
+    });
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Reads the shader at `path`, only returning sources that are valid and safe to reload
+fn load(path: &Path) -> Result<String, String> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    validate(&source, path)?;
+    Ok(source)
+}
+
+/// Parses and validates `source` with naga, the errors point at the offending lines
+fn validate(source: &str, path: &Path) -> Result<(), String> {
+    let module = naga::front::wgsl::parse_str(source)
+        .map_err(|e| e.emit_to_string_with_path(source, path))?;
+    naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::default(),
+    )
+    .validate(&module)
+    .map_err(|e| e.emit_to_string_with_path(source, &path.to_string_lossy()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_shader_is_valid() {
+        assert!(load(Path::new(SHADER_PATH)).is_ok());
+    }
+
+    #[test]
+    fn broken_shader_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("shader.wgsl");
+        let source = std::fs::read_to_string(SHADER_PATH).unwrap();
+        std::fs::write(&path, source.replacen("fn ", "fn fn ", 1)).unwrap();
+
+        let error = load(&path).unwrap_err();
+
+        assert!(error.contains("shader.wgsl"), "{error}");
+    }
+
+    #[test]
+    fn invalid_shader_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("shader.wgsl");
+        std::fs::write(
+            &path,
+            "@fragment\nfn fs_main() -> @location(0) vec4<f32> {\n    return vec3<f32>(1.0);\n}\n",
+        )
+        .unwrap();
+
+        assert!(load(&path).is_err());
+    }
+
+    #[test]
+    fn missing_shader_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+
+        assert!(load(&dir.path().join("shader.wgsl")).is_err());
+    }
+}
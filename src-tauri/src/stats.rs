@@ -0,0 +1,297 @@
+use crate::gpu_timing::GpuDurations;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Frames kept for the rolling percentiles, a few seconds at common camera rates
+const STATS_WINDOW: usize = 300;
+
+/// How often the render loop publishes the stats to the UI
+pub const STATS_EMIT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When a frame passed each stage of the pipeline
+#[derive(Debug, Clone, Copy)]
+pub struct FrameTimestamps {
+    /// Returned by the camera
+    pub captured: Instant,
+    /// Converted to RGBA and run through the frame processors, ready for upload
+    pub decoded: Instant,
+    /// Command buffer submitted to the GPU queue
+    pub submitted: Instant,
+    /// Handed to every surface for presentation
+    pub presented: Instant,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LatencyPercentiles {
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+}
+
+impl LatencyPercentiles {
+    pub fn from_durations(mut durations: Vec<Duration>) -> Self {
+        if durations.is_empty() {
+            return Self::default();
+        }
+        durations.sort_unstable();
+
+        // Nearest-rank percentile
+        let percentile = |p: f64| {
+            let rank = ((p / 100.0) * durations.len() as f64).ceil() as usize;
+            durations[rank.clamp(1, durations.len()) - 1].as_secs_f64() * 1000.0
+        };
+
+        Self {
+            p50_ms: percentile(50.0),
+            p95_ms: percentile(95.0),
+            p99_ms: percentile(99.0),
+        }
+    }
+}
+
+/// Snapshot of the rolling frame statistics, every latency is measured from capture
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PipelineStats {
+    /// Frames rendered since startup
+    pub frames: u64,
+    /// Frames that could not be presented to every target
+    pub dropped_frames: u64,
+    pub fps: f64,
+    pub decode: LatencyPercentiles,
+    pub submit: LatencyPercentiles,
+    /// End-to-end latency
+    pub present: LatencyPercentiles,
+    /// GPU execution times, absent when the adapter has no timestamp queries
+    pub gpu: Option<GpuStats>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GpuStats {
+    /// Absent when timestamps can't be written between passes
+    pub upload: Option<LatencyPercentiles>,
+    /// Blur, effects, watermark and burned-in overlays, absent like `upload`
+    pub processing: Option<LatencyPercentiles>,
+    pub render: LatencyPercentiles,
+}
+
+/// Rolling window of frame timestamps, fed by the render loop
+#[derive(Default)]
+pub struct PipelineStatsRecorder {
+    window: VecDeque<FrameTimestamps>,
+    gpu_window: VecDeque<GpuDurations>,
+    frames: u64,
+    dropped_frames: u64,
+}
+
+impl PipelineStatsRecorder {
+    pub fn record(&mut self, timestamps: FrameTimestamps) {
+        if self.window.len() == STATS_WINDOW {
+            self.window.pop_front();
+        }
+        self.window.push_back(timestamps);
+        self.frames += 1;
+    }
+
+    pub fn record_dropped(&mut self) {
+        self.dropped_frames += 1;
+    }
+
+    /// Time between consecutive presented frames, oldest first
+    pub fn frame_times(&self) -> Vec<Duration> {
+        self.window
+            .iter()
+            .zip(self.window.iter().skip(1))
+            .map(|(previous, frame)| frame.presented.duration_since(previous.presented))
+            .collect()
+    }
+
+    pub fn record_gpu(&mut self, durations: GpuDurations) {
+        if self.gpu_window.len() == STATS_WINDOW {
+            self.gpu_window.pop_front();
+        }
+        self.gpu_window.push_back(durations);
+    }
+
+    pub fn snapshot(&self) -> PipelineStats {
+        let latencies = |stage: fn(&FrameTimestamps) -> Instant| {
+            LatencyPercentiles::from_durations(
+                self.window
+                    .iter()
+                    .map(|frame| stage(frame).saturating_duration_since(frame.captured))
+                    .collect(),
+            )
+        };
+
+        let fps = match (self.window.front(), self.window.back()) {
+            (Some(first), Some(last)) if self.window.len() > 1 => {
+                let elapsed = last.presented.duration_since(first.presented).as_secs_f64();
+                if elapsed > 0.0 {
+                    (self.window.len() - 1) as f64 / elapsed
+                } else {
+                    0.0
+                }
+            }
+            _ => 0.0,
+        };
+
+        PipelineStats {
+            frames: self.frames,
+            dropped_frames: self.dropped_frames,
+            fps,
+            decode: latencies(|frame| frame.decoded),
+            submit: latencies(|frame| frame.submitted),
+            present: latencies(|frame| frame.presented),
+            gpu: self.gpu_snapshot(),
+        }
+    }
+
+    fn gpu_snapshot(&self) -> Option<GpuStats> {
+        if self.gpu_window.is_empty() {
+            return None;
+        }
+
+        let measured = |stage: fn(&GpuDurations) -> Option<Duration>| {
+            let durations: Vec<Duration> = self.gpu_window.iter().filter_map(stage).collect();
+            (!durations.is_empty()).then(|| LatencyPercentiles::from_durations(durations))
+        };
+
+        Some(GpuStats {
+            upload: measured(|durations| durations.upload),
+            processing: measured(|durations| durations.processing),
+            render: LatencyPercentiles::from_durations(
+                self.gpu_window
+                    .iter()
+                    .map(|durations| durations.render)
+                    .collect(),
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_ms(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{actual} != {expected}");
+    }
+
+    fn millis(values: impl IntoIterator<Item = u64>) -> Vec<Duration> {
+        values.into_iter().map(Duration::from_millis).collect()
+    }
+
+    /// A frame presented `at_ms` after `start`, `latency_ms` after it was captured and
+    /// decoded halfway through
+    fn frame(start: Instant, at_ms: u64, latency_ms: u64) -> FrameTimestamps {
+        let presented = start + Duration::from_millis(at_ms);
+        let captured = presented - Duration::from_millis(latency_ms);
+        FrameTimestamps {
+            captured,
+            decoded: captured + Duration::from_millis(latency_ms / 2),
+            submitted: presented,
+            presented,
+        }
+    }
+
+    fn gpu(upload_ms: Option<u64>, render_ms: u64) -> GpuDurations {
+        GpuDurations {
+            upload: upload_ms.map(Duration::from_millis),
+            processing: None,
+            render: Duration::from_millis(render_ms),
+        }
+    }
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let percentiles = LatencyPercentiles::from_durations(millis((1..=100).rev()));
+
+        assert_ms(percentiles.p50_ms, 50.0);
+        assert_ms(percentiles.p95_ms, 95.0);
+        assert_ms(percentiles.p99_ms, 99.0);
+    }
+
+    #[test]
+    fn few_samples_give_the_closest_ones() {
+        let empty = LatencyPercentiles::from_durations(Vec::new());
+        assert_ms(empty.p50_ms, 0.0);
+        assert_ms(empty.p99_ms, 0.0);
+
+        let single = LatencyPercentiles::from_durations(millis([7]));
+        assert_ms(single.p50_ms, 7.0);
+        assert_ms(single.p99_ms, 7.0);
+
+        let pair = LatencyPercentiles::from_durations(millis([9, 3]));
+        assert_ms(pair.p50_ms, 3.0);
+        assert_ms(pair.p95_ms, 9.0);
+    }
+
+    #[test]
+    fn snapshot_counts_frames_and_their_rate() {
+        let start = Instant::now();
+        let mut recorder = PipelineStatsRecorder::default();
+        for index in 0..11 {
+            recorder.record(frame(start, index * 100, 30));
+        }
+        recorder.record_dropped();
+        recorder.record_dropped();
+
+        let stats = recorder.snapshot();
+
+        assert_eq!(stats.frames, 11);
+        assert_eq!(stats.dropped_frames, 2);
+        assert!((stats.fps - 10.0).abs() < 1e-9, "{}", stats.fps);
+        assert_ms(stats.decode.p50_ms, 15.0);
+        assert_ms(stats.present.p99_ms, 30.0);
+        assert!(stats.gpu.is_none());
+    }
+
+    #[test]
+    fn no_rate_before_the_second_frame() {
+        let mut recorder = PipelineStatsRecorder::default();
+        assert_eq!(recorder.snapshot().fps, 0.0);
+
+        recorder.record(frame(Instant::now(), 0, 30));
+        let stats = recorder.snapshot();
+        assert_eq!(stats.fps, 0.0);
+        assert_ms(stats.present.p50_ms, 30.0);
+    }
+
+    #[test]
+    fn old_frames_leave_the_window() {
+        let start = Instant::now();
+        let mut recorder = PipelineStatsRecorder::default();
+        // Slow frames first, pushed out by the fast ones that follow
+        for index in 0..10 {
+            recorder.record(frame(start, index, 500));
+        }
+        for index in 10..STATS_WINDOW as u64 + 10 {
+            recorder.record(frame(start, index, 2));
+        }
+
+        let stats = recorder.snapshot();
+
+        assert_eq!(stats.frames, STATS_WINDOW as u64 + 10);
+        assert_ms(stats.present.p99_ms, 2.0);
+        assert_eq!(recorder.frame_times().len(), STATS_WINDOW - 1);
+    }
+
+    #[test]
+    fn gpu_stages_only_count_measured_frames() {
+        let mut recorder = PipelineStatsRecorder::default();
+        recorder.record_gpu(gpu(Some(1), 4));
+        recorder.record_gpu(gpu(None, 6));
+
+        let gpu_stats = recorder.snapshot().gpu.unwrap();
+
+        let upload = gpu_stats.upload.unwrap();
+        assert_ms(upload.p50_ms, 1.0);
+        assert_ms(upload.p99_ms, 1.0);
+        assert!(gpu_stats.processing.is_none());
+        assert_ms(gpu_stats.render.p50_ms, 4.0);
+        assert_ms(gpu_stats.render.p99_ms, 6.0);
+    }
+}
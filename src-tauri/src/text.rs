@@ -0,0 +1,452 @@
+use std::collections::HashMap;
+use std::sync::{LazyLock, RwLock};
+use wgpu::util::DeviceExt;
+
+/// Line height in layout units, text drawn at scale 1 is this many pixels tall
+pub const GLYPH_HEIGHT: u32 = 12;
+
+/// Bundled monospace font, see `fonts/LICENSE`
+const FONT_DATA: &[u8] = include_bytes!("../fonts/DejaVuSansMono.ttf");
+/// Pixel size the glyphs are rasterized at, text is scaled from it when drawn
+const ATLAS_FONT_SIZE: f32 = 48.0;
+/// Empty texels around each atlas cell, so filtering never picks up a neighbouring glyph
+const CELL_PADDING: u32 = 1;
+/// Printable ASCII is in the atlas, anything else is drawn as `?`
+const FIRST_GLYPH: char = ' ';
+const LAST_GLYPH: char = '~';
+const GLYPH_COUNT: u32 = LAST_GLYPH as u32 - FIRST_GLYPH as u32 + 1;
+/// Atlas cell that is fully covered, used for boxes and graph bars
+const SOLID_GLYPH: u32 = GLYPH_COUNT;
+/// Glyph cells followed by the solid cell, side by side in a single row
+const CELL_COUNT: u32 = GLYPH_COUNT + 1;
+
+static GLYPH_ATLAS: LazyLock<GlyphAtlas> = LazyLock::new(|| {
+    GlyphAtlas::rasterize(FONT_DATA, ATLAS_FONT_SIZE).expect("Bundled font could not be loaded")
+});
+
+fn glyph_index(c: char) -> u32 {
+    let c = if (FIRST_GLYPH..=LAST_GLYPH).contains(&c) {
+        c
+    } else {
+        '?'
+    };
+    c as u32 - FIRST_GLYPH as u32
+}
+
+/// Coverage of every glyph of the bundled font, each in a cell one advance wide and one line tall
+pub struct GlyphAtlas {
+    pub width: u32,
+    pub height: u32,
+    /// One coverage byte per texel
+    pub pixels: Vec<u8>,
+    cell_width: u32,
+    /// Glyph advance relative to the line height, the same for every glyph of a monospace font
+    advance_ratio: f32,
+}
+
+impl GlyphAtlas {
+    fn rasterize(font_data: &[u8], size: f32) -> Result<Self, String> {
+        let font = fontdue::Font::from_bytes(font_data, fontdue::FontSettings::default())
+            .map_err(|e| format!("Could not parse font: {e}"))?;
+        let line = font
+            .horizontal_line_metrics(size)
+            .ok_or("Font has no horizontal line metrics")?;
+        let advance = font.metrics('M', size).advance_width.ceil() as u32;
+        let line_height = (line.ascent - line.descent).ceil() as u32;
+        let cell_width = advance + 2 * CELL_PADDING;
+        let cell_height = line_height + 2 * CELL_PADDING;
+        let width = cell_width * CELL_COUNT;
+        let mut pixels = vec![0u8; (width * cell_height) as usize];
+
+        for (cell, c) in (FIRST_GLYPH..=LAST_GLYPH).enumerate() {
+            let (metrics, coverage) = font.rasterize(c, size);
+            if metrics.width == 0 {
+                continue;
+            }
+            // Glyphs share the baseline, `ymin` is how far the glyph's bottom is above it
+            let left = metrics.xmin;
+            let top = line.ascent.round() as i32 - metrics.ymin - metrics.height as i32;
+            for (row, coverage_row) in coverage.chunks_exact(metrics.width).enumerate() {
+                for (column, &value) in coverage_row.iter().enumerate() {
+                    let (x, y) = (left + column as i32, top + row as i32);
+                    // Anything past the advance or the line is clipped, keeping the padding empty
+                    if !(0..advance as i32).contains(&x) || !(0..line_height as i32).contains(&y) {
+                        continue;
+                    }
+                    let x = cell * cell_width as usize + (CELL_PADDING as i32 + x) as usize;
+                    let y = (CELL_PADDING as i32 + y) as usize;
+                    pixels[y * width as usize + x] = value;
+                }
+            }
+        }
+        // Padding included, so box edges stay sharp when filtered
+        let solid_start = (SOLID_GLYPH * cell_width) as usize;
+        for row in pixels.chunks_exact_mut(width as usize) {
+            row[solid_start..].fill(255);
+        }
+
+        Ok(Self {
+            width,
+            height: cell_height,
+            pixels,
+            cell_width,
+            advance_ratio: advance as f32 / line_height as f32,
+        })
+    }
+
+    /// Coverage of the texel at `x`, `y` within atlas cell `cell`, padding included
+    #[cfg(test)]
+    fn coverage(&self, cell: u32, x: u32, y: u32) -> u8 {
+        self.pixels[(y * self.width + cell * self.cell_width + x) as usize]
+    }
+}
+
+/// Horizontal distance between glyphs drawn at `scale`
+fn glyph_advance(scale: f32) -> f32 {
+    GLYPH_HEIGHT as f32 * scale * GLYPH_ATLAS.advance_ratio
+}
+
+/// One glyph or solid box, drawn as an instanced quad
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TextQuad {
+    /// Left, top, right, bottom in NDC space
+    pub rect: [f32; 4],
+    pub color: [f32; 4],
+    pub glyph: u32,
+    _padding: [u32; 3],
+}
+
+/// Builds text quads for a target of a given size, positions are in pixels from the top left
+pub struct TextLayout {
+    target_width: f32,
+    target_height: f32,
+    pub quads: Vec<TextQuad>,
+}
+
+impl TextLayout {
+    pub fn new(target_width: u32, target_height: u32) -> Self {
+        Self {
+            target_width: target_width.max(1) as f32,
+            target_height: target_height.max(1) as f32,
+            quads: Vec::new(),
+        }
+    }
+
+    /// Size in pixels of `text` drawn with glyphs scaled by `scale`
+    pub fn measure(text: &str, scale: f32) -> (f32, f32) {
+        let columns = text.chars().count() as f32;
+        (columns * glyph_advance(scale), GLYPH_HEIGHT as f32 * scale)
+    }
+
+    pub fn push_rect(&mut self, x: f32, y: f32, width: f32, height: f32, color: [f32; 4]) {
+        self.push_quad(x, y, width, height, color, SOLID_GLYPH);
+    }
+
+    /// Draws `text` on a single line with its top left corner at `x`, `y`
+    pub fn push_text(&mut self, x: f32, y: f32, text: &str, scale: f32, color: [f32; 4]) {
+        let advance = glyph_advance(scale);
+        for (i, c) in text.chars().enumerate() {
+            if c == ' ' {
+                continue;
+            }
+            self.push_quad(
+                x + i as f32 * advance,
+                y,
+                advance,
+                GLYPH_HEIGHT as f32 * scale,
+                color,
+                glyph_index(c),
+            );
+        }
+    }
+
+    fn push_quad(&mut self, x: f32, y: f32, width: f32, height: f32, color: [f32; 4], glyph: u32) {
+        // Snap to whole pixels so the glyphs stay crisp
+        let (x, y) = (x.round(), y.round());
+        let to_ndc_x = |px: f32| px / self.target_width * 2.0 - 1.0;
+        let to_ndc_y = |py: f32| 1.0 - py / self.target_height * 2.0;
+
+        self.quads.push(TextQuad {
+            rect: [
+                to_ndc_x(x),
+                to_ndc_y(y),
+                to_ndc_x(x + width.round()),
+                to_ndc_y(y + height.round()),
+            ],
+            color,
+            glyph,
+            _padding: [0; 3],
+        });
+    }
+}
+
+/// Second pipeline drawing text quads on top of the camera frame
+pub struct TextRenderer {
+    device: wgpu::Device,
+    shader: wgpu::ShaderModule,
+    pipeline_layout: wgpu::PipelineLayout,
+    atlas_bind_group: wgpu::BindGroup,
+    pipelines: RwLock<HashMap<wgpu::TextureFormat, wgpu::RenderPipeline>>,
+}
+
+impl TextRenderer {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("text.wgsl"));
+
+        let atlas = &*GLYPH_ATLAS;
+        let atlas_texture = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("glyph_atlas"),
+                sample_count: 1,
+                mip_level_count: 1,
+                size: wgpu::Extent3d {
+                    width: atlas.width,
+                    height: atlas.height,
+                    depth_or_array_layers: 1,
+                },
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::R8Unorm,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::LayerMajor,
+            &atlas.pixels,
+        );
+        // Glyphs are magnified or shrunk from the atlas size
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("glyph_sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("glyph_atlas_bind_group_layout"),
+        });
+
+        let atlas_view = atlas_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let atlas_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&atlas_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+            label: Some("glyph_atlas_bind_group"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("text_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            immediate_size: 0,
+        });
+
+        Self {
+            device: device.clone(),
+            shader,
+            pipeline_layout,
+            atlas_bind_group,
+            pipelines: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Draws `quads` into the current pass of a target rendering in `format`
+    pub fn draw(
+        &self,
+        rpass: &mut wgpu::RenderPass,
+        format: wgpu::TextureFormat,
+        quads: &[TextQuad],
+    ) {
+        if quads.is_empty() {
+            return;
+        }
+
+        let instance_buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("text_quads"),
+                contents: bytemuck::cast_slice(quads),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+
+        rpass.set_pipeline(&self.pipeline(format));
+        rpass.set_bind_group(0, &self.atlas_bind_group, &[]);
+        rpass.set_vertex_buffer(0, instance_buffer.slice(..));
+        rpass.draw(0..6, 0..quads.len() as u32);
+    }
+
+    fn pipeline(&self, format: wgpu::TextureFormat) -> wgpu::RenderPipeline {
+        if let Some(pipeline) = self.pipelines.read().unwrap().get(&format) {
+            return pipeline.clone();
+        }
+
+        let pipeline = self
+            .device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("text_pipeline"),
+                layout: Some(&self.pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &self.shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[wgpu::VertexBufferLayout {
+                        array_stride: std::mem::size_of::<TextQuad>() as wgpu::BufferAddress,
+                        step_mode: wgpu::VertexStepMode::Instance,
+                        attributes: &wgpu::vertex_attr_array![
+                            0 => Float32x4,
+                            1 => Float32x4,
+                            2 => Uint32
+                        ],
+                    }],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &self.shader,
+                    entry_point: Some("fs_main"),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: wgpu::PipelineCompilationOptions {
+                        constants: &[
+                            ("CELL_COUNT", CELL_COUNT as f64),
+                            ("CELL_PADDING", CELL_PADDING as f64),
+                        ],
+                        ..Default::default()
+                    },
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                multisample: wgpu::MultisampleState::default(),
+                depth_stencil: None,
+                multiview_mask: None,
+                cache: None,
+            });
+
+        self.pipelines
+            .write()
+            .unwrap()
+            .entry(format)
+            .or_insert(pipeline)
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn printable_ascii_has_a_glyph_of_its_own() {
+        let indices: Vec<u32> = (FIRST_GLYPH..=LAST_GLYPH).map(glyph_index).collect();
+
+        assert_eq!(indices, (0..GLYPH_COUNT).collect::<Vec<_>>());
+        assert_ne!(glyph_index('a'), glyph_index('A'));
+    }
+
+    #[test]
+    fn missing_characters_are_drawn_as_question_marks() {
+        assert_eq!(glyph_index('é'), glyph_index('?'));
+        assert_eq!(glyph_index('\n'), glyph_index('?'));
+    }
+
+    #[test]
+    fn atlas_cells_hold_their_glyphs() {
+        let atlas = &*GLYPH_ATLAS;
+        let cell_height = atlas.height;
+        let covered = |c: char| {
+            (0..cell_height)
+                .any(|y| (0..atlas.cell_width).any(|x| atlas.coverage(glyph_index(c), x, y) > 0))
+        };
+
+        assert_eq!(atlas.width, atlas.cell_width * CELL_COUNT);
+        assert!(!covered(' '));
+        assert!(covered('a') && covered('A') && covered('~'));
+    }
+
+    #[test]
+    fn glyph_padding_stays_empty() {
+        let atlas = &*GLYPH_ATLAS;
+        for cell in 0..GLYPH_COUNT {
+            for y in 0..atlas.height {
+                assert_eq!(atlas.coverage(cell, 0, y), 0);
+                assert_eq!(atlas.coverage(cell, atlas.cell_width - 1, y), 0);
+            }
+            for x in 0..atlas.cell_width {
+                assert_eq!(atlas.coverage(cell, x, 0), 0);
+                assert_eq!(atlas.coverage(cell, x, atlas.height - 1), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn solid_cell_is_fully_covered() {
+        let atlas = &*GLYPH_ATLAS;
+        for y in 0..atlas.height {
+            for x in 0..atlas.cell_width {
+                assert_eq!(atlas.coverage(SOLID_GLYPH, x, y), 255);
+            }
+        }
+    }
+
+    #[test]
+    fn measure_grows_with_the_text_and_the_scale() {
+        let (width, height) = TextLayout::measure("abc", 1.0);
+
+        assert!(width > 0.0);
+        assert_eq!(height, GLYPH_HEIGHT as f32);
+        assert_eq!(TextLayout::measure("abcdef", 1.0).0, 2.0 * width);
+        assert_eq!(TextLayout::measure("abc", 2.0), (2.0 * width, 2.0 * height));
+        assert_eq!(TextLayout::measure("", 1.0).0, 0.0);
+    }
+
+    #[test]
+    fn glyphs_are_laid_out_one_advance_apart() {
+        let mut layout = TextLayout::new(1000, 100);
+        layout.push_text(0.0, 0.0, "a b", 10.0, [1.0; 4]);
+
+        // Spaces only move the pen
+        assert_eq!(layout.quads.len(), 2);
+        let [first, second] = [&layout.quads[0], &layout.quads[1]];
+        assert_eq!(first.glyph, glyph_index('a'));
+        assert_eq!(second.glyph, glyph_index('b'));
+        assert_eq!(first.rect[0], -1.0);
+        assert_eq!(first.rect[1], 1.0);
+        let advance_ndc = glyph_advance(10.0) * 2.0 / 1000.0;
+        assert!((second.rect[0] - first.rect[0] - 2.0 * advance_ndc).abs() < 0.002);
+    }
+
+    #[test]
+    fn measured_width_spans_the_laid_out_glyphs() {
+        let mut layout = TextLayout::new(1000, 100);
+        let (width, _) = TextLayout::measure("Hello, world!", 2.0);
+        layout.push_text(0.0, 0.0, "Hello, world!", 2.0, [1.0; 4]);
+
+        let right = layout.quads.last().unwrap().rect[2];
+        assert!(((right + 1.0) / 2.0 * 1000.0 - width).abs() <= 1.0);
+    }
+}
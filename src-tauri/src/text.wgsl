@@ -0,0 +1,61 @@
+// Glyph quads, one instance per character or solid box
+
+// Cells in the glyph atlas, side by side in a single row, and the empty texels around each.
+// Set by the pipeline from text.rs
+override CELL_COUNT: f32;
+override CELL_PADDING: f32;
+
+struct QuadInput {
+    // Left, top, right, bottom in NDC space
+    @location(0) rect: vec4<f32>,
+    @location(1) color: vec4<f32>,
+    // Cell in the glyph atlas
+    @location(2) glyph: u32,
+};
+
+@group(0) @binding(0) var glyph_atlas: texture_2d<f32>;
+@group(0) @binding(1) var glyph_sampler: sampler;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) @interpolate(flat) glyph: u32,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32, quad: QuadInput) -> VertexOutput {
+    var out: VertexOutput;
+
+    // Two triangles forming a quad, uv (0, 0) is the top left corner
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(1.0, 1.0)
+    );
+
+    let corner = corners[in_vertex_index];
+    let x = mix(quad.rect.x, quad.rect.z, corner.x);
+    let y = mix(quad.rect.y, quad.rect.w, corner.y);
+
+    out.position = vec4<f32>(x, y, 0.0, 1.0);
+    out.uv = corner;
+    out.color = quad.color;
+    out.glyph = quad.glyph;
+
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let atlas_size = vec2<f32>(textureDimensions(glyph_atlas));
+    let cell_width = atlas_size.x / CELL_COUNT;
+    // The quad covers the cell without its padding
+    let cell_size = vec2<f32>(cell_width, atlas_size.y) - 2.0 * CELL_PADDING;
+    let texel = vec2<f32>(f32(in.glyph) * cell_width, 0.0) + CELL_PADDING + in.uv * cell_size;
+    let coverage = textureSample(glyph_atlas, glyph_sampler, texel / atlas_size).r;
+    return vec4<f32>(in.color.rgb, in.color.a * coverage);
+}
@@ -0,0 +1,541 @@
+use crate::image::{self, FrameReadback, RgbaImage};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Capture stops when the output disk has less space left than this
+const MIN_FREE_SPACE: u64 = 512 * 1024 * 1024;
+/// Snapshots read back from the GPU and waiting to be written, more are skipped
+const MAX_QUEUED_SNAPSHOTS: usize = 8;
+const MAX_PLAYBACK_FRAME_RATE: u32 = 120;
+
+/// Time or frames between two snapshots
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CaptureInterval {
+    Seconds(f32),
+    Frames(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TimelapseOutput {
+    /// Numbered PNGs, `snapshot-000001.png` onwards
+    ImageSequence,
+    /// A single Y4M clip playing the snapshots back at `playback_frame_rate`
+    Recording,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimelapseSettings {
+    pub interval: CaptureInterval,
+    pub output: TimelapseOutput,
+    /// Created if missing
+    pub directory: PathBuf,
+    /// Stops after this many snapshots
+    pub max_count: Option<u32>,
+    /// Stops after this long
+    pub max_seconds: Option<f32>,
+    pub playback_frame_rate: u32,
+}
+
+impl TimelapseSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        match self.interval {
+            CaptureInterval::Seconds(seconds) if seconds <= 0.0 => {
+                return Err("Snapshot interval must be above 0 seconds".to_string());
+            }
+            CaptureInterval::Frames(0) => {
+                return Err("Snapshot interval must be at least 1 frame".to_string());
+            }
+            _ => {}
+        }
+        if self.max_count == Some(0) {
+            return Err("Snapshot count limit must not be zero".to_string());
+        }
+        if self.max_seconds.is_some_and(|seconds| seconds <= 0.0) {
+            return Err("Time-lapse duration limit must be above 0 seconds".to_string());
+        }
+        if !(1..=MAX_PLAYBACK_FRAME_RATE).contains(&self.playback_frame_rate) {
+            return Err(format!(
+                "Playback frame rate must be between 1 and {MAX_PLAYBACK_FRAME_RATE}"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Decides which frames are snapshotted, the first one always is
+#[derive(Debug)]
+struct Schedule {
+    interval: CaptureInterval,
+    last: Option<(Instant, u64)>,
+}
+
+impl Schedule {
+    fn due(&mut self, now: Instant, frame_number: u64) -> bool {
+        let due = match (self.last, self.interval) {
+            (None, _) => true,
+            (Some((last, _)), CaptureInterval::Seconds(seconds)) => {
+                now.duration_since(last) >= Duration::from_secs_f32(seconds)
+            }
+            (Some((_, last)), CaptureInterval::Frames(frames)) => {
+                frame_number.saturating_sub(last) >= frames as u64
+            }
+        };
+        if due {
+            self.last = Some((now, frame_number));
+        }
+        due
+    }
+}
+
+/// Shared between a run and its writer thread
+#[derive(Default)]
+struct Progress {
+    written: AtomicU32,
+    /// Set by the writer when it gives up
+    stopped: AtomicBool,
+    error: Mutex<Option<String>>,
+}
+
+struct TimelapseRun {
+    settings: TimelapseSettings,
+    started_at: Instant,
+    schedule: Schedule,
+    captured: u32,
+    /// Dropped when the run stops, the writer then finishes the queued snapshots
+    snapshots: Option<flume::Sender<FrameReadback>>,
+    progress: Arc<Progress>,
+}
+
+impl TimelapseRun {
+    fn is_running(&self) -> bool {
+        self.snapshots.is_some() && !self.progress.stopped.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimelapseStatus {
+    pub running: bool,
+    /// Settings of the current or last run
+    pub settings: Option<TimelapseSettings>,
+    /// Snapshots taken from the pipeline
+    pub captured: u32,
+    /// Snapshots saved to disk
+    pub written: u32,
+    /// Why the last run stopped early
+    pub error: Option<String>,
+}
+
+/// Interval capture of the processed frames, written to disk by a thread of its own so the
+/// live preview only pays for a texture copy
+#[derive(Default)]
+pub struct Timelapse {
+    run: Mutex<Option<TimelapseRun>>,
+}
+
+impl Timelapse {
+    pub fn start(&self, settings: TimelapseSettings) -> Result<TimelapseStatus, String> {
+        settings.validate()?;
+        let mut run = self.run.lock().unwrap();
+        if run.as_ref().is_some_and(TimelapseRun::is_running) {
+            return Err("A time-lapse is already running".to_string());
+        }
+
+        std::fs::create_dir_all(&settings.directory)
+            .map_err(|e| format!("Failed to create {}: {e}", settings.directory.display()))?;
+        check_free_space(&settings.directory)?;
+        let mut writer = SnapshotWriter::new(&settings)?;
+
+        let (tx, rx) = flume::bounded::<FrameReadback>(MAX_QUEUED_SNAPSHOTS);
+        let progress = Arc::new(Progress::default());
+        let writer_progress = progress.clone();
+        let directory = settings.directory.clone();
+        std::thread::Builder::new()
+            .name("timelapse-writer".to_string())
+            .spawn(move || {
+                let result = rx.iter().try_for_each(|readback| {
+                    check_free_space(&directory)?;
+                    writer.write(&readback.into_image())?;
+                    writer_progress.written.fetch_add(1, Ordering::Relaxed);
+                    Ok(())
+                });
+                if let Err(e) = result.and_then(|()| writer.finish()) {
+                    warn!("Time-lapse stopped: {}", e);
+                    *writer_progress.error.lock().unwrap() = Some(e);
+                }
+                writer_progress.stopped.store(true, Ordering::Relaxed);
+            })
+            .map_err(|e| format!("Could not start the time-lapse writer: {e}"))?;
+
+        info!("Time-lapse started in {}", settings.directory.display());
+        *run = Some(TimelapseRun {
+            schedule: Schedule {
+                interval: settings.interval,
+                last: None,
+            },
+            settings,
+            started_at: Instant::now(),
+            captured: 0,
+            snapshots: Some(tx),
+            progress,
+        });
+        Ok(status(run.as_ref()))
+    }
+
+    /// Stops taking snapshots, the ones already taken are still written
+    pub fn stop(&self) -> TimelapseStatus {
+        let mut run = self.run.lock().unwrap();
+        if let Some(run) = run.as_mut() {
+            if run.snapshots.take().is_some() {
+                info!("Time-lapse stopped after {} snapshots", run.captured);
+            }
+        }
+        status(run.as_ref())
+    }
+
+    pub fn status(&self) -> TimelapseStatus {
+        status(self.run.lock().unwrap().as_ref())
+    }
+
+    /// Whether a run is waiting for snapshots, frames have to be rendered for it even when
+    /// nothing is on screen
+    pub fn is_running(&self) -> bool {
+        self.run
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(TimelapseRun::is_running)
+    }
+
+    /// Whether frame `frame_number` should be snapshotted, called once per rendered frame.
+    /// Stops the run once it reaches its limits
+    pub fn due(&self, frame_number: u64) -> bool {
+        let mut run = self.run.lock().unwrap();
+        let Some(run) = run.as_mut().filter(|run| run.is_running()) else {
+            return false;
+        };
+
+        let now = Instant::now();
+        let settings = &run.settings;
+        let count_reached = settings.max_count.is_some_and(|max| run.captured >= max);
+        let time_reached = settings
+            .max_seconds
+            .is_some_and(|max| now.duration_since(run.started_at) >= Duration::from_secs_f32(max));
+        if count_reached || time_reached {
+            info!("Time-lapse finished after {} snapshots", run.captured);
+            run.snapshots = None;
+            return false;
+        }
+
+        let due = run.schedule.due(now, frame_number);
+        if due {
+            run.captured += 1;
+        }
+        due
+    }
+
+    /// Hands a snapshot over to the writer once it has been read back from the GPU
+    pub fn submit(&self, readback: FrameReadback) {
+        let Some(snapshots) = self
+            .run
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|run| run.snapshots.clone())
+        else {
+            return;
+        };
+        readback.map(move |result| match result {
+            Ok(readback) => {
+                if snapshots.try_send(readback).is_err() {
+                    warn!("Time-lapse writer is falling behind, snapshot skipped");
+                }
+            }
+            Err(e) => warn!("Could not read a snapshot back: {}", e),
+        });
+    }
+}
+
+fn status(run: Option<&TimelapseRun>) -> TimelapseStatus {
+    match run {
+        Some(run) => TimelapseStatus {
+            running: run.is_running(),
+            settings: Some(run.settings.clone()),
+            captured: run.captured,
+            written: run.progress.written.load(Ordering::Relaxed),
+            error: run.progress.error.lock().unwrap().clone(),
+        },
+        None => TimelapseStatus {
+            running: false,
+            settings: None,
+            captured: 0,
+            written: 0,
+            error: None,
+        },
+    }
+}
+
+fn check_free_space(directory: &Path) -> Result<(), String> {
+    let available = fs4::available_space(directory).map_err(|e| {
+        format!(
+            "Could not check the free space of {}: {e}",
+            directory.display()
+        )
+    })?;
+    if available < MIN_FREE_SPACE {
+        return Err(format!(
+            "Less than {} MB left on the disk of {}",
+            MIN_FREE_SPACE / (1024 * 1024),
+            directory.display()
+        ));
+    }
+    Ok(())
+}
+
+enum SnapshotWriter {
+    Images {
+        directory: PathBuf,
+        count: u32,
+    },
+    /// The header is written with the first snapshot, which sets the clip resolution
+    Recording {
+        path: PathBuf,
+        out: BufWriter<File>,
+        size: Option<(u32, u32)>,
+        frame_rate: u32,
+    },
+}
+
+impl SnapshotWriter {
+    fn new(settings: &TimelapseSettings) -> Result<Self, String> {
+        Ok(match settings.output {
+            TimelapseOutput::ImageSequence => Self::Images {
+                directory: settings.directory.clone(),
+                count: 0,
+            },
+            TimelapseOutput::Recording => {
+                let path = settings.directory.join(format!(
+                    "timelapse-{}.y4m",
+                    Local::now().format("%Y%m%d-%H%M%S")
+                ));
+                let file = File::create(&path)
+                    .map_err(|e| format!("Failed to create {}: {e}", path.display()))?;
+                Self::Recording {
+                    path,
+                    out: BufWriter::new(file),
+                    size: None,
+                    frame_rate: settings.playback_frame_rate,
+                }
+            }
+        })
+    }
+
+    fn write(&mut self, snapshot: &RgbaImage) -> Result<(), String> {
+        match self {
+            Self::Images { directory, count } => {
+                *count += 1;
+                image::save_png(
+                    snapshot,
+                    &directory.join(format!("snapshot-{count:06}.png")),
+                )
+            }
+            Self::Recording {
+                path,
+                out,
+                size,
+                frame_rate,
+            } => {
+                let frame_size = (snapshot.width, snapshot.height);
+                let result = match *size {
+                    None => {
+                        *size = Some(frame_size);
+                        writeln!(
+                            out,
+                            "YUV4MPEG2 W{} H{} F{frame_rate}:1 Ip A1:1 C444 XCOLORRANGE=LIMITED",
+                            snapshot.width, snapshot.height
+                        )
+                    }
+                    Some(size) if size != frame_size => {
+                        warn!("Skipping a snapshot at a different resolution than the clip");
+                        return Ok(());
+                    }
+                    Some(_) => Ok(()),
+                };
+                result
+                    .and_then(|()| out.write_all(b"FRAME\n"))
+                    .and_then(|()| out.write_all(&rgba_to_yuv444(&snapshot.pixels)))
+                    .map_err(|e| format!("Failed to write {}: {e}", path.display()))
+            }
+        }
+    }
+
+    fn finish(self) -> Result<(), String> {
+        match self {
+            Self::Images { .. } => Ok(()),
+            Self::Recording { path, mut out, .. } => out
+                .flush()
+                .map_err(|e| format!("Failed to write {}: {e}", path.display())),
+        }
+    }
+}
+
+/// Limited range BT.601 Y, U and V planes, the inverse of `camera::yuyv_to_rgba`
+fn rgba_to_yuv444(rgba: &[u8]) -> Vec<u8> {
+    let pixels = rgba.len() / 4;
+    let mut planes = vec![0; pixels * 3];
+    let (luma, chroma) = planes.split_at_mut(pixels);
+    let (u, v) = chroma.split_at_mut(pixels);
+    for (i, pixel) in rgba.chunks_exact(4).enumerate() {
+        let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(f32::from);
+        luma[i] = (16.0 + (65.738 * r + 129.057 * g + 25.064 * b) / 256.0).round() as u8;
+        u[i] = (128.0 + (-37.945 * r - 74.494 * g + 112.439 * b) / 256.0).round() as u8;
+        v[i] = (128.0 + (112.439 * r - 94.154 * g - 18.285 * b) / 256.0).round() as u8;
+    }
+    planes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(directory: &Path, output: TimelapseOutput) -> TimelapseSettings {
+        TimelapseSettings {
+            interval: CaptureInterval::Seconds(1.0),
+            output,
+            directory: directory.to_path_buf(),
+            max_count: None,
+            max_seconds: None,
+            playback_frame_rate: 30,
+        }
+    }
+
+    fn snapshot(width: u32, height: u32, rgb: [u8; 3]) -> RgbaImage {
+        RgbaImage {
+            width,
+            height,
+            pixels: [rgb[0], rgb[1], rgb[2], 255].repeat((width * height) as usize),
+        }
+    }
+
+    #[test]
+    fn frame_interval_counts_frames() {
+        let now = Instant::now();
+        let mut schedule = Schedule {
+            interval: CaptureInterval::Frames(3),
+            last: None,
+        };
+
+        let due: Vec<u64> = (10..20).filter(|&frame| schedule.due(now, frame)).collect();
+
+        assert_eq!(due, vec![10, 13, 16, 19]);
+    }
+
+    #[test]
+    fn time_interval_waits_for_the_interval() {
+        let start = Instant::now();
+        let mut schedule = Schedule {
+            interval: CaptureInterval::Seconds(0.5),
+            last: None,
+        };
+
+        let due: Vec<u64> = (0..10)
+            .filter(|&frame| schedule.due(start + Duration::from_millis(frame * 200), frame))
+            .collect();
+
+        assert_eq!(due, vec![0, 3, 6, 9]);
+    }
+
+    #[test]
+    fn settings_are_validated() {
+        let dir = Path::new("snapshots");
+        let valid = settings(dir, TimelapseOutput::ImageSequence);
+        assert!(valid.validate().is_ok());
+
+        for invalid in [
+            TimelapseSettings {
+                interval: CaptureInterval::Seconds(0.0),
+                ..valid.clone()
+            },
+            TimelapseSettings {
+                interval: CaptureInterval::Frames(0),
+                ..valid.clone()
+            },
+            TimelapseSettings {
+                max_count: Some(0),
+                ..valid.clone()
+            },
+            TimelapseSettings {
+                playback_frame_rate: 0,
+                ..valid.clone()
+            },
+        ] {
+            assert!(invalid.validate().is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn stopped_run_is_not_running() {
+        let dir = tempfile::tempdir().unwrap();
+        let timelapse = Timelapse::default();
+        assert!(!timelapse.is_running());
+
+        timelapse
+            .start(settings(dir.path(), TimelapseOutput::ImageSequence))
+            .unwrap();
+        assert!(timelapse.is_running());
+
+        timelapse.stop();
+        assert!(!timelapse.is_running());
+    }
+
+    #[test]
+    fn image_sequence_is_numbered() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut writer =
+            SnapshotWriter::new(&settings(dir.path(), TimelapseOutput::ImageSequence)).unwrap();
+
+        writer.write(&snapshot(2, 2, [255, 0, 0])).unwrap();
+        writer.write(&snapshot(2, 2, [0, 255, 0])).unwrap();
+        writer.finish().unwrap();
+
+        let second = image::load_png(&dir.path().join("snapshot-000002.png")).unwrap();
+        assert_eq!(second.pixels[..4], [0, 255, 0, 255]);
+        assert!(dir.path().join("snapshot-000001.png").exists());
+    }
+
+    #[test]
+    fn recording_keeps_the_first_resolution() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut writer =
+            SnapshotWriter::new(&settings(dir.path(), TimelapseOutput::Recording)).unwrap();
+
+        writer.write(&snapshot(2, 1, [255, 255, 255])).unwrap();
+        writer.write(&snapshot(4, 4, [0, 0, 0])).unwrap();
+        writer.write(&snapshot(2, 1, [0, 0, 0])).unwrap();
+        writer.finish().unwrap();
+
+        let clip = std::fs::read_dir(dir.path())
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        let clip = std::fs::read(clip.path()).unwrap();
+        let header = b"YUV4MPEG2 W2 H1 F30:1 Ip A1:1 C444 XCOLORRANGE=LIMITED\n";
+        assert!(clip.starts_with(header));
+        let frames = &clip[header.len()..];
+        assert_eq!(frames.len(), 2 * (6 + 6));
+        // White and black at the ends of the limited range, with neutral chroma
+        assert_eq!(frames[..12], *b"FRAME\n\xeb\xeb\x80\x80\x80\x80");
+        assert_eq!(frames[12..], *b"FRAME\n\x10\x10\x80\x80\x80\x80");
+    }
+}
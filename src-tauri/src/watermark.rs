@@ -0,0 +1,375 @@
+use crate::image::RgbaImage;
+use crate::overlays::OverlayAnchor;
+use crate::webgpu::OFFSCREEN_FORMAT;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+const MAX_WATERMARK_SCALE: f32 = 10.0;
+
+/// PNG drawn into every frame, sizes and margins are in camera pixels
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct WatermarkSettings {
+    pub enabled: bool,
+    pub path: Option<PathBuf>,
+    pub anchor: OverlayAnchor,
+    /// Camera pixels per image pixel
+    pub scale: f32,
+    /// Distance from the anchor, towards the center of the frame
+    pub margin: f32,
+    pub opacity: f32,
+}
+
+impl Default for WatermarkSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: None,
+            anchor: OverlayAnchor::BottomRight,
+            scale: 1.0,
+            margin: 16.0,
+            opacity: 0.8,
+        }
+    }
+}
+
+impl WatermarkSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.scale > 0.0 && self.scale <= MAX_WATERMARK_SCALE) {
+            return Err(format!(
+                "Watermark scale must be above 0 and at most {MAX_WATERMARK_SCALE}"
+            ));
+        }
+        if self.margin < 0.0 {
+            return Err("Watermark margin can't be negative".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.opacity) {
+            return Err("Watermark opacity must be between 0 and 1".to_string());
+        }
+        if self.enabled && self.path.is_none() {
+            return Err("An enabled watermark needs an image".to_string());
+        }
+        Ok(())
+    }
+
+    /// Left, top, right, bottom in NDC space of an image of `image_size` pixels drawn into
+    /// a frame of `frame_size` pixels
+    fn rect(&self, image_size: (u32, u32), frame_size: (u32, u32)) -> [f32; 4] {
+        let (frame_width, frame_height) = (frame_size.0 as f32, frame_size.1 as f32);
+        let size = (
+            image_size.0 as f32 * self.scale,
+            image_size.1 as f32 * self.scale,
+        );
+        let (x, y) = self.anchor.place(
+            [self.margin, self.margin],
+            size,
+            (frame_width, frame_height),
+        );
+        let to_ndc_x = |x: f32| x / frame_width * 2.0 - 1.0;
+        let to_ndc_y = |y: f32| 1.0 - y / frame_height * 2.0;
+        [
+            to_ndc_x(x),
+            to_ndc_y(y),
+            to_ndc_x(x + size.0),
+            to_ndc_y(y + size.1),
+        ]
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct WatermarkUniform {
+    /// Left, top, right, bottom in NDC space
+    rect: [f32; 4],
+    opacity: f32,
+    _padding: [f32; 3],
+}
+
+/// Watermark image uploaded for the current device, with the bind group sampling it
+struct UploadedWatermark {
+    image: Arc<RgbaImage>,
+    bind_group: wgpu::BindGroup,
+}
+
+/// Blends the watermark image over the processed camera frame
+pub struct WatermarkRenderer {
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+    buffer: wgpu::Buffer,
+    /// Compared by pointer to notice a new image
+    uploaded: Mutex<Option<UploadedWatermark>>,
+}
+
+impl WatermarkRenderer {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("watermark.wgsl"));
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("watermark_bind_group_layout"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            immediate_size: 0,
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("watermark"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: OFFSCREEN_FORMAT,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            multisample: wgpu::MultisampleState::default(),
+            depth_stencil: None,
+            multiview_mask: None,
+            cache: None,
+        });
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("watermark"),
+            size: std::mem::size_of::<WatermarkUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            bind_group_layout,
+            pipeline,
+            buffer,
+            uploaded: Mutex::new(None),
+        }
+    }
+
+    /// Records the watermark being drawn into the processed camera frame `texture`
+    #[allow(clippy::too_many_arguments)]
+    pub fn apply(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        sampler: &wgpu::Sampler,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+        settings: &WatermarkSettings,
+        image: Option<&Arc<RgbaImage>>,
+    ) {
+        let Some(image) = image else {
+            return;
+        };
+        if !settings.enabled || settings.opacity <= 0.0 {
+            return;
+        }
+
+        queue.write_buffer(
+            &self.buffer,
+            0,
+            bytemuck::bytes_of(&WatermarkUniform {
+                rect: settings.rect(
+                    (image.width, image.height),
+                    (texture.width(), texture.height()),
+                ),
+                opacity: settings.opacity,
+                _padding: [0.0; 3],
+            }),
+        );
+
+        let mut uploaded = self.uploaded.lock().unwrap();
+        if !matches!(uploaded.as_ref(), Some(current) if Arc::ptr_eq(&current.image, image)) {
+            let view = image
+                .create_texture(
+                    device,
+                    queue,
+                    "watermark",
+                    wgpu::TextureFormat::Rgba8UnormSrgb,
+                )
+                .create_view(&wgpu::TextureViewDescriptor::default());
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &self.bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: self.buffer.as_entire_binding(),
+                    },
+                ],
+                label: Some("watermark_bind_group"),
+            });
+            *uploaded = Some(UploadedWatermark {
+                image: image.clone(),
+                bind_group,
+            });
+        }
+        let bind_group = &uploaded.as_ref().unwrap().bind_group;
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("watermark"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &view,
+                depth_slice: None,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+            multiview_mask: None,
+        });
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, bind_group, &[]);
+        rpass.draw(0..6, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enabled() -> WatermarkSettings {
+        WatermarkSettings {
+            enabled: true,
+            path: Some(PathBuf::from("logo.png")),
+            ..WatermarkSettings::default()
+        }
+    }
+
+    fn assert_rect(actual: [f32; 4], expected: [f32; 4]) {
+        assert!(
+            actual
+                .iter()
+                .zip(expected)
+                .all(|(a, b)| (a - b).abs() < 1e-5),
+            "{actual:?} != {expected:?}"
+        );
+    }
+
+    #[test]
+    fn bounds_are_validated() {
+        assert!(WatermarkSettings::default().validate().is_ok());
+        for valid in [
+            WatermarkSettings {
+                scale: MAX_WATERMARK_SCALE,
+                opacity: 0.0,
+                margin: 0.0,
+                ..enabled()
+            },
+            WatermarkSettings {
+                opacity: 1.0,
+                ..enabled()
+            },
+        ] {
+            assert!(valid.validate().is_ok(), "{valid:?}");
+        }
+
+        for invalid in [
+            WatermarkSettings {
+                scale: 0.0,
+                ..enabled()
+            },
+            WatermarkSettings {
+                scale: MAX_WATERMARK_SCALE + 0.1,
+                ..enabled()
+            },
+            WatermarkSettings {
+                opacity: -0.1,
+                ..enabled()
+            },
+            WatermarkSettings {
+                opacity: 1.1,
+                ..enabled()
+            },
+            WatermarkSettings {
+                margin: -1.0,
+                ..enabled()
+            },
+            WatermarkSettings {
+                path: None,
+                ..enabled()
+            },
+        ] {
+            assert!(invalid.validate().is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn image_is_placed_against_the_anchor() {
+        let bottom_right = enabled();
+        assert_rect(
+            bottom_right.rect((100, 60), (400, 200)),
+            [0.42, -0.24, 0.92, -0.84],
+        );
+
+        let top_left = WatermarkSettings {
+            anchor: OverlayAnchor::TopLeft,
+            scale: 2.0,
+            margin: 0.0,
+            ..enabled()
+        };
+        assert_rect(top_left.rect((100, 60), (400, 200)), [-1.0, 1.0, 0.0, -0.2]);
+    }
+
+    #[test]
+    fn image_larger_than_the_frame_starts_at_its_corner() {
+        let settings = WatermarkSettings {
+            scale: 4.0,
+            ..enabled()
+        };
+
+        assert_rect(
+            settings.rect((200, 100), (400, 200)),
+            [-1.0, 1.0, 3.0, -3.0],
+        );
+    }
+}
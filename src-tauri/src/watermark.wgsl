@@ -0,0 +1,44 @@
+struct Watermark {
+    // Left, top, right, bottom in NDC space
+    rect: vec4<f32>,
+    opacity: f32,
+};
+
+@group(0) @binding(0) var watermark_texture: texture_2d<f32>;
+@group(0) @binding(1) var watermark_sampler: sampler;
+@group(0) @binding(2) var<uniform> watermark: Watermark;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    // Two triangles forming a quad, in texture coordinates
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(1.0, 0.0)
+    );
+    let corner = corners[in_vertex_index];
+
+    var out: VertexOutput;
+    out.position = vec4<f32>(
+        mix(watermark.rect.x, watermark.rect.z, corner.x),
+        mix(watermark.rect.y, watermark.rect.w, corner.y),
+        0.0,
+        1.0
+    );
+    out.tex_coords = corner;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(watermark_texture, watermark_sampler, in.tex_coords);
+    return vec4<f32>(color.rgb, color.a * watermark.opacity);
+}
@@ -1,6 +1,19 @@
+use crate::blur::{BlurMask, BlurRenderer, BlurSettings};
+use crate::effects::EffectChain;
+use crate::gpu_timing::GpuTimer;
+use crate::image::{self, RgbaImage};
+use crate::overlays::TextOverlay;
+use crate::text::TextRenderer;
+use crate::watermark::{WatermarkRenderer, WatermarkSettings};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::sync::Mutex;
 use std::sync::RwLock;
-use tauri::Window;
+use std::time::{Duration, Instant};
+use tauri::{PhysicalSize, Window};
+use tracing::{error, info, warn};
 use wgpu::util::DeviceExt;
 
 #[repr(C)]
@@ -23,40 +36,362 @@ impl Default for CameraSettingsUniform {
     }
 }
 
-pub struct WgpuState {
-    pub instance: wgpu::Instance,
+impl CameraSettingsUniform {
+    /// Centered quad that fills the target as much as possible without distorting the camera
+    pub fn fit(camera_aspect: f32, target_aspect: f32) -> Self {
+        let size = if camera_aspect > target_aspect {
+            // Camera is wider than window - fit to width
+            [2.0, 2.0 / camera_aspect * target_aspect]
+        } else {
+            // Camera is taller than window - fit to height
+            [2.0 * camera_aspect / target_aspect, 2.0]
+        };
+
+        Self {
+            position: [0.0, 0.0],
+            size,
+        }
+    }
+}
+
+/// How frames are handed to the compositor, mirrors `wgpu::PresentMode`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PresentModeSetting {
+    /// Vsync, supported everywhere
+    Fifo,
+    /// Vsync without blocking, newer frames replace queued ones
+    Mailbox,
+    /// No vsync, lowest latency but may tear
+    Immediate,
+    /// Best available vsync mode
+    AutoVsync,
+    /// Best available mode without vsync
+    AutoNoVsync,
+}
+
+impl PresentModeSetting {
+    pub const ALL: [Self; 5] = [
+        Self::Fifo,
+        Self::Mailbox,
+        Self::Immediate,
+        Self::AutoVsync,
+        Self::AutoNoVsync,
+    ];
+
+    /// Whether a surface reporting `present_modes` accepts this mode.
+    /// The auto modes always are, as wgpu resolves them itself
+    pub fn is_supported(self, present_modes: &[wgpu::PresentMode]) -> bool {
+        let mode = wgpu::PresentMode::from(self);
+        matches!(
+            mode,
+            wgpu::PresentMode::AutoVsync | wgpu::PresentMode::AutoNoVsync
+        ) || present_modes.contains(&mode)
+    }
+}
+
+impl From<PresentModeSetting> for wgpu::PresentMode {
+    fn from(setting: PresentModeSetting) -> Self {
+        match setting {
+            PresentModeSetting::Fifo => wgpu::PresentMode::Fifo,
+            PresentModeSetting::Mailbox => wgpu::PresentMode::Mailbox,
+            PresentModeSetting::Immediate => wgpu::PresentMode::Immediate,
+            PresentModeSetting::AutoVsync => wgpu::PresentMode::AutoVsync,
+            PresentModeSetting::AutoNoVsync => wgpu::PresentMode::AutoNoVsync,
+        }
+    }
+}
+
+/// Presentation settings applied to every surface render target
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenderingSettings {
+    pub present_mode: PresentModeSetting,
+    /// Frames the GPU may queue ahead of presentation, lower means less latency
+    pub max_frame_latency: u32,
+}
+
+impl Default for RenderingSettings {
+    fn default() -> Self {
+        Self {
+            present_mode: PresentModeSetting::Fifo,
+            max_frame_latency: 2,
+        }
+    }
+}
+
+impl RenderingSettings {
+    pub const MAX_FRAME_LATENCY_RANGE: std::ops::RangeInclusive<u32> = 1..=3;
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !Self::MAX_FRAME_LATENCY_RANGE.contains(&self.max_frame_latency) {
+            return Err(format!(
+                "Max frame latency must be between {} and {}",
+                Self::MAX_FRAME_LATENCY_RANGE.start(),
+                Self::MAX_FRAME_LATENCY_RANGE.end()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Resolves the requested present mode against what a surface supports, falling back to Fifo.
+/// `set_rendering_settings` only accepts supported modes, but a surface added later may lack one
+pub fn negotiate_present_mode(
+    setting: PresentModeSetting,
+    present_modes: &[wgpu::PresentMode],
+) -> wgpu::PresentMode {
+    let requested = wgpu::PresentMode::from(setting);
+    if setting.is_supported(present_modes) {
+        return requested;
+    }
+    warn!(
+        "Present mode {:?} is not supported (available: {:?}), falling back to Fifo",
+        requested, present_modes
+    );
+    wgpu::PresentMode::Fifo
+}
+
+/// Name of the render target that follows the display mode
+pub const PRIMARY_RENDER_TARGET: &str = "primary";
+
+/// Format of offscreen render targets, matching the camera texture so copies need no conversion
+pub const OFFSCREEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// Format the render pipeline draws into for a target, which is the sRGB view when the
+/// surface itself isn't sRGB
+pub fn render_format(config: &wgpu::SurfaceConfiguration) -> wgpu::TextureFormat {
+    config
+        .view_formats
+        .first()
+        .copied()
+        .unwrap_or(config.format)
+}
+
+/// Picks the surface format and the format the pipeline renders in.
+/// The camera texture is sRGB, so an sRGB target is preferred to avoid washed out colors
+pub fn negotiate_surface_format(
+    formats: &[wgpu::TextureFormat],
+) -> (wgpu::TextureFormat, wgpu::TextureFormat) {
+    if let Some(format) = formats.iter().find(|format| format.is_srgb()) {
+        return (*format, *format);
+    }
+
+    let Some(&first) = formats.first() else {
+        warn!(
+            "Surface reports no formats, assuming {:?}",
+            OFFSCREEN_FORMAT
+        );
+        return (OFFSCREEN_FORMAT, OFFSCREEN_FORMAT);
+    };
+
+    // A linear surface can still be rendered through an sRGB view of the same format
+    let srgb_view = first.add_srgb_suffix();
+    if srgb_view != first {
+        info!(
+            "No sRGB surface format available, rendering to {:?} through a {:?} view",
+            first, srgb_view
+        );
+        return (first, srgb_view);
+    }
+
+    warn!(
+        "No sRGB surface format available and {:?} has no sRGB view, colors will be too dark",
+        first
+    );
+    (first, first)
+}
+
+/// Picks a compositing mode that lets transparent pixels show what is behind the window.
+/// The shader outputs opaque camera pixels over a zero clear color, which is valid for both
+/// premultiplied and straight alpha
+pub fn negotiate_alpha_mode(alpha_modes: &[wgpu::CompositeAlphaMode]) -> wgpu::CompositeAlphaMode {
+    let preferred = [
+        wgpu::CompositeAlphaMode::PreMultiplied,
+        wgpu::CompositeAlphaMode::PostMultiplied,
+        wgpu::CompositeAlphaMode::Inherit,
+    ];
+    if let Some(alpha_mode) = preferred
+        .into_iter()
+        .find(|alpha_mode| alpha_modes.contains(alpha_mode))
+    {
+        return alpha_mode;
+    }
+
+    let fallback = alpha_modes
+        .first()
+        .copied()
+        .unwrap_or(wgpu::CompositeAlphaMode::Auto);
+    warn!(
+        "Surface supports no transparent alpha mode (available: {:?}), using {:?}; background mode will not be see-through",
+        alpha_modes, fallback
+    );
+    fallback
+}
+
+/// Where a render target puts its frames
+pub enum RenderTargetOutput {
+    Surface(wgpu::Surface<'static>),
+    /// Texture that is never presented, its content can be copied out instead
+    Offscreen(wgpu::Texture),
+}
+
+/// One of the places the camera frame is drawn to, each with its own quad placement
+pub struct RenderTarget {
+    pub output: RenderTargetOutput,
+    pub camera_settings_buffer: wgpu::Buffer,
+    pub camera_settings_bind_group: wgpu::BindGroup,
+    /// Quad placement of each additional camera source, grown as sources are composited
+    pub source_camera_settings: Vec<(wgpu::Buffer, wgpu::BindGroup)>,
+}
+
+impl RenderTarget {
+    pub fn update_camera_settings(&self, queue: &wgpu::Queue, settings: &CameraSettingsUniform) {
+        queue.write_buffer(
+            &self.camera_settings_buffer,
+            0,
+            bytemuck::cast_slice(&[*settings]),
+        );
+    }
+
+    /// Places the quad of additional source `index`
+    pub fn update_source_camera_settings(
+        &mut self,
+        gpu: &GpuContext,
+        index: usize,
+        settings: &CameraSettingsUniform,
+    ) {
+        while self.source_camera_settings.len() <= index {
+            self.source_camera_settings
+                .push(gpu.create_camera_settings());
+        }
+        gpu.queue.write_buffer(
+            &self.source_camera_settings[index].0,
+            0,
+            bytemuck::cast_slice(&[*settings]),
+        );
+    }
+}
+
+/// Render targets by name, owned by the render loop
+pub type RenderTargets = HashMap<String, RenderTarget>;
+
+/// Requests handled by the render loop, which is the only owner of the render targets
+pub enum RenderCommand {
+    /// Add a render target, replacing any target with the same name.
+    /// `done` is signalled once the old target is released and the new one is configured
+    AddTarget {
+        name: String,
+        output: RenderTargetOutput,
+        size: PhysicalSize<u32>,
+        done: flume::Sender<()>,
+    },
+    RemoveTarget {
+        name: String,
+        done: flume::Sender<()>,
+    },
+    /// Clear a render target to black
+    Clear {
+        name: String,
+        done: flume::Sender<()>,
+    },
+}
+
+/// Consecutive frames with failing surfaces after which the renderer is rebuilt
+pub const SURFACE_ERROR_RECOVERY_THRESHOLD: u32 = 30;
+/// Consecutive frames with failing surfaces after which the failure is reported to the UI
+pub const SURFACE_ERROR_ESCALATION_THRESHOLD: u32 = 120;
+/// Wait after a failed renderer rebuild, doubled with every further failure
+const RECOVERY_RETRY_DELAY: Duration = Duration::from_secs(1);
+const RECOVERY_MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Spaces out renderer rebuilds that keep failing, and reports each failure episode to the
+/// UI once. An episode lasts until a frame renders again
+#[derive(Debug, Default)]
+pub struct RecoveryBackoff {
+    failures: u32,
+    next_attempt: Option<Instant>,
+    reported: bool,
+}
+
+impl RecoveryBackoff {
+    /// Whether the wait after the last failed rebuild is over
+    pub fn ready(&self, now: Instant) -> bool {
+        self.next_attempt
+            .is_none_or(|next_attempt| now >= next_attempt)
+    }
+
+    /// Records a failed rebuild and returns how long to wait before the next one
+    pub fn failed(&mut self, now: Instant) -> Duration {
+        self.failures += 1;
+        let delay = RECOVERY_RETRY_DELAY
+            .saturating_mul(1 << (self.failures - 1).min(16))
+            .min(RECOVERY_MAX_RETRY_DELAY);
+        self.next_attempt = Some(now + delay);
+        delay
+    }
+
+    /// Whether the current failure episode still has to be reported
+    pub fn report(&mut self) -> bool {
+        !std::mem::replace(&mut self.reported, true)
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Everything tied to one adapter and device, replaced as a whole when the device is lost
+pub struct GpuContext {
     pub adapter: wgpu::Adapter,
     pub queue: wgpu::Queue,
     pub device: wgpu::Device,
     pub sampler: wgpu::Sampler,
-    pub surface: RwLock<wgpu::Surface<'static>>,
-    pub render_pipeline: wgpu::RenderPipeline,
+    /// Replaced when `shader.wgsl` is hot reloaded
+    shader: RwLock<wgpu::ShaderModule>,
+    /// Source of the last hot reload, kept so it survives a device recovery
+    reloaded_shader: RwLock<Option<String>>,
+    pipeline_layout: wgpu::PipelineLayout,
+    /// Render pipelines by target format, surfaces on different monitors or backends may
+    /// negotiate different formats
+    render_pipelines: RwLock<HashMap<wgpu::TextureFormat, wgpu::RenderPipeline>>,
     pub bind_group_layout: wgpu::BindGroupLayout,
-    pub config: RwLock<wgpu::SurfaceConfiguration>,
-    pub needs_reconfigure: Mutex<bool>,
     // Camera settings
-    pub camera_settings_buffer: wgpu::Buffer,
-    pub camera_settings_bind_group: wgpu::BindGroup,
+    pub camera_settings_bind_group_layout: wgpu::BindGroupLayout,
+    /// Present when the adapter supports timestamp queries
+    pub timer: Option<GpuTimer>,
+    /// Draws the HUD and other text on top of the camera frame
+    pub text: TextRenderer,
+    /// Background blur, applied before the user effects
+    pub blur: BlurRenderer,
+    /// User post-processing shaders, applied before the frame is drawn to the targets
+    pub effects: EffectChain,
+    /// Image blended over the frame after the effects
+    pub watermark: WatermarkRenderer,
 }
 
-impl WgpuState {
-    pub async fn new(window: Window) -> Self {
-        let size = window.inner_size().unwrap();
-        let instance = wgpu::Instance::default();
-        let surface = instance.create_surface(window).unwrap();
+impl GpuContext {
+    /// Requests an adapter and device able to present to `compatible_surface` and builds
+    /// the resources shared by every render target. `device_lost` is raised if the device
+    /// goes away unexpectedly
+    async fn new(
+        instance: &wgpu::Instance,
+        compatible_surface: Option<&wgpu::Surface<'static>>,
+        device_lost: Arc<AtomicBool>,
+    ) -> Result<Self, String> {
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
                 power_preference: wgpu::PowerPreference::default(),
                 force_fallback_adapter: false,
-                compatible_surface: Some(&surface),
+                compatible_surface,
             })
             .await
-            .expect("Failed to find an appropriate adapter");
+            .map_err(|e| format!("Failed to find an appropriate adapter: {e}"))?;
 
         let (device, queue) = adapter
             .request_device(&wgpu::DeviceDescriptor {
                 label: None,
-                required_features: wgpu::Features::empty(),
+                required_features: GpuTimer::features(&adapter),
                 required_limits: wgpu::Limits::downlevel_webgl2_defaults()
                     .using_resolution(adapter.limits()),
                 experimental_features: wgpu::ExperimentalFeatures::disabled(),
@@ -64,7 +399,17 @@ impl WgpuState {
                 trace: wgpu::Trace::Off,
             })
             .await
-            .expect("Failed to create device");
+            .map_err(|e| format!("Failed to create device: {e}"))?;
+
+        device.set_device_lost_callback(move |reason, message| {
+            // Destroyed is reported when a replaced device is dropped
+            if reason == wgpu::DeviceLostReason::Unknown {
+                error!("GPU device lost: {}", message);
+                device_lost.store(true, Ordering::SeqCst);
+            }
+        });
+
+        info!("Rendering with {:?}", adapter.get_info());
 
         let shader = device.create_shader_module(wgpu::include_wgsl!("shader.wgsl"));
 
@@ -117,127 +462,933 @@ impl WgpuState {
                 label: Some("camera_settings_bind_group_layout"),
             });
 
-        // Create camera settings uniform buffer with default values
-        let camera_settings = CameraSettingsUniform::default();
-        let camera_settings_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Camera Settings Buffer"),
-            contents: bytemuck::cast_slice(&[camera_settings]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
-
-        let camera_settings_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &camera_settings_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: camera_settings_buffer.as_entire_binding(),
-            }],
-            label: Some("camera_settings_bind_group"),
-        });
-
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: None,
             bind_group_layouts: &[&bind_group_layout, &camera_settings_bind_group_layout],
             immediate_size: 0,
         });
 
-        let swapchain_capabilities = surface.get_capabilities(&adapter);
-        let swapchain_format = swapchain_capabilities.formats[0];
-
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("render_pipeline"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: Some("vs_main"),
-                buffers: &[],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: Some("fs_main"),
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: swapchain_format,
-                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            }),
-            primitive: wgpu::PrimitiveState::default(),
-            multisample: wgpu::MultisampleState::default(),
-            depth_stencil: None,
-            multiview_mask: None,
-            cache: None,
-        });
-
-        let config = wgpu::SurfaceConfiguration {
-            width: size.width.max(1),
-            height: size.height.max(1),
-            format: swapchain_format,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            present_mode: wgpu::PresentMode::Fifo,
-            alpha_mode: swapchain_capabilities.alpha_modes[0],
-            view_formats: vec![],
-            desired_maximum_frame_latency: 2,
-        };
+        let timer = GpuTimer::new(&device, &queue);
+        if timer.is_none() {
+            info!("Timestamp queries are not supported, GPU timing is disabled");
+        }
 
-        surface.configure(&device, &config);
+        let text = TextRenderer::new(&device, &queue);
+        let blur = BlurRenderer::new(&device, &queue);
+        let effects = EffectChain::new(&device);
+        let watermark = WatermarkRenderer::new(&device);
 
-        Self {
-            instance,
+        Ok(Self {
+            timer,
+            text,
+            blur,
+            effects,
+            watermark,
             adapter,
             device,
             queue,
-            surface: RwLock::new(surface),
-            render_pipeline,
-            config: RwLock::new(config),
             sampler,
+            shader: RwLock::new(shader),
+            reloaded_shader: RwLock::new(None),
+            pipeline_layout,
+            render_pipelines: RwLock::new(HashMap::new()),
             bind_group_layout,
+            camera_settings_bind_group_layout,
+        })
+    }
+
+    /// Bind group sampling a camera frame `texture` in the render pipeline
+    pub fn frame_bind_group(&self, texture: &wgpu::Texture) -> wgpu::BindGroup {
+        let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+            label: None,
+        })
+    }
+
+    /// Uniform buffer and bind group placing a camera quad, centered and filling the target
+    pub fn create_camera_settings(&self) -> (wgpu::Buffer, wgpu::BindGroup) {
+        let camera_settings = CameraSettingsUniform::default();
+        let camera_settings_buffer =
+            self.device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Camera Settings Buffer"),
+                    contents: bytemuck::cast_slice(&[camera_settings]),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                });
+
+        let camera_settings_bind_group =
+            self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &self.camera_settings_bind_group_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: camera_settings_buffer.as_entire_binding(),
+                }],
+                label: Some("camera_settings_bind_group"),
+            });
+
+        (camera_settings_buffer, camera_settings_bind_group)
+    }
+
+    /// Creates a texture holding the RGBA `bytes` of a camera frame. The copy is recorded into
+    /// `encoder` rather than queued separately, so it can be timed with the rest of the frame
+    pub fn upload_frame(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        bytes: &[u8],
+        width: u32,
+        height: u32,
+    ) -> wgpu::Texture {
+        let texture_size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            sample_count: 1,
+            mip_level_count: 1,
+            size: texture_size,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            // Overlays may be burned into the frame, which may then be copied out
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        // Buffer to texture copies need rows aligned to 256 bytes, common widths already are
+        let row_bytes = 4 * width;
+        let padded_row_bytes = row_bytes.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let contents = if padded_row_bytes == row_bytes {
+            std::borrow::Cow::Borrowed(bytes)
+        } else {
+            let mut padded = vec![0; (padded_row_bytes * height) as usize];
+            for (padded_row, row) in padded
+                .chunks_exact_mut(padded_row_bytes as usize)
+                .zip(bytes.chunks_exact(row_bytes as usize))
+            {
+                padded_row[..row_bytes as usize].copy_from_slice(row);
+            }
+            std::borrow::Cow::Owned(padded)
+        };
+
+        let staging_buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("frame_upload"),
+                contents: &contents,
+                usage: wgpu::BufferUsages::COPY_SRC,
+            });
+
+        encoder.copy_buffer_to_texture(
+            wgpu::TexelCopyBufferInfo {
+                buffer: &staging_buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_bytes),
+                    rows_per_image: Some(height),
+                },
+            },
+            wgpu::TexelCopyTextureInfo {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            texture_size,
+        );
+
+        texture
+    }
+
+    /// Returns the render pipeline for targets rendering in `format`, building it on first use
+    pub fn render_pipeline(&self, format: wgpu::TextureFormat) -> wgpu::RenderPipeline {
+        if let Some(pipeline) = self.render_pipelines.read().unwrap().get(&format) {
+            return pipeline.clone();
+        }
+
+        let pipeline = self.create_render_pipeline(&self.shader.read().unwrap(), format);
+        self.render_pipelines
+            .write()
+            .unwrap()
+            .entry(format)
+            .or_insert(pipeline)
+            .clone()
+    }
+
+    /// Swaps in a new version of `shader.wgsl` and rebuilds the render pipelines with it.
+    /// Errors are caught rather than panicking, and leave the current shader in place
+    pub async fn reload_shader(&self, source: String) -> Result<(), String> {
+        let scope = self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let shader = self
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("shader.wgsl"),
+                source: wgpu::ShaderSource::Wgsl(source.as_str().into()),
+            });
+        let formats: Vec<wgpu::TextureFormat> = self
+            .render_pipelines
+            .read()
+            .unwrap()
+            .keys()
+            .copied()
+            .collect();
+        let pipelines: HashMap<wgpu::TextureFormat, wgpu::RenderPipeline> = formats
+            .into_iter()
+            .map(|format| (format, self.create_render_pipeline(&shader, format)))
+            .collect();
+        if let Some(error) = scope.pop().await {
+            return Err(error.to_string());
+        }
+
+        *self.shader.write().unwrap() = shader;
+        *self.render_pipelines.write().unwrap() = pipelines;
+        *self.reloaded_shader.write().unwrap() = Some(source);
+        Ok(())
+    }
+
+    fn create_render_pipeline(
+        &self,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        info!("Building render pipeline for {:?}", format);
+        self.device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("render_pipeline"),
+                layout: Some(&self.pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: shader,
+                    entry_point: Some("fs_main"),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                multisample: wgpu::MultisampleState::default(),
+                depth_stencil: None,
+                multiview_mask: None,
+                cache: None,
+            })
+    }
+}
+
+pub struct WgpuState {
+    pub instance: wgpu::Instance,
+    /// Replaced by `recover` when the device is lost
+    gpu: RwLock<Arc<GpuContext>>,
+    /// Raised by the device lost callback, checked by the render loop before each frame
+    pub device_lost: Arc<AtomicBool>,
+    pub render_commands: flume::Sender<RenderCommand>,
+    /// Configuration of every render target, by name
+    pub configs: RwLock<HashMap<String, wgpu::SurfaceConfiguration>>,
+    /// Present modes each surface render target supports, by name
+    present_modes: RwLock<HashMap<String, Vec<wgpu::PresentMode>>>,
+    pub needs_reconfigure: Mutex<bool>,
+    pub rendering_settings: RwLock<RenderingSettings>,
+    /// Whether the performance HUD is drawn over the camera frame
+    pub hud_visible: AtomicBool,
+    pub blur_settings: RwLock<BlurSettings>,
+    /// Decoded mask image when the blur is masked by one
+    pub blur_mask: RwLock<Option<Arc<RgbaImage>>>,
+    /// Text overlays, drawn in order
+    pub overlays: RwLock<Vec<TextOverlay>>,
+    pub watermark: RwLock<WatermarkSettings>,
+    /// Decoded watermark image, kept while the watermark is disabled so it can be toggled
+    pub watermark_image: RwLock<Option<Arc<RgbaImage>>>,
+}
+
+impl WgpuState {
+    /// Returns the state together with the primary render target for `window`,
+    /// which is meant to be handed over to the render loop
+    pub async fn new(
+        window: Window,
+        render_commands: flume::Sender<RenderCommand>,
+    ) -> (Self, RenderTargets) {
+        let size = window.inner_size().unwrap();
+        let instance = wgpu::Instance::default();
+        let surface = instance.create_surface(window).unwrap();
+        let state = Self::with_instance(instance, Some(&surface), render_commands).await;
+
+        let config = state.surface_config(PRIMARY_RENDER_TARGET, &surface, size);
+        surface.configure(&state.gpu().device, &config);
+        let targets = state.primary_target(RenderTargetOutput::Surface(surface), config);
+
+        (state, targets)
+    }
+
+    /// Same as `new`, but the primary render target is an offscreen texture of `size`
+    /// so frames keep flowing through the renderer without any window
+    pub async fn new_headless(
+        render_commands: flume::Sender<RenderCommand>,
+        size: PhysicalSize<u32>,
+    ) -> (Self, RenderTargets) {
+        let state = Self::with_instance(wgpu::Instance::default(), None, render_commands).await;
+
+        let config = state.offscreen_config(size);
+        let texture = state.create_offscreen_texture(size);
+        let targets = state.primary_target(RenderTargetOutput::Offscreen(texture), config);
+
+        (state, targets)
+    }
+
+    async fn with_instance(
+        instance: wgpu::Instance,
+        compatible_surface: Option<&wgpu::Surface<'static>>,
+        render_commands: flume::Sender<RenderCommand>,
+    ) -> Self {
+        let device_lost = Arc::new(AtomicBool::new(false));
+        let gpu = GpuContext::new(&instance, compatible_surface, device_lost.clone())
+            .await
+            .expect("Failed to initialize the renderer");
+
+        Self {
+            instance,
+            gpu: RwLock::new(Arc::new(gpu)),
+            device_lost,
+            render_commands,
+            configs: RwLock::new(HashMap::new()),
+            present_modes: RwLock::new(HashMap::new()),
             needs_reconfigure: Mutex::new(false),
+            rendering_settings: RwLock::new(RenderingSettings::default()),
+            hud_visible: AtomicBool::new(false),
+            blur_settings: RwLock::new(BlurSettings::default()),
+            blur_mask: RwLock::new(None),
+            overlays: RwLock::new(Vec::new()),
+            watermark: RwLock::new(WatermarkSettings::default()),
+            watermark_image: RwLock::new(None),
+        }
+    }
+
+    fn primary_target(
+        &self,
+        output: RenderTargetOutput,
+        config: wgpu::SurfaceConfiguration,
+    ) -> RenderTargets {
+        self.configs
+            .write()
+            .unwrap()
+            .insert(PRIMARY_RENDER_TARGET.to_string(), config);
+        let primary_target = self.create_render_target(output);
+        HashMap::from([(PRIMARY_RENDER_TARGET.to_string(), primary_target)])
+    }
+
+    /// The current adapter and device, hold on to it for the duration of a frame
+    pub fn gpu(&self) -> Arc<GpuContext> {
+        self.gpu.read().unwrap().clone()
+    }
+
+    /// Rebuilds the adapter, device and pipelines, then moves every render target over to
+    /// the new device. Surfaces belong to the instance and outlive the device, so they are
+    /// reconfigured rather than recreated
+    pub async fn recover(&self, targets: &mut RenderTargets) -> Result<(), String> {
+        warn!("Rebuilding the renderer");
+        let compatible_surface = targets.values().find_map(|target| match &target.output {
+            RenderTargetOutput::Surface(surface) => Some(surface),
+            RenderTargetOutput::Offscreen(_) => None,
+        });
+        let gpu =
+            GpuContext::new(&self.instance, compatible_surface, self.device_lost.clone()).await?;
+        let previous = self.gpu();
+        gpu.effects
+            .restore(&gpu.device, previous.effects.sources())
+            .await;
+        let reloaded_shader = previous.reloaded_shader.read().unwrap().clone();
+        if let Some(source) = reloaded_shader {
+            if let Err(e) = gpu.reload_shader(source).await {
+                warn!(
+                    "Reloaded shader no longer compiles, using the built-in one: {}",
+                    e
+                );
+            }
+        }
+        *self.gpu.write().unwrap() = Arc::new(gpu);
+        self.device_lost.store(false, Ordering::SeqCst);
+
+        let old_targets = std::mem::take(targets);
+        for (name, target) in old_targets {
+            let output = match target.output {
+                RenderTargetOutput::Surface(surface) => RenderTargetOutput::Surface(surface),
+                RenderTargetOutput::Offscreen(texture) => {
+                    RenderTargetOutput::Offscreen(self.create_offscreen_texture(PhysicalSize::new(
+                        texture.width(),
+                        texture.height(),
+                    )))
+                }
+            };
+            targets.insert(name, self.create_render_target(output));
+        }
+
+        // Formats and capabilities may differ on the new adapter
+        let mut configs = self.configs.write().unwrap();
+        for (name, target) in targets.iter() {
+            let (Some(config), RenderTargetOutput::Surface(surface)) =
+                (configs.get_mut(name), &target.output)
+            else {
+                continue;
+            };
+            *config = self.surface_config(
+                name,
+                surface,
+                PhysicalSize::new(config.width, config.height),
+            );
+            surface.configure(&self.gpu().device, config);
+        }
+
+        Ok(())
+    }
+
+    /// Points the primary render target at `window` and waits until the render loop presents to it
+    pub async fn switch_surface(self: &Arc<Self>, window: Window) -> Result<(), String> {
+        self.add_window_target(PRIMARY_RENDER_TARGET, window).await
+    }
+
+    /// Drops the primary render target, the render loop keeps consuming frames
+    pub async fn release_surface(&self) -> Result<(), String> {
+        self.remove_target(PRIMARY_RENDER_TARGET).await
+    }
+
+    pub async fn clear_surface(&self) -> Result<(), String> {
+        self.clear_target(PRIMARY_RENDER_TARGET).await
+    }
+
+    /// Creates a surface for `window` and waits until the render loop presents to it under `name`.
+    /// The surface is created on the main thread, as Metal layers can't be created anywhere else
+    pub async fn add_window_target(
+        self: &Arc<Self>,
+        name: &str,
+        window: Window,
+    ) -> Result<(), String> {
+        let size = window.inner_size().unwrap_or(PhysicalSize::new(640, 480));
+
+        let (surface_tx, surface_rx) = flume::bounded(1);
+        let state = self.clone();
+        let main_thread_window = window.clone();
+        window
+            .run_on_main_thread(move || {
+                let surface = state
+                    .instance
+                    .create_surface(main_thread_window)
+                    .map_err(|e| e.to_string());
+                let _ = surface_tx.send(surface);
+            })
+            .map_err(|e| e.to_string())?;
+        let surface = surface_rx.recv_async().await.map_err(|e| e.to_string())??;
+
+        let name = name.to_string();
+        self.send_render_command(|done| RenderCommand::AddTarget {
+            name,
+            output: RenderTargetOutput::Surface(surface),
+            size,
+            done,
+        })
+        .await
+    }
+
+    pub async fn add_offscreen_target(
+        &self,
+        name: &str,
+        size: PhysicalSize<u32>,
+    ) -> Result<(), String> {
+        let texture = self.create_offscreen_texture(size);
+        let name = name.to_string();
+        self.send_render_command(|done| RenderCommand::AddTarget {
+            name,
+            output: RenderTargetOutput::Offscreen(texture),
+            size,
+            done,
+        })
+        .await
+    }
+
+    pub async fn remove_target(&self, name: &str) -> Result<(), String> {
+        let name = name.to_string();
+        self.send_render_command(|done| RenderCommand::RemoveTarget { name, done })
+            .await
+    }
+
+    pub async fn clear_target(&self, name: &str) -> Result<(), String> {
+        let name = name.to_string();
+        self.send_render_command(|done| RenderCommand::Clear { name, done })
+            .await
+    }
+
+    pub fn render_target_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.configs.read().unwrap().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Records a new size for a render target, the render loop applies it before the next frame
+    pub fn resize_target(&self, name: &str, size: PhysicalSize<u32>) {
+        let mut configs = self.configs.write().unwrap();
+        let Some(config) = configs.get_mut(name) else {
+            return;
+        };
+        if config.width == size.width.max(1) && config.height == size.height.max(1) {
+            return;
+        }
+        config.width = size.width.max(1);
+        config.height = size.height.max(1);
+        drop(configs);
+
+        let mut needs_reconfigure = self.needs_reconfigure.lock().unwrap();
+        *needs_reconfigure = true;
+        drop(needs_reconfigure);
+    }
+
+    /// Present modes every surface render target supports. Without any surface, as in hidden
+    /// or headless mode, there is nothing to rule out
+    pub fn supported_present_modes(&self) -> Vec<PresentModeSetting> {
+        let present_modes = self.present_modes.read().unwrap();
+        PresentModeSetting::ALL
+            .into_iter()
+            .filter(|mode| present_modes.values().all(|modes| mode.is_supported(modes)))
+            .collect()
+    }
+
+    /// Rejects a present mode that one of the surfaces would have to fall back from
+    pub fn check_present_mode(&self, present_mode: PresentModeSetting) -> Result<(), String> {
+        let supported = self.supported_present_modes();
+        if !supported.contains(&present_mode) {
+            return Err(format!(
+                "Present mode {present_mode:?} is not supported, available: {supported:?}"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Records new presentation settings, the render loop applies them to every surface
+    /// before the next frame
+    pub fn set_rendering_settings(&self, settings: RenderingSettings) -> Result<(), String> {
+        settings.validate()?;
+        *self.rendering_settings.write().unwrap() = settings;
+
+        let mut needs_reconfigure = self.needs_reconfigure.lock().unwrap();
+        *needs_reconfigure = true;
+        drop(needs_reconfigure);
+
+        Ok(())
+    }
+
+    /// Validates `settings` and loads the mask image they point to, if any
+    pub fn set_blur_settings(&self, settings: BlurSettings) -> Result<(), String> {
+        settings.validate()?;
+        let mask = match &settings.mask {
+            BlurMask::Image { path } => Some(Arc::new(image::load_png(path)?)),
+            _ => None,
+        };
+        *self.blur_mask.write().unwrap() = mask;
+        *self.blur_settings.write().unwrap() = settings;
+        Ok(())
+    }
+
+    /// Validates `settings` and loads the watermark image they point to, if any
+    pub fn set_watermark(&self, settings: WatermarkSettings) -> Result<(), String> {
+        settings.validate()?;
+        let image = match &settings.path {
+            Some(path) => Some(Arc::new(image::load_png(path)?)),
+            None => None,
+        };
+        *self.watermark_image.write().unwrap() = image;
+        *self.watermark.write().unwrap() = settings;
+        Ok(())
+    }
+
+    /// Adds `overlay`, or replaces the overlay with the same id in place
+    pub fn set_overlay(&self, overlay: TextOverlay) -> Result<(), String> {
+        overlay.validate()?;
+        let mut overlays = self.overlays.write().unwrap();
+        match overlays
+            .iter_mut()
+            .find(|existing| existing.id == overlay.id)
+        {
+            Some(existing) => *existing = overlay,
+            None => overlays.push(overlay),
+        }
+        Ok(())
+    }
+
+    pub fn remove_overlay(&self, id: &str) -> Result<(), String> {
+        let mut overlays = self.overlays.write().unwrap();
+        let count = overlays.len();
+        overlays.retain(|overlay| overlay.id != id);
+        if overlays.len() == count {
+            return Err(format!("No overlay with id \"{id}\""));
+        }
+        Ok(())
+    }
+
+    async fn send_render_command(
+        &self,
+        command: impl FnOnce(flume::Sender<()>) -> RenderCommand,
+    ) -> Result<(), String> {
+        let (done_tx, done_rx) = flume::bounded(1);
+        self.render_commands
+            .send(command(done_tx))
+            .map_err(|_| "Render loop is not running".to_string())?;
+        done_rx
+            .recv_async()
+            .await
+            .map_err(|_| "Render loop stopped before completing the request".to_string())
+    }
+
+    /// Runs a render command on behalf of the render loop, which owns `targets`
+    pub fn handle_render_command(&self, targets: &mut RenderTargets, command: RenderCommand) {
+        match command {
+            RenderCommand::AddTarget {
+                name,
+                output,
+                size,
+                done,
+            } => {
+                // Release the old target before the new one starts presenting
+                drop(targets.remove(&name));
+
+                let new_config = match &output {
+                    RenderTargetOutput::Surface(surface) => {
+                        let new_config = self.surface_config(&name, surface, size);
+                        surface.configure(&self.gpu().device, &new_config);
+                        new_config
+                    }
+                    RenderTargetOutput::Offscreen(_) => {
+                        self.present_modes.write().unwrap().remove(&name);
+                        self.offscreen_config(size)
+                    }
+                };
+                // Build the pipeline now if this target negotiated a new format,
+                // rather than stalling its first frame
+                self.gpu().render_pipeline(render_format(&new_config));
+                targets.insert(name.clone(), self.create_render_target(output));
+                self.configs.write().unwrap().insert(name, new_config);
+
+                let _ = done.send(());
+            }
+            RenderCommand::RemoveTarget { name, done } => {
+                drop(targets.remove(&name));
+                self.configs.write().unwrap().remove(&name);
+                self.present_modes.write().unwrap().remove(&name);
+
+                let _ = done.send(());
+            }
+            RenderCommand::Clear { name, done } => {
+                if let Some(RenderTargetOutput::Surface(surface)) =
+                    targets.get(&name).map(|target| &target.output)
+                {
+                    self.clear(surface);
+                }
+                let _ = done.send(());
+            }
+        }
+    }
+
+    /// Applies the sizes recorded by `resize_target` and the current rendering settings
+    /// to the render targets
+    pub fn reconfigure_targets(&self, targets: &mut RenderTargets) {
+        let settings = *self.rendering_settings.read().unwrap();
+        let mut configs = self.configs.write().unwrap();
+        for (name, target) in targets.iter_mut() {
+            let Some(config) = configs.get_mut(name) else {
+                continue;
+            };
+            match &target.output {
+                RenderTargetOutput::Surface(surface) => {
+                    let present_modes = surface.get_capabilities(&self.gpu().adapter).present_modes;
+                    config.present_mode =
+                        negotiate_present_mode(settings.present_mode, &present_modes);
+                    config.desired_maximum_frame_latency = settings.max_frame_latency;
+                    surface.configure(&self.gpu().device, config);
+                }
+                RenderTargetOutput::Offscreen(texture) => {
+                    if texture.width() != config.width || texture.height() != config.height {
+                        target.output =
+                            RenderTargetOutput::Offscreen(self.create_offscreen_texture(
+                                PhysicalSize::new(config.width, config.height),
+                            ));
+                    }
+                }
+            }
+        }
+    }
+
+    fn create_render_target(&self, output: RenderTargetOutput) -> RenderTarget {
+        let (camera_settings_buffer, camera_settings_bind_group) =
+            self.gpu().create_camera_settings();
+
+        RenderTarget {
+            output,
             camera_settings_buffer,
             camera_settings_bind_group,
+            source_camera_settings: Vec::new(),
         }
     }
 
-    pub fn switch_surface(&self, window: Window) {
-        let size = window
-            .inner_size()
-            .unwrap_or(tauri::PhysicalSize::new(640, 480));
+    fn create_offscreen_texture(&self, size: PhysicalSize<u32>) -> wgpu::Texture {
+        self.gpu().device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("offscreen_render_target"),
+            sample_count: 1,
+            mip_level_count: 1,
+            size: wgpu::Extent3d {
+                width: size.width.max(1),
+                height: size.height.max(1),
+                depth_or_array_layers: 1,
+            },
+            dimension: wgpu::TextureDimension::D2,
+            format: OFFSCREEN_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        })
+    }
 
-        // Create new surface for the target window
-        let new_surface = self.instance.create_surface(window).unwrap();
+    fn offscreen_config(&self, size: PhysicalSize<u32>) -> wgpu::SurfaceConfiguration {
+        wgpu::SurfaceConfiguration {
+            width: size.width.max(1),
+            height: size.height.max(1),
+            format: OFFSCREEN_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: vec![],
+            desired_maximum_frame_latency: 2,
+        }
+    }
 
-        // Get capabilities and configure
-        let swapchain_capabilities = new_surface.get_capabilities(&self.adapter);
-        let swapchain_format = swapchain_capabilities.formats[0];
+    /// Configuration for the surface of render target `name`, recording the present modes it supports
+    fn surface_config(
+        &self,
+        name: &str,
+        surface: &wgpu::Surface<'static>,
+        size: PhysicalSize<u32>,
+    ) -> wgpu::SurfaceConfiguration {
+        let swapchain_capabilities = surface.get_capabilities(&self.gpu().adapter);
+        self.present_modes.write().unwrap().insert(
+            name.to_string(),
+            swapchain_capabilities.present_modes.clone(),
+        );
+        let (swapchain_format, view_format) =
+            negotiate_surface_format(&swapchain_capabilities.formats);
+        let settings = *self.rendering_settings.read().unwrap();
 
-        let new_config = wgpu::SurfaceConfiguration {
+        wgpu::SurfaceConfiguration {
             width: size.width.max(1),
             height: size.height.max(1),
             format: swapchain_format,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            present_mode: negotiate_present_mode(
+                settings.present_mode,
+                &swapchain_capabilities.present_modes,
+            ),
+            alpha_mode: negotiate_alpha_mode(&swapchain_capabilities.alpha_modes),
+            view_formats: if view_format == swapchain_format {
+                vec![]
+            } else {
+                vec![view_format]
+            },
+            desired_maximum_frame_latency: settings.max_frame_latency,
+        }
+    }
+
+    fn clear(&self, surface: &wgpu::Surface<'static>) {
+        if let Ok(output) = surface.get_current_texture() {
+            let view = output
+                .texture
+                .create_view(&wgpu::TextureViewDescriptor::default());
+            let gpu = self.gpu();
+            let mut encoder = gpu
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+            {
+                let _rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: None,
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                            store: wgpu::StoreOp::Store,
+                        },
+                        depth_slice: None,
+                    })],
+                    depth_stencil_attachment: None,
+                    timestamp_writes: None,
+                    occlusion_query_set: None,
+                    multiview_mask: None,
+                });
+            }
+            gpu.queue.submit(Some(encoder.finish()));
+            output.present();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wgpu::{CompositeAlphaMode, TextureFormat};
+
+    #[test]
+    fn recovery_waits_longer_after_each_failure() {
+        let start = Instant::now();
+        let mut backoff = RecoveryBackoff::default();
+        assert!(backoff.ready(start));
+
+        assert_eq!(backoff.failed(start), RECOVERY_RETRY_DELAY);
+        assert!(!backoff.ready(start));
+        assert!(backoff.ready(start + RECOVERY_RETRY_DELAY));
+        assert_eq!(backoff.failed(start), RECOVERY_RETRY_DELAY * 2);
+        for _ in 0..20 {
+            backoff.failed(start);
+        }
+        assert_eq!(backoff.failed(start), RECOVERY_MAX_RETRY_DELAY);
+    }
+
+    #[test]
+    fn failure_episode_is_reported_once() {
+        let mut backoff = RecoveryBackoff::default();
+        backoff.failed(Instant::now());
+
+        assert!(backoff.report());
+        assert!(!backoff.report());
+
+        backoff.reset();
+        assert!(backoff.ready(Instant::now()));
+        assert!(backoff.report());
+    }
+
+    #[test]
+    fn supported_present_mode_is_kept() {
+        let present_modes = [wgpu::PresentMode::Fifo, wgpu::PresentMode::Mailbox];
+
+        assert_eq!(
+            negotiate_present_mode(PresentModeSetting::Mailbox, &present_modes),
+            wgpu::PresentMode::Mailbox
+        );
+    }
+
+    #[test]
+    fn unsupported_present_mode_falls_back_to_fifo() {
+        let present_modes = [wgpu::PresentMode::Fifo, wgpu::PresentMode::Mailbox];
+
+        assert_eq!(
+            negotiate_present_mode(PresentModeSetting::Immediate, &present_modes),
+            wgpu::PresentMode::Fifo
+        );
+    }
+
+    #[test]
+    fn auto_present_modes_are_always_accepted() {
+        let present_modes = [wgpu::PresentMode::Fifo];
+
+        assert_eq!(
+            negotiate_present_mode(PresentModeSetting::AutoNoVsync, &present_modes),
+            wgpu::PresentMode::AutoNoVsync
+        );
+        assert!(PresentModeSetting::AutoVsync.is_supported(&[]));
+        assert!(!PresentModeSetting::Mailbox.is_supported(&present_modes));
+    }
+
+    #[test]
+    fn srgb_surface_format_is_preferred() {
+        let formats = [TextureFormat::Bgra8Unorm, TextureFormat::Bgra8UnormSrgb];
+
+        assert_eq!(
+            negotiate_surface_format(&formats),
+            (TextureFormat::Bgra8UnormSrgb, TextureFormat::Bgra8UnormSrgb)
+        );
+    }
+
+    #[test]
+    fn linear_surface_is_rendered_through_an_srgb_view() {
+        let formats = [TextureFormat::Bgra8Unorm, TextureFormat::Rgba16Float];
+
+        assert_eq!(
+            negotiate_surface_format(&formats),
+            (TextureFormat::Bgra8Unorm, TextureFormat::Bgra8UnormSrgb)
+        );
+    }
+
+    #[test]
+    fn format_without_srgb_view_is_used_as_is() {
+        let formats = [TextureFormat::Rgba16Float];
+
+        assert_eq!(
+            negotiate_surface_format(&formats),
+            (TextureFormat::Rgba16Float, TextureFormat::Rgba16Float)
+        );
+    }
+
+    #[test]
+    fn surface_without_formats_gets_the_offscreen_format() {
+        assert_eq!(
+            negotiate_surface_format(&[]),
+            (OFFSCREEN_FORMAT, OFFSCREEN_FORMAT)
+        );
+    }
+
+    #[test]
+    fn pipeline_renders_in_the_view_format() {
+        let mut config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: TextureFormat::Bgra8Unorm,
+            width: 1,
+            height: 1,
             present_mode: wgpu::PresentMode::Fifo,
-            alpha_mode: swapchain_capabilities.alpha_modes[0],
-            view_formats: vec![],
             desired_maximum_frame_latency: 2,
+            alpha_mode: CompositeAlphaMode::Auto,
+            view_formats: vec![TextureFormat::Bgra8UnormSrgb],
         };
+        assert_eq!(render_format(&config), TextureFormat::Bgra8UnormSrgb);
 
-        new_surface.configure(&self.device, &new_config);
+        config.view_formats.clear();
+        assert_eq!(render_format(&config), TextureFormat::Bgra8Unorm);
+    }
 
-        // Update the surface and config
-        let mut surface = self.surface.write().unwrap();
-        *surface = new_surface;
-        drop(surface);
+    #[test]
+    fn alpha_modes_are_tried_in_order_of_preference() {
+        use CompositeAlphaMode::*;
 
-        let mut config = self.config.write().unwrap();
-        *config = new_config;
-        drop(config);
+        assert_eq!(
+            negotiate_alpha_mode(&[Opaque, PostMultiplied, PreMultiplied]),
+            PreMultiplied
+        );
+        assert_eq!(
+            negotiate_alpha_mode(&[Opaque, Inherit, PostMultiplied]),
+            PostMultiplied
+        );
+        assert_eq!(negotiate_alpha_mode(&[Opaque, Inherit]), Inherit);
     }
 
-    pub fn update_camera_settings(&self, settings: &CameraSettingsUniform) {
-        self.queue.write_buffer(
-            &self.camera_settings_buffer,
-            0,
-            bytemuck::cast_slice(&[*settings]),
+    #[test]
+    fn opaque_surface_falls_back_to_its_first_alpha_mode() {
+        assert_eq!(
+            negotiate_alpha_mode(&[CompositeAlphaMode::Opaque]),
+            CompositeAlphaMode::Opaque
         );
+        assert_eq!(negotiate_alpha_mode(&[]), CompositeAlphaMode::Auto);
     }
 }
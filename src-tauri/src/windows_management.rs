@@ -1,38 +1,252 @@
-use crate::webgpu::WgpuState;
+use crate::app::{AppState, DisplayMode};
+use crate::events::AppEvent;
+use crate::settings;
+use crate::webgpu::{WgpuState, PRIMARY_RENDER_TARGET};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::Duration;
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+use tauri::window::Color;
 use tauri::window::WindowBuilder;
-use tauri::{AppHandle, Manager, PhysicalPosition, PhysicalSize, RunEvent, Window, WindowEvent};
+use tauri::{
+    AppHandle, Manager, PhysicalPosition, PhysicalSize, RunEvent, WebviewWindow, Window,
+    WindowEvent,
+};
+use tracing::warn;
+
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(target_os = "macos")]
+mod macos;
+#[cfg(target_os = "windows")]
+mod win32;
 
 const CAMERA_SIZE_FRACTION: f32 = 0.4;
 const CAMERA_MARGIN_PX: i32 = 20;
-const CAMERA_CORNER_RADIUS_PX: f64 = 12.0;
+const MIN_OVERLAY_WIDTH_PX: u32 = 120;
+// Distance from a corner's resting position under which the overlay snaps to it
+const SNAP_DISTANCE_PX: i32 = 48;
+// How long the overlay has to stay still before a drag/resize is considered finished
+const OVERLAY_SETTLE_DELAY: Duration = Duration::from_millis(250);
+const FLOATING_WINDOW_WIDTH: f64 = 480.0;
 
 pub const CAMERA_OVERLAY_WINDOW_LABEL: &str = "camera-overlay";
+pub const CAMERA_FLOATING_WINDOW_LABEL: &str = "camera-floating";
+pub const CAMERA_FULLSCREEN_WINDOW_LABEL: &str = "camera-fullscreen";
+/// Extra preview windows are labelled with this prefix followed by their render target name
+pub const CAMERA_PREVIEW_WINDOW_LABEL_PREFIX: &str = "camera-preview-";
+const HEADLESS_ERROR: &str = "No windows can be opened in headless mode";
+const CAMERA_WINDOW_LABELS: [&str; 3] = [
+    CAMERA_OVERLAY_WINDOW_LABEL,
+    CAMERA_FLOATING_WINDOW_LABEL,
+    CAMERA_FULLSCREEN_WINDOW_LABEL,
+];
+
+/// Window operations that need native APIs. The rest of this module only reaches the
+/// platform through this trait
+pub trait WindowPlatform: Send + Sync {
+    /// Blends the main window's title bar with its content
+    fn style_title_bar(&self, main_window: &WebviewWindow);
+    /// Rounds the corners of the thumbnail overlay
+    fn style_overlay_window(&self, overlay_window: &Window);
+    /// Lets the overlay receive the cursor and be dragged by its background
+    fn set_overlay_interactivity(&self, overlay_window: &Window, interactive: bool);
+    /// Makes the main window see-through, so a surface behind the webview shows
+    fn set_background_transparency(&self, main_window: &WebviewWindow, transparent: bool);
+    /// Layers the wgpu surface of the main window behind the webview
+    fn move_surface_to_back(&self, main_window: &WebviewWindow);
+}
+
+/// Leaves windows untouched. Stands in for platforms without a native implementation, and
+/// for the native one in tests
+#[cfg(any(
+    test,
+    not(any(target_os = "macos", target_os = "linux", target_os = "windows"))
+))]
+pub struct NoopPlatform;
+
+#[cfg(any(
+    test,
+    not(any(target_os = "macos", target_os = "linux", target_os = "windows"))
+))]
+impl WindowPlatform for NoopPlatform {
+    fn style_title_bar(&self, _main_window: &WebviewWindow) {}
+    fn style_overlay_window(&self, _overlay_window: &Window) {}
+    fn set_overlay_interactivity(&self, _overlay_window: &Window, _interactive: bool) {}
+    fn set_background_transparency(&self, _main_window: &WebviewWindow, _transparent: bool) {}
+    fn move_surface_to_back(&self, _main_window: &WebviewWindow) {}
+}
+
+pub fn native_platform() -> Box<dyn WindowPlatform> {
+    #[cfg(target_os = "macos")]
+    let platform = macos::MacOsPlatform;
+    #[cfg(target_os = "linux")]
+    let platform = linux::LinuxPlatform;
+    #[cfg(target_os = "windows")]
+    let platform = win32::WindowsPlatform;
+    #[cfg(not(any(target_os = "macos", target_os = "linux", target_os = "windows")))]
+    let platform = NoopPlatform;
+
+    Box::new(platform)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MonitorInfo {
+    pub index: usize,
+    pub name: Option<String>,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OverlayCorner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+/// Placement of the thumbnail overlay relative to the main window's content area
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct OverlayLayout {
+    /// Corner the overlay is snapped to. When set, `x` and `y` are ignored
+    pub corner: Option<OverlayCorner>,
+    /// Left edge of the overlay as a fraction of the main window width (0 to 1)
+    pub x: f32,
+    /// Top edge of the overlay as a fraction of the main window height (0 to 1)
+    pub y: f32,
+    /// Width of the overlay as a fraction of the main window width (0 to 1)
+    pub width: f32,
+}
+
+impl Default for OverlayLayout {
+    fn default() -> Self {
+        Self {
+            corner: Some(OverlayCorner::TopRight),
+            x: 0.0,
+            y: 0.0,
+            width: CAMERA_SIZE_FRACTION,
+        }
+    }
+}
+
+fn corner_position(
+    corner: OverlayCorner,
+    main_inner_pos: PhysicalPosition<i32>,
+    main_inner_size: PhysicalSize<u32>,
+    overlay_size: PhysicalSize<u32>,
+) -> PhysicalPosition<i32> {
+    let left = main_inner_pos.x + CAMERA_MARGIN_PX;
+    let right = main_inner_pos.x + main_inner_size.width as i32
+        - overlay_size.width as i32
+        - CAMERA_MARGIN_PX;
+    let top = main_inner_pos.y + CAMERA_MARGIN_PX;
+    let bottom = main_inner_pos.y + main_inner_size.height as i32
+        - overlay_size.height as i32
+        - CAMERA_MARGIN_PX;
+
+    match corner {
+        OverlayCorner::TopLeft => PhysicalPosition::new(left, top),
+        OverlayCorner::TopRight => PhysicalPosition::new(right, top),
+        OverlayCorner::BottomLeft => PhysicalPosition::new(left, bottom),
+        OverlayCorner::BottomRight => PhysicalPosition::new(right, bottom),
+    }
+}
 
 pub fn calculate_overlay_geometry(
-    main_outer_pos: PhysicalPosition<i32>,
+    main_inner_pos: PhysicalPosition<i32>,
     main_inner_size: PhysicalSize<u32>,
     camera_aspect: f32,
+    layout: &OverlayLayout,
 ) -> (PhysicalPosition<i32>, PhysicalSize<u32>) {
-    let overlay_width = (main_inner_size.width as f32 * CAMERA_SIZE_FRACTION) as u32;
+    let max_width = main_inner_size
+        .width
+        .saturating_sub(2 * CAMERA_MARGIN_PX as u32)
+        .max(1);
+    let overlay_width = ((main_inner_size.width as f32 * layout.width) as u32)
+        .clamp(MIN_OVERLAY_WIDTH_PX.min(max_width), max_width);
     let overlay_height = (overlay_width as f32 / camera_aspect) as u32;
+    let overlay_size = PhysicalSize::new(overlay_width.max(1), overlay_height.max(1));
 
-    let overlay_x =
-        main_outer_pos.x + main_inner_size.width as i32 - overlay_width as i32 - CAMERA_MARGIN_PX;
-    let overlay_y = main_outer_pos.y + CAMERA_MARGIN_PX;
+    let overlay_pos = match layout.corner {
+        Some(corner) => corner_position(corner, main_inner_pos, main_inner_size, overlay_size),
+        None => PhysicalPosition::new(
+            main_inner_pos.x + (main_inner_size.width as f32 * layout.x) as i32,
+            main_inner_pos.y + (main_inner_size.height as f32 * layout.y) as i32,
+        ),
+    };
 
-    (
-        PhysicalPosition::new(overlay_x, overlay_y),
-        PhysicalSize::new(overlay_width.max(1), overlay_height.max(1)),
-    )
+    (overlay_pos, overlay_size)
+}
+
+/// Inverse of `calculate_overlay_geometry`: derives a layout from where the user left the overlay.
+/// With `snap` set, the overlay is attached to the nearest corner if it is close enough to it.
+pub fn overlay_layout_from_geometry(
+    main_inner_pos: PhysicalPosition<i32>,
+    main_inner_size: PhysicalSize<u32>,
+    overlay_pos: PhysicalPosition<i32>,
+    overlay_size: PhysicalSize<u32>,
+    snap: bool,
+) -> OverlayLayout {
+    let main_width = main_inner_size.width.max(1) as f32;
+    let main_height = main_inner_size.height.max(1) as f32;
+
+    let corner = if snap {
+        [
+            OverlayCorner::TopLeft,
+            OverlayCorner::TopRight,
+            OverlayCorner::BottomLeft,
+            OverlayCorner::BottomRight,
+        ]
+        .into_iter()
+        .map(|corner| {
+            let target = corner_position(corner, main_inner_pos, main_inner_size, overlay_size);
+            let distance = (target.x - overlay_pos.x)
+                .abs()
+                .max((target.y - overlay_pos.y).abs());
+            (corner, distance)
+        })
+        .filter(|(_, distance)| *distance <= SNAP_DISTANCE_PX)
+        .min_by_key(|(_, distance)| *distance)
+        .map(|(corner, _)| corner)
+    } else {
+        None
+    };
+
+    // Keep the overlay fully inside the main window
+    let max_x = (main_inner_size.width as i32 - overlay_size.width as i32).max(0);
+    let max_y = (main_inner_size.height as i32 - overlay_size.height as i32).max(0);
+    let relative_x = (overlay_pos.x - main_inner_pos.x).clamp(0, max_x);
+    let relative_y = (overlay_pos.y - main_inner_pos.y).clamp(0, max_y);
+
+    OverlayLayout {
+        corner,
+        x: relative_x as f32 / main_width,
+        y: relative_y as f32 / main_height,
+        width: (overlay_size.width as f32 / main_width).clamp(0.0, 1.0),
+    }
+}
+
+pub fn save_overlay_layout(app_handle: &AppHandle, layout: &OverlayLayout) {
+    let layout = *layout;
+    settings::persist(app_handle, |settings| settings.overlay_layout = layout);
 }
 
 pub fn create_overlay_window(app: &tauri::AppHandle, main_window: &Window) -> Window {
     let main_webview_window = app.get_webview_window("main").unwrap();
-    let main_outer_pos = main_webview_window.outer_position().unwrap();
+    let main_inner_pos = main_webview_window.inner_position().unwrap();
     let main_inner_size = main_webview_window.inner_size().unwrap();
-    let (overlay_pos, overlay_size) =
-        calculate_overlay_geometry(main_outer_pos, main_inner_size, 16.0 / 9.0);
+    let app_state = app.state::<Arc<AppState>>();
+    let layout = *app_state.overlay_layout.lock().unwrap();
+    let interactive = app_state.overlay_interactive.load(Ordering::SeqCst);
+    let (overlay_pos, overlay_size) = calculate_overlay_geometry(
+        main_inner_pos,
+        main_inner_size,
+        app_state.camera_aspect(),
+        &layout,
+    );
 
     // create_child_window
     let overlay_window = WindowBuilder::new(app, CAMERA_OVERLAY_WINDOW_LABEL)
@@ -44,17 +258,17 @@ pub fn create_overlay_window(app: &tauri::AppHandle, main_window: &Window) -> Wi
         .always_on_top(true)
         .visible(true)
         .skip_taskbar(true)
-        .resizable(false)
+        .resizable(interactive)
         .shadow(false)
         .parent(main_window)
         .expect("Failed to set parent window")
         .build()
         .expect("Failed to create overlay window");
 
-    #[cfg(target_os = "macos")]
-    {
-        style_child_window(&overlay_window);
-    }
+    app_state
+        .window_platform
+        .style_overlay_window(&overlay_window);
+    set_overlay_interactivity(&overlay_window, interactive);
 
     overlay_window.show().unwrap();
 
@@ -65,24 +279,265 @@ pub fn create_overlay_window(app: &tauri::AppHandle, main_window: &Window) -> Wi
     overlay_window
 }
 
-#[cfg(target_os = "macos")]
-fn style_child_window(window: &Window) {
-    use objc2_app_kit::NSView;
+pub fn create_floating_window(app: &AppHandle) -> Window {
+    let camera_aspect = app.state::<Arc<AppState>>().camera_aspect() as f64;
 
-    let _ = window.set_ignore_cursor_events(true);
+    let floating_window = WindowBuilder::new(app, CAMERA_FLOATING_WINDOW_LABEL)
+        .title("Camera")
+        .inner_size(FLOATING_WINDOW_WIDTH, FLOATING_WINDOW_WIDTH / camera_aspect)
+        .always_on_top(true)
+        .visible(true)
+        .resizable(true)
+        .build()
+        .expect("Failed to create floating window");
 
-    if let Ok(ns_view_ptr) = window.ns_view() {
-        unsafe {
-            let ns_view: &NSView = &*(ns_view_ptr as *const NSView);
-            ns_view.setWantsLayer(true);
+    floating_window.show().unwrap();
 
-            if let Some(layer) = ns_view.layer() {
-                layer.setCornerRadius(CAMERA_CORNER_RADIUS_PX);
-                layer.setMasksToBounds(true);
-                layer.setBorderWidth(0.0);
-            }
+    floating_window
+}
+
+pub fn create_preview_window(app: &AppHandle, name: &str) -> Result<Window, String> {
+    let camera_aspect = app.state::<Arc<AppState>>().camera_aspect() as f64;
+
+    let preview_window =
+        WindowBuilder::new(app, format!("{CAMERA_PREVIEW_WINDOW_LABEL_PREFIX}{name}"))
+            .title(name)
+            .inner_size(FLOATING_WINDOW_WIDTH, FLOATING_WINDOW_WIDTH / camera_aspect)
+            .visible(true)
+            .resizable(true)
+            .build()
+            .map_err(|e| e.to_string())?;
+
+    preview_window.show().map_err(|e| e.to_string())?;
+
+    Ok(preview_window)
+}
+
+pub fn create_fullscreen_window(app: &AppHandle, monitor: usize) -> Result<Window, String> {
+    let monitors = app.available_monitors().map_err(|e| e.to_string())?;
+    let target_monitor = monitors
+        .get(monitor)
+        .ok_or_else(|| format!("Monitor {monitor} not found"))?;
+
+    let fullscreen_window = match app.get_window(CAMERA_FULLSCREEN_WINDOW_LABEL) {
+        Some(fullscreen_window) => {
+            let _ = fullscreen_window.set_fullscreen(false);
+            fullscreen_window
+        }
+        None => WindowBuilder::new(app, CAMERA_FULLSCREEN_WINDOW_LABEL)
+            .title("")
+            .decorations(false)
+            .visible(false)
+            .build()
+            .map_err(|e| e.to_string())?,
+    };
+
+    // Fullscreen uses the monitor the window is on, so move it there first
+    let _ = fullscreen_window.set_position(*target_monitor.position());
+    let _ = fullscreen_window.set_size(*target_monitor.size());
+    let _ = fullscreen_window.set_fullscreen(true);
+    fullscreen_window.show().map_err(|e| e.to_string())?;
+
+    Ok(fullscreen_window)
+}
+
+pub fn list_monitors(app_handle: &AppHandle) -> Result<Vec<MonitorInfo>, String> {
+    let monitors = app_handle.available_monitors().map_err(|e| e.to_string())?;
+
+    Ok(monitors
+        .iter()
+        .enumerate()
+        .map(|(index, monitor)| MonitorInfo {
+            index,
+            name: monitor.name().cloned(),
+            width: monitor.size().width,
+            height: monitor.size().height,
+        })
+        .collect())
+}
+
+/// Destroys the camera windows other than `keep`. They are closed from here once nothing
+/// renders to them, so this skips the `CloseRequested` handlers meant for the user closing them
+fn close_camera_windows(app_handle: &AppHandle, keep: Option<&str>) {
+    for label in CAMERA_WINDOW_LABELS {
+        if Some(label) == keep {
+            continue;
         }
+        if let Some(window) = app_handle.get_window(label) {
+            let _ = window.destroy();
+        }
+    }
+}
+
+/// Runs `f` on the main thread and waits for its result. AppKit calls have to happen there,
+/// while mode switches run as async commands
+pub async fn run_on_main_thread<R: Send + 'static>(
+    app_handle: &AppHandle,
+    f: impl FnOnce(&AppHandle) -> R + Send + 'static,
+) -> Result<R, String> {
+    let (result_tx, result_rx) = flume::bounded(1);
+    let main_thread_handle = app_handle.clone();
+    app_handle
+        .run_on_main_thread(move || {
+            let _ = result_tx.send(f(&main_thread_handle));
+        })
+        .map_err(|e| e.to_string())?;
+
+    result_rx.recv_async().await.map_err(|e| e.to_string())
+}
+
+fn camera_window_label(mode: DisplayMode) -> Option<&'static str> {
+    match mode {
+        DisplayMode::Hidden | DisplayMode::Background => None,
+        DisplayMode::Thumbnail => Some(CAMERA_OVERLAY_WINDOW_LABEL),
+        DisplayMode::Floating => Some(CAMERA_FLOATING_WINDOW_LABEL),
+        DisplayMode::Fullscreen { .. } => Some(CAMERA_FULLSCREEN_WINDOW_LABEL),
+    }
+}
+
+/// Whether switching between the two modes keeps the camera window and its surface,
+/// as when moving between monitors in fullscreen
+fn reuses_camera_window(from: DisplayMode, to: DisplayMode) -> bool {
+    camera_window_label(to).is_some() && camera_window_label(to) == camera_window_label(from)
+}
+
+/// Creates the window the camera renders into in `mode`
+fn prepare_camera_window(app_handle: &AppHandle, mode: DisplayMode) -> Result<Window, String> {
+    let main_window = app_handle
+        .get_window("main")
+        .ok_or("Main window not found")?;
+
+    match mode {
+        DisplayMode::Hidden | DisplayMode::Background => Ok(main_window),
+        DisplayMode::Thumbnail => Ok(create_overlay_window(app_handle, &main_window)),
+        DisplayMode::Floating => Ok(create_floating_window(app_handle)),
+        DisplayMode::Fullscreen { monitor } => create_fullscreen_window(app_handle, monitor),
+    }
+}
+
+/// Switches the camera to `mode` like [`apply_display_mode`] and saves it as the mode to start in
+pub async fn switch_display_mode(
+    app_handle: &AppHandle,
+    mode: DisplayMode,
+) -> Result<DisplayMode, String> {
+    let mode = apply_display_mode(app_handle, mode).await?;
+    settings::persist(app_handle, |settings| settings.display_mode = mode);
+    Ok(mode)
+}
+
+/// Switches the camera to `mode` and returns once the render loop presents to the new window.
+/// The new window is ready before the old one goes away, so a failure leaves the current mode untouched.
+/// The mode isn't saved, for modes restored from the settings or given on the command line
+pub async fn apply_display_mode(
+    app_handle: &AppHandle,
+    mode: DisplayMode,
+) -> Result<DisplayMode, String> {
+    let app_state = app_handle.state::<Arc<AppState>>().inner().clone();
+    let wgpu_state = app_handle.state::<Arc<WgpuState>>().inner().clone();
+
+    // One switch at a time
+    let _switching = app_state.mode_switch.lock().await;
+    if app_state.headless {
+        return Err(HEADLESS_ERROR.to_string());
+    }
+
+    let current_mode = app_state.display_mode();
+    if current_mode == mode {
+        return Ok(mode);
     }
+
+    // Don't leave a stale frame behind the webview when leaving background mode
+    if current_mode == DisplayMode::Background {
+        wgpu_state.clear_surface().await?;
+    }
+
+    let target_window = run_on_main_thread(app_handle, move |app_handle| {
+        prepare_camera_window(app_handle, mode)
+    })
+    .await??;
+
+    if mode == DisplayMode::Hidden {
+        wgpu_state.release_surface().await?;
+    } else if !reuses_camera_window(current_mode, mode) {
+        wgpu_state.switch_surface(target_window.clone()).await?;
+    }
+
+    // The render loop has let go of the old surface, so its window can be closed now
+    run_on_main_thread(app_handle, move |app_handle| {
+        // In background mode, the main window needs to be transparent
+        // In every other mode, the main window needs to be opaque
+        set_main_window_background_transparency(app_handle, mode == DisplayMode::Background);
+        close_camera_windows(app_handle, camera_window_label(mode));
+
+        // This covers a weird edge case on MacOs where the Metal layer is not moved to the back
+        // after the very first thumbnail-> background mode switch
+        if mode == DisplayMode::Background {
+            move_surface_to_back(app_handle);
+        }
+
+        // Restore focus to main window after the camera windows changed
+        if matches!(mode, DisplayMode::Fullscreen { .. }) {
+            let _ = target_window.set_focus();
+        } else if let Some(main_window) = app_handle.get_window("main") {
+            let _ = main_window.set_focus();
+        }
+    })
+    .await?;
+
+    *app_state.display_mode.lock().unwrap() = mode;
+    AppEvent::CameraModeChanged(mode).emit(app_handle);
+
+    Ok(mode)
+}
+
+/// Opens an extra window that shows the camera alongside the display mode's own window
+pub async fn add_preview_window(app_handle: &AppHandle, name: &str) -> Result<(), String> {
+    if name == PRIMARY_RENDER_TARGET {
+        return Err(format!("\"{name}\" is reserved for the display mode"));
+    }
+    if app_handle.state::<Arc<AppState>>().headless {
+        return Err(HEADLESS_ERROR.to_string());
+    }
+
+    let wgpu_state = app_handle.state::<Arc<WgpuState>>().inner().clone();
+    let window_name = name.to_string();
+    let preview_window = run_on_main_thread(app_handle, move |app_handle| {
+        create_preview_window(app_handle, &window_name)
+    })
+    .await??;
+
+    wgpu_state.add_window_target(name, preview_window).await
+}
+
+/// Removes a render target, closing its preview window if it has one
+pub async fn remove_render_target(app_handle: &AppHandle, name: &str) -> Result<(), String> {
+    if name == PRIMARY_RENDER_TARGET {
+        return Err(format!(
+            "\"{name}\" follows the display mode, use set_camera_mode instead"
+        ));
+    }
+
+    let wgpu_state = app_handle.state::<Arc<WgpuState>>().inner().clone();
+    wgpu_state.remove_target(name).await?;
+
+    if let Some(preview_window) =
+        app_handle.get_window(&format!("{CAMERA_PREVIEW_WINDOW_LABEL_PREFIX}{name}"))
+    {
+        let _ = preview_window.destroy();
+    }
+
+    Ok(())
+}
+
+/// In interactive mode the overlay accepts the cursor so it can be dragged by its background
+/// and resized by its edges. Otherwise clicks go through to the main window.
+pub fn set_overlay_interactivity(overlay_window: &Window, interactive: bool) {
+    let _ = overlay_window.set_resizable(interactive);
+
+    overlay_window
+        .state::<Arc<AppState>>()
+        .window_platform
+        .set_overlay_interactivity(overlay_window, interactive);
 }
 
 pub fn sync_camera_window_with_main(app_handle: &AppHandle, event: RunEvent) {
@@ -93,12 +548,7 @@ pub fn sync_camera_window_with_main(app_handle: &AppHandle, event: RunEvent) {
             ..
         } if label == "main" => {
             // When main window moves, update overlay position
-            if let Some(main_window) = app_handle.get_webview_window("main") {
-                if let Some(overlay_window) = app_handle.get_window(CAMERA_OVERLAY_WINDOW_LABEL) {
-                    let wgpu_state = app_handle.state::<Arc<WgpuState>>();
-                    adjust_overlay_geometry(&main_window, &overlay_window, &wgpu_state);
-                }
-            }
+            refit_overlay(app_handle);
         }
         RunEvent::WindowEvent {
             label,
@@ -106,21 +556,69 @@ pub fn sync_camera_window_with_main(app_handle: &AppHandle, event: RunEvent) {
             ..
         } if label == "main" => {
             // When main window resizes, update overlay size and position
-            if let Some(main_window) = app_handle.get_webview_window("main") {
-                if let Some(overlay_window) = app_handle.get_window(CAMERA_OVERLAY_WINDOW_LABEL) {
-                    let wgpu_state = app_handle.state::<Arc<WgpuState>>();
-                    adjust_overlay_geometry(&main_window, &overlay_window, &wgpu_state);
-                }
-            }
+            refit_overlay(app_handle);
+        }
+        RunEvent::WindowEvent {
+            label,
+            event: WindowEvent::Moved(_) | WindowEvent::Resized(_),
+            ..
+        } if label == CAMERA_OVERLAY_WINDOW_LABEL => {
+            // The user is dragging or resizing the overlay
+            track_overlay_interaction(app_handle);
+        }
+        RunEvent::WindowEvent {
+            label,
+            event: WindowEvent::Resized(size),
+            ..
+        } if label == CAMERA_FLOATING_WINDOW_LABEL || label == CAMERA_FULLSCREEN_WINDOW_LABEL => {
+            let wgpu_state = app_handle.state::<Arc<WgpuState>>();
+            wgpu_state.resize_target(PRIMARY_RENDER_TARGET, size);
+        }
+        RunEvent::WindowEvent {
+            label,
+            event: WindowEvent::CloseRequested { api, .. },
+            ..
+        } if label == CAMERA_FLOATING_WINDOW_LABEL || label == CAMERA_FULLSCREEN_WINDOW_LABEL => {
+            // The surface still points at this window, so switch away before it goes
+            api.prevent_close();
+            let app_handle = app_handle.clone();
+            tauri::async_runtime::spawn(async move {
+                let _ = switch_display_mode(&app_handle, DisplayMode::Thumbnail).await;
+            });
+        }
+        RunEvent::WindowEvent {
+            label,
+            event: WindowEvent::Resized(size),
+            ..
+        } if label.starts_with(CAMERA_PREVIEW_WINDOW_LABEL_PREFIX) => {
+            let wgpu_state = app_handle.state::<Arc<WgpuState>>();
+            wgpu_state.resize_target(&label[CAMERA_PREVIEW_WINDOW_LABEL_PREFIX.len()..], size);
+        }
+        RunEvent::WindowEvent {
+            label,
+            event: WindowEvent::CloseRequested { api, .. },
+            ..
+        } if label.starts_with(CAMERA_PREVIEW_WINDOW_LABEL_PREFIX) => {
+            // Release the render target before its window goes
+            api.prevent_close();
+            let app_handle = app_handle.clone();
+            tauri::async_runtime::spawn(async move {
+                let name = &label[CAMERA_PREVIEW_WINDOW_LABEL_PREFIX.len()..];
+                let _ = remove_render_target(&app_handle, name).await;
+            });
         }
         RunEvent::WindowEvent {
             label,
             event: WindowEvent::CloseRequested { .. },
             ..
         } if label == "main" => {
-            // When main window closes, close overlay too
-            if let Some(overlay_window) = app_handle.get_window(CAMERA_OVERLAY_WINDOW_LABEL) {
-                let _ = overlay_window.close();
+            // When main window closes, close the camera and preview windows too. The app exits
+            // with them, so there is no mode to switch to or render target to release first
+            close_camera_windows(app_handle, None);
+            for (label, window) in app_handle.windows() {
+                if label.starts_with(CAMERA_PREVIEW_WINDOW_LABEL_PREFIX) {
+                    let _ = window.destroy();
+                }
             }
         }
 
@@ -128,38 +626,146 @@ pub fn sync_camera_window_with_main(app_handle: &AppHandle, event: RunEvent) {
     }
 }
 
+/// Re-applies the overlay layout, e.g. after the main window or the camera resolution changed
+pub fn refit_overlay(app_handle: &AppHandle) {
+    if let Some(main_window) = app_handle.get_webview_window("main") {
+        if let Some(overlay_window) = app_handle.get_window(CAMERA_OVERLAY_WINDOW_LABEL) {
+            let wgpu_state = app_handle.state::<Arc<WgpuState>>();
+            adjust_overlay_geometry(&main_window, &overlay_window, &wgpu_state);
+        }
+    }
+}
+
+fn track_overlay_interaction(app_handle: &AppHandle) {
+    let app_state = app_handle.state::<Arc<AppState>>();
+    if !app_state.overlay_interactive.load(Ordering::SeqCst) {
+        return;
+    }
+
+    let (Some(main_window), Some(overlay_window)) = (
+        app_handle.get_webview_window("main"),
+        app_handle.get_window(CAMERA_OVERLAY_WINDOW_LABEL),
+    ) else {
+        return;
+    };
+
+    if let (Ok(main_inner_pos), Ok(main_inner_size), Ok(overlay_pos), Ok(overlay_size)) = (
+        main_window.inner_position(),
+        main_window.inner_size(),
+        overlay_window.outer_position(),
+        overlay_window.inner_size(),
+    ) {
+        let layout = overlay_layout_from_geometry(
+            main_inner_pos,
+            main_inner_size,
+            overlay_pos,
+            overlay_size,
+            false,
+        );
+        *app_state.overlay_layout.lock().unwrap() = layout;
+
+        let wgpu_state = app_handle.state::<Arc<WgpuState>>();
+        wgpu_state.resize_target(PRIMARY_RENDER_TARGET, overlay_size);
+    }
+
+    // Moved/Resized fire continuously while the user drags, so snapping and saving
+    // only happen once the overlay has been still for a moment
+    let interactions = app_state
+        .overlay_interactions
+        .get_or_init(|| spawn_overlay_settler(app_handle.clone()));
+    let _ = interactions.send(());
+}
+
+/// Starts the worker that settles the overlay after each drag or resize
+fn spawn_overlay_settler(app_handle: AppHandle) -> flume::Sender<()> {
+    let (interaction_tx, interaction_rx) = flume::unbounded();
+    let spawned = std::thread::Builder::new()
+        .name("overlay-settler".to_string())
+        .spawn(move || {
+            settle_when_still(interaction_rx, OVERLAY_SETTLE_DELAY, || {
+                let main_thread_handle = app_handle.clone();
+                let _ = app_handle.run_on_main_thread(move || settle_overlay(&main_thread_handle));
+            });
+        });
+    if let Err(e) = spawned {
+        warn!("Could not start the overlay settler: {}", e);
+    }
+    interaction_tx
+}
+
+/// Calls `settle` once no interaction arrived for `delay` after a burst of them,
+/// until every sender is gone
+fn settle_when_still(interactions: flume::Receiver<()>, delay: Duration, mut settle: impl FnMut()) {
+    while interactions.recv().is_ok() {
+        // Every interaction restarts the delay
+        while interactions.recv_timeout(delay).is_ok() {}
+        settle();
+    }
+}
+
+fn settle_overlay(app_handle: &AppHandle) {
+    let (Some(main_window), Some(overlay_window)) = (
+        app_handle.get_webview_window("main"),
+        app_handle.get_window(CAMERA_OVERLAY_WINDOW_LABEL),
+    ) else {
+        return;
+    };
+
+    if let (Ok(main_inner_pos), Ok(main_inner_size), Ok(overlay_pos), Ok(overlay_size)) = (
+        main_window.inner_position(),
+        main_window.inner_size(),
+        overlay_window.outer_position(),
+        overlay_window.inner_size(),
+    ) {
+        let layout = overlay_layout_from_geometry(
+            main_inner_pos,
+            main_inner_size,
+            overlay_pos,
+            overlay_size,
+            true,
+        );
+
+        let app_state = app_handle.state::<Arc<AppState>>();
+        *app_state.overlay_layout.lock().unwrap() = layout;
+        save_overlay_layout(app_handle, &layout);
+
+        // Snap into place and restore the aspect ratio the free resize may have broken
+        let wgpu_state = app_handle.state::<Arc<WgpuState>>();
+        adjust_overlay_geometry(&main_window, &overlay_window, &wgpu_state);
+    }
+}
+
 pub fn adjust_overlay_geometry(
     main_window: &tauri::WebviewWindow,
     overlay_window: &Window,
     wgpu_state: &Arc<WgpuState>,
 ) {
-    if let (Ok(main_outer_pos), Ok(main_inner_size)) =
-        (main_window.outer_position(), main_window.inner_size())
+    if let (Ok(main_inner_pos), Ok(main_inner_size)) =
+        (main_window.inner_position(), main_window.inner_size())
     {
-        if let Ok(overlay_size) = overlay_window.inner_size() {
-            // Preserve the current camera aspect ratio
-            let camera_aspect = overlay_size.width as f32 / overlay_size.height.max(1) as f32;
-            let (overlay_pos, new_overlay_size) =
-                calculate_overlay_geometry(main_outer_pos, main_inner_size, camera_aspect);
+        if let (Ok(overlay_pos), Ok(overlay_size)) =
+            (overlay_window.outer_position(), overlay_window.inner_size())
+        {
+            let app_state = main_window.state::<Arc<AppState>>();
+            let layout = *app_state.overlay_layout.lock().unwrap();
+            let (new_overlay_pos, new_overlay_size) = calculate_overlay_geometry(
+                main_inner_pos,
+                main_inner_size,
+                app_state.camera_aspect(),
+                &layout,
+            );
 
-            // Always update position (this is only called on window move/resize events, not every frame)
-            let _ = overlay_window.set_position(overlay_pos);
+            // Skip redundant moves: in interactive mode every move is reported back to us
+            if overlay_pos != new_overlay_pos {
+                let _ = overlay_window.set_position(new_overlay_pos);
+            }
 
             // Only resize if size changed
-            if overlay_size.width != new_overlay_size.width
-                || overlay_size.height != new_overlay_size.height
-            {
+            if overlay_size != new_overlay_size {
                 let _ = overlay_window.set_size(new_overlay_size);
 
                 // Update wgpu surface config
-                let mut config = wgpu_state.config.write().unwrap();
-                config.width = new_overlay_size.width.max(1);
-                config.height = new_overlay_size.height.max(1);
-                drop(config);
-
-                let mut needs_reconfigure = wgpu_state.needs_reconfigure.lock().unwrap();
-                *needs_reconfigure = true;
-                drop(needs_reconfigure);
+                wgpu_state.resize_target(PRIMARY_RENDER_TARGET, new_overlay_size);
             }
         }
     }
@@ -170,103 +776,171 @@ pub fn set_main_window_background_transparency(
     new_mode_is_background: bool,
 ) {
     if let Some(main_window) = app_handle.get_webview_window("main") {
-        #[cfg(target_os = "macos")]
-        {
-            use objc2_app_kit::{NSColor, NSView};
-            use objc2_foundation::MainThreadMarker;
-
-            // Set the main window's background transparency
-            if let Ok(ns_view_ptr) = main_window.ns_view() {
-                unsafe {
-                    let ns_view: &NSView = &*(ns_view_ptr as *const NSView);
-
-                    if let Some(window) = ns_view.window() {
-                        if new_mode_is_background {
-                            // Make main window transparent
-                            window.setOpaque(false);
-                            if let Some(_mtm) = MainThreadMarker::new() {
-                                window.setBackgroundColor(Some(&NSColor::clearColor()));
-                            }
-                        } else {
-                            // Thumbnail mode: make main window opaque
-                            window.setOpaque(true);
-                            if let Some(_mtm) = MainThreadMarker::new() {
-                                window.setBackgroundColor(Some(&NSColor::windowBackgroundColor()));
-                            }
-                        }
-                    }
-                }
-            }
-        }
+        app_handle
+            .state::<Arc<AppState>>()
+            .window_platform
+            .set_background_transparency(&main_window, new_mode_is_background);
     }
 }
 
-#[cfg(target_os = "macos")]
-pub fn move_metal_layer_to_back(app_handle: &AppHandle) {
-    use objc2_app_kit::NSView;
-    use objc2_foundation::NSArray;
-
-    if let Some(main_webview_window) = app_handle.get_webview_window("main") {
-        if let Ok(ns_view_ptr) = main_webview_window.ns_view() {
-            unsafe {
-                let ns_view: &NSView = &*(ns_view_ptr as *const NSView);
-                if let Some(layer) = ns_view.layer() {
-                    if let Some(sublayers) = layer.sublayers() {
-                        // Find the Metal layer (CAMetalLayer) and move it to the back
-                        let count = sublayers.len();
-                        if count >= 2 {
-                            let metal_layer = NSArray::objectAtIndex(&sublayers, count - 1);
-                            metal_layer.setZPosition(-1.0);
-                        }
-                    }
-                }
-            }
-        }
+pub fn move_surface_to_back(app_handle: &AppHandle) {
+    if let Some(main_window) = app_handle.get_webview_window("main") {
+        app_handle
+            .state::<Arc<AppState>>()
+            .window_platform
+            .move_surface_to_back(&main_window);
     }
 }
 
-pub fn clear_main_window_surface(wgpu_state: &WgpuState) {
-    let surface = wgpu_state.surface.read().unwrap();
-    if let Ok(output) = surface.get_current_texture() {
-        let view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
-        let mut encoder = wgpu_state
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
-        {
-            let _rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: None,
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                        store: wgpu::StoreOp::Store,
-                    },
-                    depth_slice: None,
-                })],
-                depth_stencil_attachment: None,
-                timestamp_writes: None,
-                occlusion_query_set: None,
-                multiview_mask: None,
-            });
-        }
-        wgpu_state.queue.submit(Some(encoder.finish()));
-        output.present();
-    }
+/// The webview paints its own background, which has to go for the camera to show through
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+fn set_webview_background_transparency(main_window: &WebviewWindow, transparent: bool) {
+    let _ = main_window.set_background_color(transparent.then_some(Color(0, 0, 0, 0)));
 }
 
-#[cfg(target_os = "macos")]
-pub fn style_title_bar(main_webview_window: &tauri::WebviewWindow) {
-    use objc2_app_kit::NSView;
-
-    if let Ok(ns_view_ptr) = main_webview_window.ns_view() {
-        unsafe {
-            let ns_view: &NSView = &*(ns_view_ptr as *const NSView);
-            if let Some(window) = ns_view.window() {
-                window.setTitlebarAppearsTransparent(true);
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAIN_POS: PhysicalPosition<i32> = PhysicalPosition::new(100, 50);
+    const MAIN_SIZE: PhysicalSize<u32> = PhysicalSize::new(1000, 800);
+
+    #[test]
+    fn corners_keep_a_margin_from_the_edges() {
+        let overlay_size = PhysicalSize::new(200, 100);
+        let position = |corner| corner_position(corner, MAIN_POS, MAIN_SIZE, overlay_size);
+
+        assert_eq!(
+            position(OverlayCorner::TopLeft),
+            PhysicalPosition::new(120, 70)
+        );
+        assert_eq!(
+            position(OverlayCorner::TopRight),
+            PhysicalPosition::new(880, 70)
+        );
+        assert_eq!(
+            position(OverlayCorner::BottomLeft),
+            PhysicalPosition::new(120, 730)
+        );
+        assert_eq!(
+            position(OverlayCorner::BottomRight),
+            PhysicalPosition::new(880, 730)
+        );
+    }
+
+    #[test]
+    fn overlay_keeps_the_camera_aspect() {
+        let (_, size) =
+            calculate_overlay_geometry(MAIN_POS, MAIN_SIZE, 2.0, &OverlayLayout::default());
+
+        assert_eq!(size, PhysicalSize::new(400, 200));
+    }
+
+    #[test]
+    fn overlay_width_is_clamped() {
+        let layout = |width| OverlayLayout {
+            width,
+            ..OverlayLayout::default()
+        };
+
+        let (_, tiny) = calculate_overlay_geometry(MAIN_POS, MAIN_SIZE, 1.0, &layout(0.01));
+        let (_, huge) = calculate_overlay_geometry(MAIN_POS, MAIN_SIZE, 1.0, &layout(1.0));
+
+        assert_eq!(tiny.width, MIN_OVERLAY_WIDTH_PX);
+        assert_eq!(huge.width, MAIN_SIZE.width - 2 * CAMERA_MARGIN_PX as u32);
+    }
+
+    #[test]
+    fn free_position_is_a_fraction_of_the_window() {
+        let layout = OverlayLayout {
+            corner: None,
+            x: 0.5,
+            y: 0.25,
+            width: 0.2,
+        };
+
+        let (position, _) = calculate_overlay_geometry(MAIN_POS, MAIN_SIZE, 1.0, &layout);
+
+        assert_eq!(position, PhysicalPosition::new(600, 250));
+    }
+
+    #[test]
+    fn layout_round_trips_through_geometry() {
+        let layout = OverlayLayout {
+            corner: None,
+            x: 0.25,
+            y: 0.5,
+            width: 0.3,
+        };
+        let (position, size) = calculate_overlay_geometry(MAIN_POS, MAIN_SIZE, 1.5, &layout);
+
+        let derived = overlay_layout_from_geometry(MAIN_POS, MAIN_SIZE, position, size, false);
+
+        assert_eq!(derived, layout);
+    }
+
+    #[test]
+    fn overlay_snaps_to_a_nearby_corner() {
+        let size = PhysicalSize::new(200, 100);
+        let near_bottom_left = PhysicalPosition::new(140, 700);
+
+        let snapped =
+            overlay_layout_from_geometry(MAIN_POS, MAIN_SIZE, near_bottom_left, size, true);
+        let free = overlay_layout_from_geometry(MAIN_POS, MAIN_SIZE, near_bottom_left, size, false);
+
+        assert_eq!(snapped.corner, Some(OverlayCorner::BottomLeft));
+        assert_eq!(free.corner, None);
+    }
+
+    #[test]
+    fn overlay_far_from_corners_stays_free() {
+        let size = PhysicalSize::new(200, 100);
+        let center = PhysicalPosition::new(500, 400);
+
+        let layout = overlay_layout_from_geometry(MAIN_POS, MAIN_SIZE, center, size, true);
+
+        assert_eq!(layout.corner, None);
+    }
+
+    #[test]
+    fn dragged_out_overlay_is_kept_inside_the_window() {
+        let size = PhysicalSize::new(200, 100);
+        let outside = PhysicalPosition::new(-500, 2000);
+
+        let layout = overlay_layout_from_geometry(MAIN_POS, MAIN_SIZE, outside, size, false);
+
+        assert_eq!(layout.x, 0.0);
+        assert_eq!(layout.y, 700.0 / 800.0);
+    }
+
+    #[test]
+    fn only_fullscreen_keeps_its_window_across_switches() {
+        let fullscreen = |monitor| DisplayMode::Fullscreen { monitor };
+
+        assert!(reuses_camera_window(fullscreen(0), fullscreen(1)));
+        assert!(!reuses_camera_window(
+            DisplayMode::Thumbnail,
+            DisplayMode::Floating
+        ));
+        assert!(!reuses_camera_window(DisplayMode::Floating, fullscreen(0)));
+        // The main window isn't a camera window of its own
+        assert!(!reuses_camera_window(
+            DisplayMode::Hidden,
+            DisplayMode::Background
+        ));
+    }
+
+    #[test]
+    fn a_burst_of_interactions_settles_once() {
+        let (interaction_tx, interaction_rx) = flume::unbounded();
+        for _ in 0..100 {
+            interaction_tx.send(()).unwrap();
         }
+        drop(interaction_tx);
+
+        let mut settled = 0;
+        settle_when_still(interaction_rx, Duration::from_millis(10), || settled += 1);
+
+        assert_eq!(settled, 1);
     }
 }
@@ -0,0 +1,35 @@
+use super::{set_webview_background_transparency, WindowPlatform};
+use gtk::prelude::*;
+use tauri::{WebviewWindow, Window};
+
+pub struct LinuxPlatform;
+
+impl WindowPlatform for LinuxPlatform {
+    fn style_title_bar(&self, _main_window: &WebviewWindow) {
+        // Client side decorations are drawn by GTK, nothing to blend
+    }
+
+    fn style_overlay_window(&self, _overlay_window: &Window) {
+        // Rounded corners would need a shaped window, the overlay stays rectangular
+    }
+
+    fn set_overlay_interactivity(&self, overlay_window: &Window, interactive: bool) {
+        let _ = overlay_window.set_ignore_cursor_events(!interactive);
+    }
+
+    fn set_background_transparency(&self, main_window: &WebviewWindow, transparent: bool) {
+        if let Ok(gtk_window) = main_window.gtk_window() {
+            // Compositors only blend windows with an RGBA visual, which GTK fixes when the
+            // window is realized. Tauri picks it for `transparent: true` in tauri.conf.json,
+            // all that is left here is keeping the GTK theme from painting the background
+            gtk_window.set_app_paintable(transparent);
+            gtk_window.queue_draw();
+        }
+
+        set_webview_background_transparency(main_window, transparent);
+    }
+
+    fn move_surface_to_back(&self, _main_window: &WebviewWindow) {
+        // The surface presents to the toplevel window and WebKitGTK is composited on top of it
+    }
+}
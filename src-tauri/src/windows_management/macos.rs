@@ -0,0 +1,90 @@
+use super::WindowPlatform;
+use objc2_app_kit::{NSColor, NSView, NSWindow};
+use objc2_foundation::{MainThreadMarker, NSArray};
+use tauri::{WebviewWindow, Window};
+
+const CAMERA_CORNER_RADIUS_PX: f64 = 12.0;
+
+pub struct MacOsPlatform;
+
+impl WindowPlatform for MacOsPlatform {
+    fn style_title_bar(&self, main_window: &WebviewWindow) {
+        if let Ok(ns_view_ptr) = main_window.ns_view() {
+            unsafe {
+                let ns_view: &NSView = &*(ns_view_ptr as *const NSView);
+                if let Some(window) = ns_view.window() {
+                    window.setTitlebarAppearsTransparent(true);
+                }
+            }
+        }
+    }
+
+    fn style_overlay_window(&self, overlay_window: &Window) {
+        if let Ok(ns_view_ptr) = overlay_window.ns_view() {
+            unsafe {
+                let ns_view: &NSView = &*(ns_view_ptr as *const NSView);
+                ns_view.setWantsLayer(true);
+
+                if let Some(layer) = ns_view.layer() {
+                    layer.setCornerRadius(CAMERA_CORNER_RADIUS_PX);
+                    layer.setMasksToBounds(true);
+                    layer.setBorderWidth(0.0);
+                }
+            }
+        }
+    }
+
+    fn set_overlay_interactivity(&self, overlay_window: &Window, interactive: bool) {
+        let _ = overlay_window.set_ignore_cursor_events(!interactive);
+
+        if let Ok(ns_window_ptr) = overlay_window.ns_window() {
+            unsafe {
+                let ns_window: &NSWindow = &*(ns_window_ptr as *const NSWindow);
+                ns_window.setMovableByWindowBackground(interactive);
+            }
+        }
+    }
+
+    fn set_background_transparency(&self, main_window: &WebviewWindow, transparent: bool) {
+        // Set the main window's background transparency
+        if let Ok(ns_view_ptr) = main_window.ns_view() {
+            unsafe {
+                let ns_view: &NSView = &*(ns_view_ptr as *const NSView);
+
+                if let Some(window) = ns_view.window() {
+                    if transparent {
+                        // Make main window transparent
+                        window.setOpaque(false);
+                        if let Some(_mtm) = MainThreadMarker::new() {
+                            window.setBackgroundColor(Some(&NSColor::clearColor()));
+                        }
+                    } else {
+                        // Thumbnail mode: make main window opaque
+                        window.setOpaque(true);
+                        if let Some(_mtm) = MainThreadMarker::new() {
+                            window.setBackgroundColor(Some(&NSColor::windowBackgroundColor()));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn move_surface_to_back(&self, main_window: &WebviewWindow) {
+        if let Ok(ns_view_ptr) = main_window.ns_view() {
+            unsafe {
+                let ns_view: &NSView = &*(ns_view_ptr as *const NSView);
+                if let Some(layer) = ns_view.layer() {
+                    if let Some(sublayers) = layer.sublayers() {
+                        // Find the Metal layer (CAMetalLayer) and move it to the back
+                        let count = sublayers.len();
+                        if count >= 2 {
+                            let metal_layer = NSArray::objectAtIndex(&sublayers, count - 1);
+                            metal_layer.setZPosition(-1.0);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
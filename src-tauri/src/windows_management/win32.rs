@@ -0,0 +1,47 @@
+use super::{set_webview_background_transparency, WindowPlatform};
+use tauri::{WebviewWindow, Window};
+use windows::Win32::Graphics::Dwm::DwmExtendFrameIntoClientArea;
+use windows::Win32::UI::Controls::MARGINS;
+
+pub struct WindowsPlatform;
+
+impl WindowPlatform for WindowsPlatform {
+    fn style_title_bar(&self, _main_window: &WebviewWindow) {
+        // The title bar is drawn by DWM
+    }
+
+    fn style_overlay_window(&self, _overlay_window: &Window) {
+        // Windows 11 rounds borderless windows on its own
+    }
+
+    fn set_overlay_interactivity(&self, overlay_window: &Window, interactive: bool) {
+        let _ = overlay_window.set_ignore_cursor_events(!interactive);
+    }
+
+    fn set_background_transparency(&self, main_window: &WebviewWindow, transparent: bool) {
+        if let Ok(hwnd) = main_window.hwnd() {
+            // Extending the frame over the whole client area lets DWM blend it with the desktop,
+            // zero margins give the opaque client area back
+            let margins = if transparent {
+                MARGINS {
+                    cxLeftWidth: -1,
+                    cxRightWidth: -1,
+                    cyTopHeight: -1,
+                    cyBottomHeight: -1,
+                }
+            } else {
+                MARGINS::default()
+            };
+            unsafe {
+                let _ = DwmExtendFrameIntoClientArea(hwnd, &margins);
+            }
+        }
+
+        set_webview_background_transparency(main_window, transparent);
+    }
+
+    fn move_surface_to_back(&self, _main_window: &WebviewWindow) {
+        // WebView2 lives in a child window, which is always drawn above the parent's client area
+        // where the surface presents
+    }
+}
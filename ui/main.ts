@@ -1,48 +1,179 @@
 import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
 
-let isBackgroundMode = false;
+type DisplayMode =
+  | "hidden"
+  | "thumbnail"
+  | "background"
+  | "floating"
+  | { fullscreen: { monitor: number } };
 
-async function toggleCameraMode() {
-  console.log("Button clicked!");
+type FrameSize = { width: number; height: number };
+type CameraStarted = {
+  name: string;
+  resolution: FrameSize;
+  frameRate: number;
+  pixelFormat: string;
+};
+type ErrorMessage = { message: string };
+type Latency = { p50Ms: number; p95Ms: number; p99Ms: number };
+type PipelineStats = { fps: number; droppedFrames: number; present: Latency };
 
+let hasCameraError = false;
+let isEditingThumbnail = false;
+let isHudVisible = false;
+
+// The <select> works with plain strings, fullscreen is encoded as "fullscreen:<monitor>"
+function modeToOption(mode: DisplayMode): string {
+  return typeof mode === "string" ? mode : `fullscreen:${mode.fullscreen.monitor}`;
+}
+
+function optionToMode(option: string): DisplayMode {
+  if (option.startsWith("fullscreen:")) {
+    return { fullscreen: { monitor: Number(option.split(":")[1]) } };
+  }
+  return option as DisplayMode;
+}
+
+function applyMode(mode: DisplayMode) {
+  const select = document.getElementById("camera-mode") as HTMLSelectElement | null;
+  if (select) {
+    select.value = modeToOption(mode);
+  }
+  document.body.classList.toggle("transparent", mode === "background");
+}
+
+async function setCameraMode(option: string) {
   try {
-    isBackgroundMode = await invoke<boolean>("toggle_camera_mode");
+    const mode = await invoke<DisplayMode>("set_camera_mode", {
+      mode: optionToMode(option),
+    });
+    applyMode(mode);
+  } catch (error) {
+    console.error("Failed to set camera mode:", error);
+    initializeMode();
+  }
+}
+
+async function toggleThumbnailEditing() {
+  try {
+    isEditingThumbnail = await invoke<boolean>("set_overlay_interactive", {
+      interactive: !isEditingThumbnail,
+    });
 
-    const button = document.getElementById("toggle-mode");
+    const button = document.getElementById("edit-thumbnail");
     if (button) {
-      button.textContent = isBackgroundMode
-        ? "Switch to Thumbnail Mode"
-        : "Switch to Background Mode";
+      button.textContent = isEditingThumbnail ? "Done Editing" : "Edit Thumbnail";
     }
-    document.body.classList.toggle("transparent", isBackgroundMode);
   } catch (error) {
-    console.error("Failed to toggle camera mode:", error);
+    console.error("Failed to toggle thumbnail editing:", error);
   }
 }
 
-async function initializeMode() {
+async function toggleHud() {
   try {
-    isBackgroundMode = await invoke<boolean>("get_camera_mode");
+    isHudVisible = await invoke<boolean>("set_hud_visible", { visible: !isHudVisible });
 
-    const button = document.getElementById("toggle-mode");
+    const button = document.getElementById("toggle-hud");
     if (button) {
-      button.textContent = isBackgroundMode
-        ? "Switch to Thumbnail Mode"
-        : "Switch to Background Mode";
+      button.textContent = isHudVisible ? "Hide HUD" : "Show HUD";
     }
+  } catch (error) {
+    console.error("Failed to toggle HUD:", error);
+  }
+}
 
-    document.body.classList.toggle("transparent", isBackgroundMode);
+async function populateMonitors() {
+  const select = document.getElementById("camera-mode") as HTMLSelectElement | null;
+  if (!select) {
+    return;
+  }
+
+  try {
+    const monitors = await invoke<{ index: number; name: string | null }[]>("get_monitors");
+    for (const monitor of monitors) {
+      const option = document.createElement("option");
+      option.value = `fullscreen:${monitor.index}`;
+      option.textContent = `Fullscreen (${monitor.name ?? `Monitor ${monitor.index + 1}`})`;
+      select.appendChild(option);
+    }
+  } catch (error) {
+    console.error("Failed to list monitors:", error);
+  }
+}
+
+async function initializeMode() {
+  try {
+    applyMode(await invoke<DisplayMode>("get_camera_mode"));
   } catch (error) {
     console.error("Failed to get camera mode:", error);
   }
 }
 
-window.addEventListener("DOMContentLoaded", () => {
+function setCameraStatus(status: string) {
+  const element = document.getElementById("camera-status");
+  if (element) {
+    element.textContent = status;
+  }
+}
+
+function showRendererError(message: string | null) {
+  const element = document.getElementById("renderer-error");
+  if (element) {
+    element.textContent = message ?? "";
+    element.hidden = message === null;
+  }
+}
+
+window.addEventListener("DOMContentLoaded", async () => {
+  // The backend pushes state changes, so every window stays in sync
+  listen<DisplayMode>("camera-mode-changed", (event) => applyMode(event.payload));
+  listen<CameraStarted>("camera-started", ({ payload }) => {
+    hasCameraError = false;
+    setCameraStatus(
+      `${payload.name} · ${payload.resolution.width}×${payload.resolution.height} ` +
+        `${payload.pixelFormat} @ ${payload.frameRate} fps`,
+    );
+  });
+  listen<FrameSize>("resolution-changed", ({ payload }) =>
+    console.info(`Camera resolution changed to ${payload.width}×${payload.height}`),
+  );
+  listen("camera-stopped", () => {
+    // Keep the error visible, the camera always stops after one
+    if (!hasCameraError) {
+      setCameraStatus("Camera stopped");
+    }
+  });
+  listen<ErrorMessage>("camera-error", ({ payload }) => {
+    hasCameraError = true;
+    setCameraStatus(`Camera error: ${payload.message}`);
+  });
+  listen<PipelineStats>("stats-updated", ({ payload }) => {
+    const element = document.getElementById("pipeline-stats");
+    if (element) {
+      element.textContent =
+        `${payload.fps.toFixed(1)} fps · ${payload.present.p95Ms.toFixed(1)} ms p95 latency` +
+        ` · ${payload.droppedFrames} dropped`;
+    }
+  });
+  listen<ErrorMessage>("renderer-error", ({ payload }) => showRendererError(payload.message));
+  listen("renderer-recovered", () => showRendererError(null));
+
+  await populateMonitors();
   initializeMode();
 
-  const toggleButton = document.getElementById("toggle-mode");
-  if (toggleButton) {
-    toggleButton.addEventListener("click", toggleCameraMode);
-    console.log("Toggle button event listener attached");
+  const modeSelect = document.getElementById("camera-mode") as HTMLSelectElement | null;
+  if (modeSelect) {
+    modeSelect.addEventListener("change", () => setCameraMode(modeSelect.value));
+  }
+
+  const editButton = document.getElementById("edit-thumbnail");
+  if (editButton) {
+    editButton.addEventListener("click", toggleThumbnailEditing);
+  }
+
+  const hudButton = document.getElementById("toggle-hud");
+  if (hudButton) {
+    hudButton.addEventListener("click", toggleHud);
   }
 });